@@ -9,6 +9,10 @@ function onInit()
     frevi_stat_msg("Test Plugin loaded")
     frevi_reg_com("!testplug", test_counter)
     frevi_reg_com("!testplug_time", show_time)
+    frevi_reg_com("!testplug_words", count_words)
+    -- keys can run lua funcs or anything !map takes
+    frevi_map("normal", "<A-t>", show_time)
+    frevi_map("normal", "<A-c>", "<Cmd>testplug")
 end
 
 function test_counter()
@@ -20,3 +24,19 @@ function show_time()
     local time = os.date("%Y-%m-%d %H:%M:%S")
     frevi_stat_msg("Current time: " .. time)
 end
+
+-- commands get args table and range ({first, last, lines}, nil if no range typed)
+-- try !%testplug_words
+function count_words(args, range)
+    if range == nil then
+        frevi_stat_msg("Usage: !{range}testplug_words")
+        return
+    end
+    local words = 0
+    for _, line in ipairs(range.lines) do
+        for _ in line:gmatch("%S+") do
+            words = words + 1
+        end
+    end
+    frevi_stat_msg("Lines " .. range.first .. "-" .. range.last .. ": " .. words .. " words")
+end
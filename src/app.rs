@@ -3,58 +3,120 @@ use std::{
     char,
     collections::HashMap,
     fmt::format,
+    path::Path,
     sync::mpsc::{self, Receiver, Sender},
+    time::Instant,
 };
 
-use crossterm::event::{Event, KeyCode, KeyEventKind, MouseEvent, MouseEventKind};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEventKind};
 use crossterm::terminal::{ScrollDown, ScrollUp};
-use mlua::Function;
-
-use crate::{commands, logger::LogLevel, plugin::PluginMessage};
+use mlua::{Function, Value};
+
+use crate::disk::DiskWatcher;
+use crate::grapheme;
+use crate::history::History;
+use crate::keymap::{Keymaps, MapTarget};
+use crate::macros::DotCmd;
+use crate::marks::{GlobalMark, JumpList};
+use crate::motions::{self, FindKind};
+use crate::prompt::Prompt;
+use crate::range::{self, LineRange};
+use crate::search::Search;
+use crate::substitute::SubstConfirm;
+use crate::syntax::{self, Syntax};
+use crate::theme::{self, Theme};
+use crate::visual::{BlockInsert, VisualKind};
+use crate::window::Windows;
+use crate::{
+    commands,
+    logger::LogLevel,
+    plugin::{MapRhs, PluginMessage},
+};
 use crate::{
     dotfiles::FreviConfig,
     plugin::{LuaLoader, PlugLoaders, PluginLoader},
 };
 use crate::{edits::Edit, plugin::PlugCom};
-use crate::{logger::Logger, tabs::Tab};
+use crate::{logger::Logger, registers::Registers, tabs::Tab};
 
 type RustHandler = fn(&mut App, Vec<String>);
+type RangeHandler = fn(&mut App, Option<LineRange>, Vec<String>); // for commands taking a line range
 #[derive(Debug, Clone)]
 pub enum CommandHandler {
     Rust(RustHandler),
+    RustRange(RangeHandler),
     Lua(Function, usize), // usize for plugin id.
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Command,
+    Visual(VisualKind),
+    Search { backward: bool }, // typing pattern after / or ?
+    Confirm,                   // !s with c flag asks about every match
+    Prompt,                    // waiting for answer to a question in status line
+}
+
+// How a status message is colored, by theme groups status, status_warning and status_error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatusLevel {
+    Info,
+    Warning,
+    Error,
+}
+
 #[derive(Debug)]
 pub struct App {
-    pub insert_mode: bool,
+    pub mode: Mode,
+    pub normal_keys: String, // pending keys typed in normal mode, like "2d" or "g"
+    pub last_find: Option<(char, FindKind)>, // last f/t/F/T for ; and ,
+    pub registers: Registers,
+    pub visual_anchor: (usize, usize), // other end of visual selection, cursor is the moving one
+    pub block_insert: Option<BlockInsert>,
+    pub search: Search,
+    pub subst_confirm: Option<SubstConfirm>,
+    pub prompts: Vec<Prompt>,                    // first one is asked now
+    pub global_marks: HashMap<char, GlobalMark>, // A-Z
+    pub jumps: JumpList,
+    pub recording: Option<char>, // register of macro being recorded with q
+    pub macro_keys: Vec<KeyEvent>,
+    pub last_macro: Option<char>, // for @@
+    pub replay_depth: usize,      // >0 while keys come from macro or `.`
+    pub dot: Option<DotCmd>,
+    pub dot_pending: Option<DotCmd>, // change still in insert mode
+    pub keymaps: Keymaps,
     pub running: bool,
-    pub left_area_open: bool,
-    pub left_area_used: bool,
-    pub left_area: Tab,
+    pub windows: Windows,
     pub cursor_pos_xy: (u16, u16),
     pub command_buf: String,
     pub commands: HashMap<String, CommandHandler>,
     pub aliases: HashMap<String, Vec<String>>,
-    pub command_hist: Vec<Vec<String>>,
+    pub command_hist: History,
     pub status_message: bool,
+    pub status_level: StatusLevel,
     pub tabs: Vec<Tab>,
     pub cur_tab: usize,
     pub version: String,
-    pub hist_ctr: usize,
     pub plugin_subsys: PlugLoaders,
     pub plugin_tx: Sender<PluginMessage>,
     pub plugin_rx: Receiver<PluginMessage>,
     pub config: FreviConfig,
     pub logger: Logger,
+    pub last_key: Instant, // swaps are written when keys stop coming
+    pub last_autosave: Instant,
+    pub disk_watcher: DiskWatcher,
+    pub syntaxes: Vec<Syntax>,
+    pub theme: Theme,
 }
 
 impl App {
-    // Creates App object with default plugin system (lualoader)
-    pub fn new() -> App {
-        let ins_mod: bool = false;
+    // App with given options; history, syntaxes and themes come from cfg_dir
+    // (~/.frevi) if there is one
+    fn with_config(mut frevi_cfg: FreviConfig, cfg_dir: Option<&Path>, mut logger: Logger) -> App {
+        let start_mode: Mode = Mode::Normal;
         let run: bool = true;
-        let left_open: bool = false;
         let cpos_xy: (u16, u16) = (0, 0);
         let com_buf: String = String::new();
         let coms: HashMap<String, CommandHandler> = HashMap::new();
@@ -72,25 +134,31 @@ impl App {
         let full_vers = format!("{} {}", vers, build_type);
 
         let com_aliases: HashMap<String, Vec<String>> = HashMap::new();
-        let com_hist: Vec<Vec<String>> = Vec::new();
-        let hist_c: usize = 0;
-
-        let mut frevi_cfg = FreviConfig::new();
-        let frevi_cfg_load_res = frevi_cfg.read_cfg();
-        let log_path = frevi_cfg.cfg_path.clone().join("latest.log");
-        let str_log_path = log_path.to_string_lossy().to_string();
 
-        let mut logger: Logger = match Logger::new(str_log_path.clone()) {
-            Ok(l) => l,
+        let com_hist = History::new(cfg_dir.map(|d| d.join("history")));
+        let syntax_dir = cfg_dir.map(|d| d.join("syntax"));
+        let syntaxes = syntax::load_syntaxes(syntax_dir.as_deref(), &mut frevi_cfg.errors);
+        let theme_dir = cfg_dir.map(|d| d.join("themes"));
+        let truecolor = frevi_cfg.truecolor.unwrap_or_else(theme::detect_truecolor);
+        let theme = match theme::load_theme(&frevi_cfg.theme, theme_dir.as_deref(), truecolor) {
+            Ok(t) => t,
             Err(e) => {
-                panic!("{}", &e);
+                frevi_cfg.errors.push(format!("config.toml: theme: {}", e));
+                frevi_cfg.theme = "default".to_string();
+                theme::load_theme("default", None, truecolor).expect("built-in theme is broken")
             }
         };
 
+        logger.level = frevi_cfg.log_level;
+        for e in frevi_cfg.errors.iter() {
+            let _ = logger.log_msg(LogLevel::Warning, e.clone());
+        }
+        for n in frevi_cfg.notices.iter() {
+            let _ = logger.log_msg(LogLevel::Info, n.clone());
+        }
+
         let (tx, rx) = mpsc::channel();
 
-        let left_area: Tab = Tab::new(None);
-        let left_ar_us: bool = false;
         let mut lua_load = LuaLoader::new();
         if let Err(errors) = lua_load.load_plugs_lines(frevi_cfg.autoplugs.clone(), tx.clone()) {
             for item in errors {
@@ -100,235 +168,265 @@ impl App {
         let pl_sys: PlugLoaders = PlugLoaders::LuaL(lua_load);
 
         let mut app = App {
-            insert_mode: ins_mod,
+            mode: start_mode,
+            normal_keys: String::new(),
+            last_find: None,
+            visual_anchor: (0, 0),
+            block_insert: None,
+            search: Search::new(),
+            subst_confirm: None,
+            prompts: Vec::new(),
+            global_marks: HashMap::new(),
+            jumps: JumpList::new(),
+            recording: None,
+            macro_keys: Vec::new(),
+            last_macro: None,
+            replay_depth: 0,
+            dot: None,
+            dot_pending: None,
+            keymaps: Keymaps::new(),
+            registers: Registers::new(
+                frevi_cfg.clipboard_copy.clone(),
+                frevi_cfg.clipboard_paste.clone(),
+            ),
             running: run,
-            left_area_open: left_open,
-            left_area: left_area,
+            windows: Windows::new(tabsv[0].id),
             cursor_pos_xy: cpos_xy,
             command_buf: com_buf,
             commands: coms,
             status_message: stat_msg,
+            status_level: StatusLevel::Info,
             tabs: tabsv,
             cur_tab: curtab,
             version: full_vers,
             command_hist: com_hist,
             aliases: com_aliases,
-            hist_ctr: hist_c,
-            left_area_used: left_ar_us,
             plugin_subsys: pl_sys,
             plugin_tx: tx,
             plugin_rx: rx,
             config: frevi_cfg,
             logger: logger,
+            last_key: Instant::now(),
+            last_autosave: Instant::now(),
+            disk_watcher: DiskWatcher::new(),
+            syntaxes,
+            theme,
         };
         app.gen_hashmap_com();
-        app.throw_status_message(str_log_path);
+        app.apply_config_maps();
+        app
+    }
+
+    // Creates App object with default plugin system (lualoader)
+    pub fn new() -> App {
+        let mut frevi_cfg = FreviConfig::new();
+        let frevi_cfg_load_res = frevi_cfg.read_cfg();
+        let log_path = frevi_cfg.cfg_path.clone().join("latest.log");
+        let str_log_path = log_path.to_string_lossy().to_string();
+        let mut logger: Logger = match Logger::new(str_log_path.clone()) {
+            Ok(l) => l,
+            Err(e) => {
+                panic!("{}", &e);
+            }
+        };
+        logger.level = frevi_cfg.log_level;
+        if let Err(e) = &frevi_cfg_load_res {
+            let _ = logger.log_msg(LogLevel::Error, e.clone());
+        }
+        let cfg_dir = frevi_cfg.cfg_path.clone();
+        let mut app = App::with_config(
+            frevi_cfg,
+            Some(cfg_dir.as_path()).filter(|_| frevi_cfg_load_res.is_ok()),
+            logger,
+        );
+        match app.config.errors.is_empty() {
+            true => app.throw_status_message(str_log_path),
+            false => app.throw_status_message(format!(
+                "Some config.toml entries were skipped, see {}",
+                str_log_path
+            )),
+        }
         app
     }
 
+    // Default options, nothing read from or written to ~/.frevi, log goes nowhere
+    #[cfg(test)]
+    pub fn new_test() -> App {
+        App::with_config(FreviConfig::new(), None, Logger::none())
+    }
+
     pub fn handle_input(&mut self, event: Event) {
         match event {
             Event::Mouse(m_ev) => match m_ev.kind {
                 MouseEventKind::ScrollUp => {
-                    let cur_tab_opt = self.tabs.get_mut(self.cur_tab);
-                    let cur_tab = match cur_tab_opt {
-                        Some(tab) => tab,
-                        None => {
-                            self.throw_status_message(format!(
-                                "E: Can't get tab with indice {}",
-                                self.cur_tab
-                            ));
-                            return;
-                        }
-                    };
-                    cur_tab.scroll_offset = cur_tab.scroll_offset.saturating_sub(1);
+                    self.tab_update_scroll_delta(-1);
                     return;
                 }
                 MouseEventKind::ScrollDown => {
-                    let cur_tab_opt = self.tabs.get_mut(self.cur_tab);
-                    let cur_tab = match cur_tab_opt {
-                        Some(tab) => tab,
-                        None => {
-                            self.throw_status_message(format!(
-                                "E: Can't get tab with indice {}",
-                                self.cur_tab
-                            ));
-                            return;
-                        }
-                    };
-                    cur_tab.scroll_offset = cur_tab.scroll_offset.saturating_add(1);
+                    self.tab_update_scroll_delta(1);
                     return;
                 }
+                MouseEventKind::ScrollLeft => self.scroll_sideways(-1),
+                MouseEventKind::ScrollRight => self.scroll_sideways(1),
                 _ => {}
             },
-            Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
-                KeyCode::Insert | KeyCode::Esc => {
-                    self.insert_mode = !self.insert_mode;
+            Event::FocusGained => self.check_disk_all(),
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                self.last_key = Instant::now();
+                if self.recording.is_some() && self.replay_depth == 0 {
+                    self.macro_keys.push(key);
                 }
-                KeyCode::Char(ch) => {
-                    if !self.insert_mode {
-                        if self.status_message {
-                            self.command_buf = "".to_string();
-                            self.command_buf.push(ch);
-                            self.cursor_pos_xy.0 = 1;
-                            self.status_message = !self.status_message;
-                            return;
-                        }
-                        if self.cursor_pos_xy.0 as usize >= self.command_buf.len() {
-                            match self.command_buf.len() {
-                                0 => {
-                                    self.cursor_pos_xy.0 = 0;
-                                }
-                                val => {
-                                    self.cursor_pos_xy.0 = (val) as u16;
-                                }
-                            }
-                        }
-                        //self.command_buf.insert(self.cursor_pos_xy.0 as usize, ch);
-                        let char_pos = self.cursor_pos_xy.0 as usize;
-                        let byte_idx = self
-                            .command_buf
-                            .char_indices()
-                            .nth(char_pos)
-                            .map(|(idx, _)| idx)
-                            .unwrap_or(self.command_buf.len()); // insert at end if position is out of bounds
-
-                        self.command_buf.insert(byte_idx, ch);
-
-                        self.cursor_pos_xy.0 += 1;
-                        return;
-                    }
-
-                    self.insert_ch_tab_buf(ch);
+                match self.keymaps.noremap {
+                    0 => self.map_key(key),
+                    _ => self.dispatch_key(key),
                 }
-                KeyCode::Tab => {
-                    if self.insert_mode {
-                        self.insert_ch_tab_buf('\t');
-                    }
+                // insert session of a change is over, `.` can repeat it now
+                if self.mode != Mode::Insert
+                    && let Some(dot) = self.dot_pending.take()
+                {
+                    self.dot = Some(dot);
                 }
-                KeyCode::Backspace => {
-                    let tgt_line: &mut String;
-                    let prev_line: &mut String;
-                    if !self.insert_mode {
-                        tgt_line = &mut self.command_buf;
-                        if self.status_message {
-                            self.command_buf = "".to_string();
-                            self.status_message = !self.status_message;
-                            return;
-                        }
-                    } else {
-                        self.tab_backspace();
-                        return;
-                    }
+                self.scroll_to_cursor();
+            }
+            _ => {}
+        }
+    }
 
-                    let curpos_x_bordered = if self.cursor_pos_xy.0 == 0 {
-                        self.cursor_pos_xy.0
-                    } else {
-                        self.cursor_pos_xy.0 - 1
-                    };
-
-                    // non ascii support stuff (should be extracted into function later)
-                    let char_count = tgt_line.chars().count() as u16;
-                    if curpos_x_bordered < char_count {
-                        if let Some(idx) = tgt_line
-                            .char_indices()
-                            .nth(curpos_x_bordered as usize)
-                            .map(|(i, _)| i)
-                        {
-                            tgt_line.remove(idx);
-                            self.cursor_pos_xy.0 = curpos_x_bordered;
-                        }
-                    }
-                }
-                KeyCode::Left => {
-                    if self.insert_mode {
-                        self.move_cursor_hor(-1);
-                        return;
-                    }
-                    if self.cursor_pos_xy.0 == 0 {
-                        return;
-                    }
-                    self.cursor_pos_xy.0 -= 1;
-                }
-                KeyCode::Right => {
-                    let tgt_str: &mut String;
-                    if !self.insert_mode {
-                        tgt_str = &mut self.command_buf;
-                    } else {
-                        self.move_cursor_hor(1);
-                        return;
-                    }
+    // Key as it is after maps, to the handler of current mode
+    pub fn dispatch_key(&mut self, key: KeyEvent) {
+        if self.mode == Mode::Insert {
+            self.record_dot_key(key);
+        }
+        match self.mode {
+            Mode::Normal => self.handle_normal_key(key),
+            Mode::Insert => self.handle_insert_key(key),
+            Mode::Command => self.handle_command_key(key),
+            Mode::Visual(_) => self.handle_visual_key(key),
+            Mode::Search { .. } => self.handle_search_key(key),
+            Mode::Confirm => self.handle_confirm_key(key),
+            Mode::Prompt => self.handle_prompt_key(key),
+        }
+    }
 
-                    if self.cursor_pos_xy.0 as usize >= tgt_str.len() {
-                        return;
-                    }
-                    self.cursor_pos_xy.0 += 1;
+    fn handle_insert_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Insert | KeyCode::Esc => {
+                if let Some(bi) = self.block_insert.take() {
+                    self.finish_block_insert(bi);
                 }
-                KeyCode::Up => {
-                    if !self.insert_mode {
-                        self.hist_ctr = self.hist_ctr.saturating_sub(1);
-                        if !self.command_hist.is_empty() {
-                            if let Some(s) = self.command_hist.get(self.hist_ctr.saturating_sub(1))
-                            {
-                                self.command_buf = s.join(" ");
-                                self.status_message = false;
-                            }
-                        }
-                        return;
-                    }
-                    self.move_cursor_vert(-1);
-                }
-                KeyCode::Down => {
-                    if !self.insert_mode {
-                        self.hist_ctr = self
-                            .hist_ctr
-                            .saturating_add(1)
-                            .clamp(0, self.command_hist.len() + 1);
-                        if self.hist_ctr >= self.command_hist.len() {
-                            self.command_buf = "".to_string();
-                            return;
-                        }
-                        if let Some(s) = self.command_hist.get(self.hist_ctr.saturating_add(1)) {
-                            self.command_buf = s.join(" ");
-                        }
-                        return;
-                    }
-                    self.move_cursor_vert(1);
-                }
-                KeyCode::Enter => {
-                    if !self.insert_mode {
-                        self.parse_command();
-                        return;
-                    }
+                self.seal_edit();
+                self.mode = Mode::Normal;
+                let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+                cur_tab.cursor_xy.0 = cur_tab.cursor_xy.0.saturating_sub(1);
+                self.clamp_normal_cursor();
+            }
+            KeyCode::Char(ch) => {
+                self.insert_ch_tab_buf(ch);
+            }
+            KeyCode::Tab => {
+                self.insert_tab_key();
+            }
+            KeyCode::Backspace => {
+                self.tab_backspace();
+            }
+            KeyCode::Enter => {
+                self.tab_newline();
+            }
+            KeyCode::Left => {
+                self.move_cursor_hor(-1);
+            }
+            KeyCode::Right => {
+                self.move_cursor_hor(1);
+            }
+            KeyCode::Up => {
+                self.move_cursor_vert(-1);
+            }
+            KeyCode::Down => {
+                self.move_cursor_vert(1);
+            }
+            _ => {}
+        }
+    }
 
-                    self.tab_newline();
-                }
-                KeyCode::PageUp => {
-                    self.tab_update_scroll_delta(-10);
-                }
-                KeyCode::PageDown => {
-                    self.tab_update_scroll_delta(10);
-                }
-                KeyCode::Home => {
-                    self.tab_update_scroll(0);
+    pub fn handle_command_key(&mut self, key: KeyEvent) {
+        if self.command_hist.rev_search.is_some() {
+            self.handle_rev_search_key(key);
+            return;
+        }
+        if !matches!(key.code, KeyCode::Up | KeyCode::Down) {
+            self.command_hist.stop_browsing();
+        }
+        match key.code {
+            KeyCode::Esc => {
+                self.command_buf.clear();
+                self.cursor_pos_xy.0 = 0;
+                self.mode = Mode::Normal;
+            }
+            KeyCode::Char('r')
+                if key.modifiers.contains(KeyModifiers::CONTROL) && self.mode == Mode::Command =>
+            {
+                self.start_rev_search();
+            }
+            KeyCode::Char(ch) => {
+                let char_pos =
+                    (self.cursor_pos_xy.0 as usize).min(self.command_buf.chars().count());
+                let byte_idx = self
+                    .command_buf
+                    .char_indices()
+                    .nth(char_pos)
+                    .map(|(idx, _)| idx)
+                    .unwrap_or(self.command_buf.len()); // insert at end if position is out of bounds
+
+                self.command_buf.insert(byte_idx, ch);
+
+                self.cursor_pos_xy.0 = char_pos as u16 + 1;
+            }
+            KeyCode::Backspace => {
+                if self.command_buf.is_empty() {
+                    self.mode = Mode::Normal;
+                    return;
                 }
-                KeyCode::End => {
-                    self.tab_update_scroll(usize::MAX); // this will work due to clamp inside function.
+                let tgt_line: &mut String = &mut self.command_buf;
+                let char_pos = (self.cursor_pos_xy.0 as usize).min(tgt_line.chars().count());
+                // removes whole grapheme cluster before cursor
+                let from = grapheme::prev(tgt_line, char_pos, 1);
+                let byte_at = |x: usize| {
+                    tgt_line
+                        .char_indices()
+                        .nth(x)
+                        .map_or(tgt_line.len(), |(i, _)| i)
+                };
+                let range = byte_at(from)..byte_at(char_pos);
+                tgt_line.replace_range(range, "");
+                self.cursor_pos_xy.0 = from as u16;
+            }
+            KeyCode::Left => {
+                let x = self.cursor_pos_xy.0 as usize;
+                self.cursor_pos_xy.0 = grapheme::prev(&self.command_buf, x, 1) as u16;
+            }
+            KeyCode::Right => {
+                let x = self.cursor_pos_xy.0 as usize;
+                self.cursor_pos_xy.0 = grapheme::next(&self.command_buf, x, 1) as u16;
+            }
+            // only commands starting with what was typed before first Up
+            KeyCode::Home => self.cursor_pos_xy.0 = 0,
+            KeyCode::End => self.cursor_pos_xy.0 = self.command_buf.chars().count() as u16,
+            KeyCode::Up => {
+                if let Some(line) = self.command_hist.older(&self.command_buf) {
+                    self.command_buf = line;
+                    self.cursor_pos_xy.0 = self.command_buf.chars().count() as u16;
+                    self.status_message = false;
                 }
-                KeyCode::F(num) => {
-                    if num as usize > self.tabs.len() {
-                        let newtab = Tab::new(None);
-                        self.tabs.push(newtab);
-                        self.cur_tab = self.tabs.len().saturating_sub(1);
-                        return;
-                    }
-                    self.cur_tab = num
-                        .saturating_sub(1)
-                        .min(self.tabs.len().saturating_sub(1) as u8)
-                        as usize;
+            }
+            KeyCode::Down => {
+                if let Some(line) = self.command_hist.newer() {
+                    self.command_buf = line;
+                    self.cursor_pos_xy.0 = self.command_buf.chars().count() as u16;
                 }
-                _ => {}
-            },
+            }
+            KeyCode::Enter => {
+                self.parse_command();
+            }
             _ => {}
         }
     }
@@ -345,145 +443,192 @@ impl App {
                 PluginMessage::RegisterCommand(name, handlr, id) => {
                     self.commands.insert(name, CommandHandler::Lua(handlr, id));
                 }
+                PluginMessage::Map(mode, keys, rhs, id) => {
+                    let res = match rhs {
+                        MapRhs::Text(text) => MapTarget::parse(&text),
+                        MapRhs::Lua(func) => Ok(MapTarget::Lua(func, id)),
+                    }
+                    .and_then(|target| self.add_map(&mode, &keys, target));
+                    if let Err(e) = res {
+                        let _ = self.logger.log_msg(LogLevel::PluginFault, e);
+                    }
+                }
+                PluginMessage::Unmap(mode, keys) => {
+                    if let Err(e) = self.remove_map(&mode, &keys) {
+                        let _ = self.logger.log_msg(LogLevel::PluginFault, e);
+                    }
+                }
                 _ => {}
             }
         }
     }
 
     fn move_cursor_vert(&mut self, delta: isize) {
+        self.seal_edit();
         let cur_tab = &mut self.tabs[self.cur_tab];
 
         let new_y =
-            ((cur_tab.cursor_xy.1 as isize) + delta).clamp(0, (cur_tab.buf.len() - 1) as isize);
+            ((cur_tab.cursor_xy.1 as isize) + delta).clamp(0, cur_tab.buf.last_line() as isize);
         cur_tab.cursor_xy.1 = new_y as usize;
-        let tgt_line: &String = &cur_tab.buf[new_y as usize];
+        let tgt_line_len: usize = cur_tab.buf.line_len(new_y as usize);
 
-        let new_x = cur_tab.cursor_xy.0.clamp(0, tgt_line.len());
-        cur_tab.cursor_xy.0 = new_x;
+        let new_x = cur_tab.cursor_xy.0.clamp(0, tgt_line_len);
+        cur_tab.cursor_xy.0 = cur_tab.buf.grapheme_start(new_y as usize, new_x);
     }
 
     fn move_cursor_hor(&mut self, delta: isize) {
+        self.seal_edit();
         let cur_tab = &mut self.tabs[self.cur_tab];
         let line_y = cur_tab.cursor_xy.1;
-        let tgt_line = &cur_tab.buf[line_y];
-
-        let new_x = ((cur_tab.cursor_xy.0 as isize) + delta).clamp(0, (tgt_line.len()) as isize);
-        cur_tab.cursor_xy.0 = new_x as usize;
+        let x = cur_tab.cursor_xy.0;
+        cur_tab.cursor_xy.0 = match delta < 0 {
+            true => cur_tab.buf.prev_grapheme(line_y, x, delta.unsigned_abs()),
+            false => cur_tab.buf.next_grapheme(line_y, x, delta as usize),
+        };
     }
 
-    fn tab_update_scroll(&mut self, new_scroll_offset: usize) {
-        let cur_tab = &mut self.tabs[self.cur_tab];
-        let max_scroll = cur_tab.buf.len().saturating_sub(1);
-        cur_tab.scroll_offset = new_scroll_offset.clamp(0, max_scroll);
+    pub fn goto_line(&mut self, line: usize) {
+        self.seal_edit();
+        self.push_jump();
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let line = line.min(cur_tab.buf.last_line());
+        cur_tab.cursor_xy = (motions::first_non_blank(&cur_tab.buf, line), line);
+        self.scroll_to_cursor();
     }
 
-    fn tab_update_scroll_delta(&mut self, delta: isize) {
-        let cur_tab = &mut self.tabs[self.cur_tab];
-        let max_scroll = cur_tab.buf.len().saturating_sub(1);
-        let new_scroll = (cur_tab.scroll_offset as isize + delta).clamp(0, max_scroll as isize);
-        cur_tab.scroll_offset = new_scroll as usize;
+    fn insert_ch_tab_buf(&mut self, ch: char) {
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let line_y = cur_tab.cursor_xy.1.min(cur_tab.buf.last_line());
+        let x_char = cur_tab.cursor_xy.0.clamp(0, cur_tab.buf.line_len(line_y));
+        cur_tab.insert_text(line_y, x_char, &ch.to_string(), true);
+        cur_tab.cursor_xy.0 = x_char + 1;
     }
 
-    fn insert_ch_tab_buf(&mut self, ch: char) {
-        let cur_tab: &mut Tab = match self.left_area_used {
-            true => &mut self.left_area,
-            false => &mut self.tabs[self.cur_tab],
-        };
-        let line_y = (cur_tab.scroll_offset + cur_tab.cursor_xy.1).clamp(0, cur_tab.buf.len());
-        let x = cur_tab.cursor_xy.0.clamp(0, cur_tab.buf[line_y].len());
-
-        let x_char = cur_tab
-            .cursor_xy
-            .0
-            .clamp(0, cur_tab.buf[line_y].chars().count());
-        let x_byte = cur_tab.buf[line_y]
-            .char_indices()
-            .nth(x_char)
-            .map(|(i, _)| i)
-            .unwrap_or(cur_tab.buf[line_y].len()); // for non-ascii
-        cur_tab.buf[line_y].insert(x_byte, ch);
-        cur_tab.cursor_xy.0 += 1;
-        cur_tab.changed = true;
+    // Tab char, or spaces up to next tabstop with expandtab
+    fn insert_tab_key(&mut self) {
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        if !self.tab_expandtab(cur_tab) {
+            self.insert_ch_tab_buf('\t');
+            return;
+        }
+        let tabstop = self.tab_tabstop(cur_tab);
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let line_y = cur_tab.cursor_xy.1.min(cur_tab.buf.last_line());
+        let x_char = cur_tab.cursor_xy.0.min(cur_tab.buf.line_len(line_y));
+        let col = grapheme::display_col(&cur_tab.buf.line(line_y), x_char, tabstop);
+        let spaces = tabstop - col % tabstop;
+        cur_tab.insert_text(line_y, x_char, &" ".repeat(spaces), true);
+        cur_tab.cursor_xy.0 = x_char + spaces;
     }
 
     fn tab_newline(&mut self) {
         let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
-        let line_y = cur_tab.cursor_xy.1.clamp(0, cur_tab.buf.len());
-        let line = &mut cur_tab.buf[line_y];
+        let line_y = cur_tab.cursor_xy.1.min(cur_tab.buf.last_line());
 
         // Clamp the cursor x to the number of chars, not bytes
-        let x_chars = cur_tab.cursor_xy.0.clamp(0, line.chars().count());
-
-        // byte offset
-        let byte_index = line
-            .char_indices()
-            .nth(x_chars)
-            .map(|(i, _)| i)
-            .unwrap_or_else(|| line.len());
-
-        let to_move: String = line[byte_index..].to_string();
-        line.truncate(byte_index);
-        cur_tab.buf.insert(line_y + 1, to_move);
+        let x_chars = cur_tab.cursor_xy.0.clamp(0, cur_tab.buf.line_len(line_y));
+
+        cur_tab.insert_text(line_y, x_chars, "\n", true);
         cur_tab.cursor_xy.0 = 0;
         cur_tab.cursor_xy.1 = line_y + 1;
-        cur_tab.changed = true;
     }
 
     fn tab_backspace(&mut self) {
         let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
-        let line_y = (cur_tab.scroll_offset + cur_tab.cursor_xy.1).clamp(0, cur_tab.buf.len());
-        let x_char = cur_tab
-            .cursor_xy
-            .0
-            .clamp(0, cur_tab.buf[line_y].chars().count());
-        let mut tgt_line = &mut cur_tab.buf[line_y];
-
-        let (before, after) = cur_tab.buf.split_at_mut(cur_tab.cursor_xy.1 as usize);
-        tgt_line = after.get_mut(0).expect("can't get cur line!");
-
-        if cur_tab.cursor_xy.0 == 0 {
-            if cur_tab.cursor_xy.1 == 0 {
+        let line_y = cur_tab.cursor_xy.1.min(cur_tab.buf.last_line());
+        let x_char = cur_tab.cursor_xy.0.clamp(0, cur_tab.buf.line_len(line_y));
+
+        if x_char == 0 {
+            if line_y == 0 {
                 return;
             }
-            let prev_line = before.last_mut().expect("can't get prev line");
-            cur_tab.cursor_xy.0 = prev_line.chars().count();
-            prev_line.push_str(tgt_line);
-            cur_tab.buf.remove(cur_tab.cursor_xy.1 as usize);
+            // joining with previous line
+            let prev_len = cur_tab.buf.line_len(line_y - 1);
+            cur_tab.remove_text(line_y - 1, prev_len, line_y, 0, true);
+            cur_tab.cursor_xy.0 = prev_len;
             cur_tab.cursor_xy.1 -= 1;
-            cur_tab.changed = true;
             return;
         }
 
-        match x_char {
-            0 => {}
-            _ => {
-                // getting byte index
-                if let Some(idx) = cur_tab.buf[line_y]
-                    .char_indices()
-                    .nth(x_char - 1)
-                    .map(|(i, _)| i)
-                {
-                    cur_tab.buf[line_y].remove(idx);
-                    cur_tab.cursor_xy.0 -= 1;
-                    cur_tab.changed = true;
-                }
-            }
+        // whole cluster goes, so no lone combining mark is left
+        let from = cur_tab.buf.prev_grapheme(line_y, x_char, 1);
+        cur_tab.remove_text(line_y, from, line_y, x_char, true);
+        cur_tab.cursor_xy.0 = from;
+    }
+
+    // Closes running typing session so next edit will be a separate undo step
+    pub fn seal_edit(&mut self) {
+        if let Some(tab) = self.tabs.get_mut(self.cur_tab) {
+            tab.edit_hist.seal();
+        }
+    }
+
+    pub fn tab_undo(&mut self) {
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        if !cur_tab.undo() {
+            self.throw_status_message("Already at oldest change".to_string());
+        }
+    }
+
+    pub fn tab_redo(&mut self) {
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        if !cur_tab.redo() {
+            self.throw_status_message("Already at newest change".to_string());
         }
     }
 
     fn parse_command(&mut self) {
+        self.command_hist.add(&self.command_buf);
+        self.run_command_buf();
+    }
+
+    // Runs command line without putting it into history, mapped commands go here
+    pub fn run_command_buf(&mut self) {
         let lexems: Vec<String> = self
             .command_buf
             .split_whitespace()
             .map(String::from)
             .collect();
         if lexems.is_empty() {
-            self.throw_status_message("ERR: Command buffer is empty".to_string());
+            self.throw_status_error("ERR: Command buffer is empty".to_string());
             return;
         }
 
-        self.command_hist.push(lexems.clone());
-        self.hist_ctr = self.command_hist.len();
+        self.mode = Mode::Normal;
+
+        // optional line range goes between '!' and command name: !10,20w part.rs
+        let line = self.command_buf.trim_start().to_string();
+        let bang = line.starts_with('!');
+        let body = line.strip_prefix('!').unwrap_or(&line);
+        let last_search = self.search.regex.as_ref();
+        let (range, rest) = match range::parse_range(body, &self.tabs[self.cur_tab], last_search) {
+            Ok(r) => r,
+            Err(e) => {
+                self.throw_status_error(e);
+                return;
+            }
+        };
+        // s takes its delimited pattern as is, it may have spaces in it
+        let mut rest_chars = rest.chars();
+        let lexems: Vec<String> = match (rest_chars.next(), rest_chars.next()) {
+            (Some('s'), Some(d)) if !d.is_alphanumeric() && !d.is_whitespace() => {
+                vec!["!s".to_string(), rest[1..].to_string()]
+            }
+            _ => {
+                let mut lx: Vec<String> = rest.split_whitespace().map(String::from).collect();
+                if lx.is_empty() {
+                    // bare range like !42 goes to the line
+                    if let Some(r) = range {
+                        self.goto_line(r.end);
+                    }
+                    return;
+                }
+                if bang {
+                    lx[0].insert(0, '!');
+                }
+                lx
+            }
+        };
         let command = &lexems.clone()[0];
 
         let mut res_com: &String = &command.clone();
@@ -501,9 +646,18 @@ impl App {
         res_args.append(&mut args);
         let mut to_throw: Option<String> = None;
 
-        match self.commands.get(&(res_com.clone())) {
+        // commands typed after ':' come without the '!'
+        let handler = match self.commands.get(res_com) {
+            Some(h) => Some(h),
+            None => self.commands.get(&format!("!{}", res_com)),
+        };
+        match handler {
             Some(handler) => match handler {
+                CommandHandler::Rust(_) if range.is_some() => {
+                    self.throw_status_error(format!("{} doesn't take a range", res_com));
+                }
                 CommandHandler::Rust(f) => f(self, res_args.clone()),
+                CommandHandler::RustRange(f) => f(self, range, res_args.clone()),
                 CommandHandler::Lua(lf, id) => {
                     let PlugLoaders::LuaL(lualoader) = &self.plugin_subsys;
                     match lualoader.plugins.get(id.clone()) {
@@ -516,7 +670,26 @@ impl App {
                                     .map_err(|e| e.to_string())
                                     .unwrap();
                             }
-                            let res = lf.call::<()>(&args_table);
+                            // range goes as second arg: {first, last, lines}, 1-based; nil without range
+                            let range_val = match range {
+                                Some(r) => {
+                                    let buf = &self.tabs[self.cur_tab].buf;
+                                    let lines: Vec<String> =
+                                        (r.start..=r.end).map(|l| buf.line(l)).collect();
+                                    plug.lua
+                                        .create_table_from([
+                                            ("first", Value::Integer(r.start as i64 + 1)),
+                                            ("last", Value::Integer(r.end as i64 + 1)),
+                                        ])
+                                        .and_then(|t| {
+                                            t.set("lines", lines)?;
+                                            Ok(Value::Table(t))
+                                        })
+                                        .unwrap_or(Value::Nil)
+                                }
+                                None => Value::Nil,
+                            };
+                            let res = lf.call::<()>((&args_table, range_val));
                             match res {
                                 Err(e) => {
                                     to_throw = Some(e.to_string());
@@ -535,7 +708,7 @@ impl App {
 
                     // debug
                     if let Some(m) = to_throw {
-                        self.throw_status_message(m);
+                        self.throw_status_error(m);
                     } else {
                         self.throw_status_message(res_com.clone());
                     }
@@ -543,22 +716,33 @@ impl App {
                 _ => {}
             },
             None => {
-                self.throw_status_message("ERR: No such command".to_string());
+                self.throw_status_error("ERR: No such command".to_string());
                 return;
             }
         };
     }
 
     pub fn throw_status_message(&mut self, error: String) {
+        self.status_level = StatusLevel::Info;
         self.command_buf = error;
         self.status_message = true;
     }
 
+    pub fn throw_status_error(&mut self, error: String) {
+        self.throw_status_message(error);
+        self.status_level = StatusLevel::Error;
+    }
+
+    pub fn throw_status_warning(&mut self, warning: String) {
+        self.throw_status_message(warning);
+        self.status_level = StatusLevel::Warning;
+    }
+
     fn gen_hashmap_com(&mut self) {
         self.commands
             .insert("!hi".to_string(), CommandHandler::Rust(commands::com_hi));
         self.commands
-            .insert("!w".to_string(), CommandHandler::Rust(commands::com_w));
+            .insert("!w".to_string(), CommandHandler::RustRange(commands::com_w));
         self.commands
             .insert("!r".to_string(), CommandHandler::Rust(commands::com_r));
         self.commands
@@ -571,7 +755,7 @@ impl App {
         );
         self.commands.insert(
             "!execn".to_string(),
-            CommandHandler::Rust(commands::com_execn),
+            CommandHandler::RustRange(commands::com_execn),
         );
         self.commands.insert(
             "!exec_f".to_string(),
@@ -599,5 +783,75 @@ impl App {
             "!plugin".to_string(),
             CommandHandler::Rust(commands::com_plugin),
         );
+        self.commands.insert(
+            "!undo".to_string(),
+            CommandHandler::Rust(commands::com_undo),
+        );
+        self.commands.insert(
+            "!redo".to_string(),
+            CommandHandler::Rust(commands::com_redo),
+        );
+        self.commands.insert(
+            "!registers".to_string(),
+            CommandHandler::Rust(commands::com_registers),
+        );
+        self.commands.insert(
+            "!filter".to_string(),
+            CommandHandler::RustRange(commands::com_filter),
+        );
+        self.commands
+            .insert("!s".to_string(), CommandHandler::RustRange(commands::com_s));
+        self.commands.insert(
+            "!marks".to_string(),
+            CommandHandler::Rust(commands::com_marks),
+        );
+        self.commands
+            .insert("!d".to_string(), CommandHandler::RustRange(commands::com_d));
+        self.commands
+            .insert("!y".to_string(), CommandHandler::RustRange(commands::com_y));
+        self.commands.insert(
+            "!nohl".to_string(),
+            CommandHandler::Rust(commands::com_nohl),
+        );
+        self.commands
+            .insert("!ff".to_string(), CommandHandler::Rust(commands::com_ff));
+        self.commands
+            .insert("!enc".to_string(), CommandHandler::Rust(commands::com_enc));
+        self.commands
+            .insert("!eol".to_string(), CommandHandler::Rust(commands::com_eol));
+        self.commands.insert(
+            "!recover".to_string(),
+            CommandHandler::Rust(commands::com_recover),
+        );
+        self.commands.insert(
+            "!session".to_string(),
+            CommandHandler::Rust(commands::com_session),
+        );
+        self.commands
+            .insert("!set".to_string(), CommandHandler::Rust(commands::com_set));
+        self.commands.insert(
+            "!setl".to_string(),
+            CommandHandler::Rust(commands::com_setl),
+        );
+        self.commands
+            .insert("!map".to_string(), CommandHandler::Rust(commands::com_map));
+        self.commands.insert(
+            "!unmap".to_string(),
+            CommandHandler::Rust(commands::com_unmap),
+        );
+        self.commands.insert(
+            "!maps".to_string(),
+            CommandHandler::Rust(commands::com_maps),
+        );
+        self.commands.insert(
+            "!filetype".to_string(),
+            CommandHandler::Rust(commands::com_filetype),
+        );
+        self.commands.insert(
+            "!theme".to_string(),
+            CommandHandler::Rust(commands::com_theme),
+        );
+        self.commands
+            .insert("!win".to_string(), CommandHandler::Rust(commands::com_win));
     }
 }
@@ -0,0 +1,221 @@
+use ropey::{Rope, RopeBuilder, RopeSlice};
+
+use crate::grapheme;
+
+// Chars looked at around the cursor when stepping over grapheme clusters
+const SEG_REACH: usize = 64;
+
+// Text storage of a tab.
+// Lines are separated by '\n' only; there's no trailing newline
+// stored, so an empty buffer still has one (empty) line.
+// Positions are (line, x) where x is counted in chars.
+#[derive(Debug, Clone, Default)]
+pub struct TextBuffer {
+    rope: Rope,
+    edited_from: usize, // first line changed since syntax state was updated, usize::MAX if none
+}
+
+impl TextBuffer {
+    pub fn new() -> TextBuffer {
+        TextBuffer {
+            rope: Rope::new(),
+            edited_from: 0,
+        }
+    }
+
+    pub fn from_text(text: &str) -> TextBuffer {
+        TextBuffer {
+            rope: Rope::from_str(text),
+            edited_from: 0,
+        }
+    }
+
+    pub fn from_lines(lines: &[String]) -> TextBuffer {
+        let mut builder = RopeBuilder::new();
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                builder.append("\n");
+            }
+            builder.append(line);
+        }
+        TextBuffer {
+            rope: builder.finish(),
+            edited_from: 0,
+        }
+    }
+
+    pub fn rope(&self) -> &Rope {
+        &self.rope
+    }
+
+    // Lines from this one on have changed since the last call
+    pub fn take_edited_from(&mut self) -> Option<usize> {
+        let from = std::mem::replace(&mut self.edited_from, usize::MAX);
+        Some(from).filter(|l| *l != usize::MAX)
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.rope.len_lines()
+    }
+
+    pub fn last_line(&self) -> usize {
+        self.rope.len_lines() - 1
+    }
+
+    // Line contents without line break
+    pub fn line(&self, idx: usize) -> String {
+        let mut res = self.rope.line(idx).to_string();
+        if res.ends_with('\n') {
+            res.pop();
+        }
+        res
+    }
+
+    // Up to `chars` first chars of the line, so a huge one isn't copied whole
+    pub fn line_head(&self, idx: usize, chars: usize) -> String {
+        let len = self.line_len(idx);
+        self.rope.line(idx).slice(..chars.min(len)).to_string()
+    }
+
+    // Line length in chars, line break not counted
+    pub fn line_len(&self, idx: usize) -> usize {
+        let slice = self.rope.line(idx);
+        let len = slice.len_chars();
+        if len > 0 && slice.char(len - 1) == '\n' {
+            return len - 1;
+        }
+        len
+    }
+
+    // Part of the line around chars from..to as (first char, text), so stepping over
+    // clusters doesn't segment a whole huge line. Its ends are moved out to cluster breaks.
+    fn seg_window(&self, line: usize, from: usize, to: usize) -> (usize, String) {
+        let slice = self.rope.line(line).slice(..self.line_len(line));
+        let len = slice.len_chars();
+        let (mut start, mut end) = (from.min(len), to.min(len));
+        let floor = start.saturating_sub(SEG_REACH);
+        while start > floor && !breaks_at(slice, start) {
+            start -= 1;
+        }
+        let ceil = (end + SEG_REACH).min(len);
+        while end < ceil && !breaks_at(slice, end) {
+            end += 1;
+        }
+        (start, slice.slice(start..end).to_string())
+    }
+
+    // Start of grapheme cluster holding char x of the line
+    pub fn grapheme_start(&self, line: usize, x: usize) -> usize {
+        let (start, text) = self.seg_window(line, x, x);
+        let st = grapheme::starts(&text);
+        match st.binary_search(&(x - start).min(text.chars().count())) {
+            Ok(i) => start + st[i],
+            Err(i) => start + st[i.saturating_sub(1)],
+        }
+    }
+
+    // x moved n clusters right, stops at line end
+    pub fn next_grapheme(&self, line: usize, x: usize, n: usize) -> usize {
+        let len = self.line_len(line);
+        let (mut x, mut n) = (x.min(len), n);
+        while n > 0 && x < len {
+            let (start, text) = self.seg_window(line, x, x + SEG_REACH);
+            let st = grapheme::starts(&text);
+            let i = match st.binary_search(&(x - start)) {
+                Ok(i) => i,
+                Err(i) => i.saturating_sub(1),
+            };
+            let steps = n.min(st.len() - 1 - i);
+            if steps == 0 {
+                break;
+            }
+            x = start + st[i + steps];
+            n -= steps;
+        }
+        x
+    }
+
+    // x moved n clusters left
+    pub fn prev_grapheme(&self, line: usize, x: usize, n: usize) -> usize {
+        let (mut x, mut n) = (x.min(self.line_len(line)), n);
+        while n > 0 && x > 0 {
+            let (start, text) = self.seg_window(line, x.saturating_sub(SEG_REACH), x);
+            let st = grapheme::starts(&text);
+            let i = match st.binary_search(&(x - start)) {
+                Ok(i) => i,
+                Err(i) => i,
+            };
+            let steps = n.min(i);
+            if steps == 0 {
+                break;
+            }
+            x = start + st[i - steps];
+            n -= steps;
+        }
+        x
+    }
+
+    // Absolute char index of (line, x); both are clamped into the buffer
+    pub fn pos_to_char(&self, line: usize, x: usize) -> usize {
+        let line = line.min(self.last_line());
+        self.rope.line_to_char(line) + x.min(self.line_len(line))
+    }
+
+    pub fn char_to_pos(&self, char_idx: usize) -> (usize, usize) {
+        let char_idx = char_idx.min(self.rope.len_chars());
+        let line = self.rope.char_to_line(char_idx);
+        (line, char_idx - self.rope.line_to_char(line))
+    }
+
+    // Splices another rope in; cheap even for huge texts
+    pub fn insert_rope(&mut self, line: usize, x: usize, text: &Rope) {
+        let at = self.pos_to_char(line, x);
+        self.edited_from = self.edited_from.min(line.min(self.last_line()));
+        let tail = self.rope.split_off(at);
+        self.rope.append(text.clone());
+        self.rope.append(tail);
+    }
+
+    // Removes text between two positions, returns what was removed
+    pub fn remove(
+        &mut self,
+        start_line: usize,
+        start_x: usize,
+        end_line: usize,
+        end_x: usize,
+    ) -> Rope {
+        let start = self.pos_to_char(start_line, start_x);
+        let end = self.pos_to_char(end_line, end_x).max(start);
+        self.edited_from = self.edited_from.min(start_line.min(self.last_line()));
+        let mut removed = self.rope.split_off(start);
+        let tail = removed.split_off(end - start);
+        self.rope.append(tail);
+        removed
+    }
+
+    pub fn slice(
+        &self,
+        start_line: usize,
+        start_x: usize,
+        end_line: usize,
+        end_x: usize,
+    ) -> String {
+        let start = self.pos_to_char(start_line, start_x);
+        let end = self.pos_to_char(end_line, end_x).max(start);
+        self.rope.slice(start..end).to_string()
+    }
+}
+
+// Whether a cluster starts at char x, judged by a few chars around it
+fn breaks_at(slice: RopeSlice, x: usize) -> bool {
+    let from = x.saturating_sub(8);
+    let to = (x + 1).min(slice.len_chars());
+    let text = slice.slice(from..to).to_string();
+    grapheme::starts(&text).contains(&(x - from))
+}
+
+impl std::fmt::Display for TextBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.rope)
+    }
+}
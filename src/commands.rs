@@ -1,14 +1,24 @@
 use crate::{
     app::App,
+    buffer::TextBuffer,
+    disk::{self, DiskInfo, DiskStamp},
+    edits::FileIdent,
+    fileformat::{self, FileFormat, LineEnding},
+    keymap::{MapMode, MapTarget},
     logger::LogLevel,
+    operators::{OpRange, Operator},
     plugin::{LoaderSysState, LuaLoader, LuaPlugin, PlugLoaders},
+    range::LineRange,
+    registers::Registers,
+    save, session,
     tabs::Tab,
+    theme,
+    window::{FocusDir, SplitDir},
 };
 use std::{
     fmt::format,
-    fs::File,
-    io::{BufRead, BufReader, Write},
-    process::Command,
+    io::{BufReader, Write},
+    process::{Command, Stdio},
 };
 
 pub fn com_hi(app: &mut App, args: Vec<String>) {
@@ -16,7 +26,11 @@ pub fn com_hi(app: &mut App, args: Vec<String>) {
     return;
 }
 
-pub fn com_w(app: &mut App, args: Vec<String>) {
+pub fn com_w(app: &mut App, range: Option<LineRange>, args: Vec<String>) {
+    if let Some(r) = range {
+        write_range(app, r, args);
+        return;
+    }
     let curtab = &mut app.tabs[app.cur_tab];
     let mut file_out_name: String = String::new();
     if !args.is_empty() {
@@ -28,110 +42,132 @@ pub fn com_w(app: &mut App, args: Vec<String>) {
         return;
     }
 
-    let mut file_out: File = match File::create(file_out_name.clone()) {
-        Ok(f) => f,
+    // someone else may have changed the file since we read it
+    if file_out_name == curtab.filename && !app.disk_unchanged_for_write(app.cur_tab) {
+        return;
+    }
+    let curtab = &mut app.tabs[app.cur_tab];
+    let bytes = match fileformat::to_bytes(&curtab.buf, &curtab.format) {
+        Ok(b) => b,
         Err(e) => {
-            app.throw_status_message(e.to_string());
+            app.throw_status_error(format!("{}; change it with !enc", e));
             return;
         }
     };
-
-    curtab.filename = file_out_name;
-    curtab.changed = false;
-    let mut contents: String = curtab.buf.join("\n");
-    contents.push('\n');
-    match file_out.write_all(contents.as_bytes()) {
-        Ok(_) => app.throw_status_message("Success".to_string()),
+    let write_res = save::write_file(&file_out_name, &bytes, &app.config.backup);
+    let curtab = &mut app.tabs[app.cur_tab];
+    match write_res {
+        Ok(_) => {
+            curtab.disk = DiskInfo::new(DiskStamp::written(&file_out_name, &bytes));
+            curtab.filename = file_out_name;
+            curtab.mark_saved();
+            app.throw_status_message("Success".to_string());
+        }
         Err(e) => {
             curtab.changed = true;
-            app.throw_status_message(e.to_string());
+            app.throw_status_error(e.to_string());
         }
     };
 }
 
-pub fn com_r(app: &mut App, args: Vec<String>) {
-    let curtab = &mut app.tabs[app.cur_tab];
-    if curtab.changed {
-        app.throw_status_message("W: Current buffer isn't saved. !ri to ignore".to_string());
-        return;
-    }
-    if args.is_empty() {
-        app.throw_status_message("Usage: !r filename".to_string());
-        return;
-    }
-
-    let file_in: File = match File::open(args[0].clone()) {
-        Ok(f) => f,
-        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
-            curtab.filename = args[0].clone();
-            curtab.changed = false;
-            curtab.cursor_xy = (0, 0);
-            app.throw_status_message(e.to_string());
+// !10,40w part.rs: writes only those lines, tab keeps its file and changed state
+fn write_range(app: &mut App, range: LineRange, args: Vec<String>) {
+    let curtab = &app.tabs[app.cur_tab];
+    let file_out_name = match args.first() {
+        Some(name) if *name != curtab.filename => name.clone(),
+        Some(_) => {
+            app.throw_status_message(
+                "Won't overwrite tab's own file with a part of it".to_string(),
+            );
             return;
         }
-        Err(e) => {
-            app.throw_status_message(e.to_string());
+        None => {
+            app.throw_status_message("Usage: !{range}w filename".to_string());
             return;
         }
     };
-
-    curtab.buf.clear();
-    let reader = BufReader::new(file_in);
-    for line in reader.lines() {
-        match line {
-            Ok(l) => {
-                let res = l.clone().replace('\n', "");
-                curtab.buf.push(res);
-            }
-            Err(e) => {
-                curtab.buf.clear();
-                app.throw_status_message(e.to_string());
-                return;
-            }
+    let last_len = curtab.buf.line_len(range.end);
+    let text = curtab.buf.slice(range.start, 0, range.end, last_len);
+    // same ending and encoding as the tab, last line always ends
+    let format = FileFormat {
+        final_newline: true,
+        ..curtab.format
+    };
+    let bytes = match fileformat::to_bytes(&TextBuffer::from_text(&text), &format) {
+        Ok(b) => b,
+        Err(e) => {
+            app.throw_status_error(e);
+            return;
         }
+    };
+    let write_res = save::write_file(&file_out_name, &bytes, &app.config.backup);
+    match write_res {
+        Ok(_) => app.throw_status_message(format!(
+            "{} line(s) written to {}",
+            range.end - range.start + 1,
+            file_out_name
+        )),
+        Err(e) => app.throw_status_error(e.to_string()),
     }
-    curtab.changed = false;
-    curtab.cursor_xy = (0, 0);
-    curtab.filename = args[0].clone();
-    app.throw_status_message("Success".to_string());
-    return;
 }
 
-pub fn com_ri(app: &mut App, args: Vec<String>) {
+pub fn com_r(app: &mut App, args: Vec<String>) {
     let curtab = &mut app.tabs[app.cur_tab];
-    if args.is_empty() {
-        app.throw_status_message("Usage: !r filename".to_string());
+    if curtab.changed {
+        app.throw_status_warning("W: Current buffer isn't saved. !ri to ignore".to_string());
         return;
     }
+    read_into_tab(app, args);
+}
 
-    let file_in: File = match File::open(args[0].clone()) {
-        Ok(f) => f,
-        Err(e) => {
-            app.throw_status_message(e.to_string());
+pub fn com_ri(app: &mut App, args: Vec<String>) {
+    read_into_tab(app, args);
+}
+
+// !r and !ri; without filename tab's own file is read again
+fn read_into_tab(app: &mut App, args: Vec<String>) {
+    let curtab = &mut app.tabs[app.cur_tab];
+    let filename = match args.first() {
+        Some(name) => name.clone(),
+        None if !curtab.filename.is_empty() => curtab.filename.clone(),
+        None => {
+            app.throw_status_message("Usage: !r filename".to_string());
             return;
         }
     };
 
-    curtab.buf.clear();
-    let reader = BufReader::new(file_in);
-    for line in reader.lines() {
-        match line {
-            Ok(l) => {
-                let res = l.clone().replace('\n', "");
-                curtab.buf.push(res);
-            }
-            Err(e) => {
-                curtab.buf.clear();
-                app.throw_status_message(e.to_string());
-                return;
-            }
+    // missing file gives an empty buffer, it's created on !w
+    let (new_buf, format, disk, msg) = match disk::read_file(&filename) {
+        Ok((buf, format, stamp)) => (
+            buf,
+            format,
+            DiskInfo::new(Some(stamp)),
+            "Success".to_string(),
+        ),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => (
+            TextBuffer::new(),
+            FileFormat::default(),
+            DiskInfo::default(),
+            e.to_string(),
+        ),
+        Err(e) => {
+            app.throw_status_error(e.to_string());
+            return;
         }
-    }
-    curtab.changed = false;
+    };
+    let before = curtab.file_ident();
+    let after = FileIdent {
+        filename,
+        format,
+        disk,
+    };
+    curtab.replace_buf(new_buf);
+    curtab.edit_hist.set_file_change(before, after.clone());
+    curtab.set_file(after);
+    curtab.mark_saved();
     curtab.cursor_xy = (0, 0);
-    curtab.filename = args[0].clone();
-    app.throw_status_message("Success".to_string());
-    return;
+    app.throw_status_message(msg);
+    app.check_swap(app.cur_tab);
 }
 
 pub fn com_rn(app: &mut App, args: Vec<String>) {
@@ -145,7 +181,7 @@ pub fn com_rn(app: &mut App, args: Vec<String>) {
     match newtab.readf(filename.clone()) {
         Ok(()) => {}
         Err(e) => {
-            app.throw_status_message(e.to_string());
+            app.throw_status_error(e.to_string());
             return;
         }
     }
@@ -153,13 +189,13 @@ pub fn com_rn(app: &mut App, args: Vec<String>) {
     app.tabs.push(newtab);
     app.cur_tab = app.tabs.len().saturating_sub(1);
     app.throw_status_message("Success".to_string());
-    return;
+    app.check_swap(app.cur_tab);
 }
 
 pub fn com_q(app: &mut App, args: Vec<String>) {
     let curtab = &app.tabs[app.cur_tab];
     if curtab.changed {
-        app.throw_status_message(
+        app.throw_status_warning(
             "W: Current buffer has unsaved changes; !qi to ignore".to_string(),
         );
         return;
@@ -176,18 +212,12 @@ pub fn com_exec(app: &mut App, args: Vec<String>) {
         app.throw_status_message("Usage: !exec command".to_string());
         return;
     }
-    let com = if cfg!(target_os = "windows") {
-        let argline: &str = &args.join(" ");
-        Command::new("cmd")
-            .args(&["/C", &argline])
-            .output()
-            .expect("Error creating cmd")
-    } else {
-        let argline: &str = &args.join(" ");
-        Command::new("sh")
-            .args(&["-c", argline])
-            .output()
-            .expect("Error running sh")
+    let com = match app.config.shell_command(&args.join(" ")).output() {
+        Ok(c) => c,
+        Err(e) => {
+            app.throw_status_error(format!("Can't run {}: {}", app.config.shell(), e));
+            return;
+        }
     };
 
     let mut output_s: String = String::new();
@@ -206,16 +236,12 @@ pub fn com_exec_f(app: &mut App, args: Vec<String>) {
         return;
     }
 
-    let com = if cfg!(target_os = "windows") {
-        Command::new("cmd")
-            .args(args)
-            .output()
-            .expect("Error creating cmd")
-    } else {
-        Command::new("sh")
-            .args(args)
-            .output()
-            .expect("Error running sh")
+    let com = match Command::new(app.config.shell()).args(args).output() {
+        Ok(c) => c,
+        Err(e) => {
+            app.throw_status_error(format!("Can't run {}: {}", app.config.shell(), e));
+            return;
+        }
     };
 
     let mut output_s: String = String::new();
@@ -227,7 +253,7 @@ pub fn com_exec_f(app: &mut App, args: Vec<String>) {
     app.throw_status_message(output_s);
 }
 
-pub fn com_execn(app: &mut App, args: Vec<String>) {
+pub fn com_execn(app: &mut App, range: Option<LineRange>, args: Vec<String>) {
     let mut same_tab: bool = false;
     let mut ignore_flag: bool = false;
 
@@ -249,16 +275,16 @@ pub fn com_execn(app: &mut App, args: Vec<String>) {
         },
         false => &args.join(" "),
     };
-    let com = if cfg!(target_os = "windows") {
-        Command::new("cmd")
-            .args(&["/C", &argline])
-            .output()
-            .expect("Error creating cmd")
-    } else {
-        Command::new("sh")
-            .args(&["-c", argline])
-            .output()
-            .expect("Error running sh")
+    if let Some(r) = range {
+        execn_range(app, r, argline, same_tab);
+        return;
+    }
+    let com = match app.config.shell_command(argline).output() {
+        Ok(c) => c,
+        Err(e) => {
+            app.throw_status_error(format!("Can't run {}: {}", app.config.shell(), e));
+            return;
+        }
     };
 
     let mut output_s: String = String::new();
@@ -273,16 +299,17 @@ pub fn com_execn(app: &mut App, args: Vec<String>) {
     if same_tab {
         if let Some(tab) = app.tabs.get_mut(app.cur_tab) {
             if (tab.changed && !ignore_flag) {
-                app.throw_status_message(
+                app.throw_status_warning(
                     "W: This tab has unsaved changes. ~ignore to ignore".to_owned(),
                 );
                 return;
             }
-            tab.buf = lines;
+            tab.replace_buf(TextBuffer::from_lines(&lines));
+            tab.cursor_xy = (0, 0);
         }
     } else {
         let mut output_tab = Tab::new(Some("Output".to_string()));
-        output_tab.buf = lines;
+        output_tab.buf = TextBuffer::from_lines(&lines);
         app.tabs.push(output_tab);
         app.cur_tab = app.tabs.len().saturating_sub(1);
     }
@@ -291,6 +318,31 @@ pub fn com_execn(app: &mut App, args: Vec<String>) {
     return;
 }
 
+// !5,10execn cmd: lines go to stdin of cmd. With ~cur output replaces them
+// (undoable, so no unsaved changes warning), otherwise it opens in new tab.
+fn execn_range(app: &mut App, range: LineRange, argline: &str, same_tab: bool) {
+    let tab = &app.tabs[app.cur_tab];
+    let last_len = tab.buf.line_len(range.end);
+    let input = tab.buf.slice(range.start, 0, range.end, last_len) + "\n";
+    let out = match pipe_through_shell(app.config.shell_command(argline), input) {
+        Ok(o) => o,
+        Err(e) => {
+            app.throw_status_error(e);
+            return;
+        }
+    };
+    if same_tab {
+        replace_lines(app, range.start, range.end, &out);
+    } else {
+        let lines: Vec<String> = out.lines().map(|line| line.to_string()).collect();
+        let mut output_tab = Tab::new(Some("Output".to_string()));
+        output_tab.buf = TextBuffer::from_lines(&lines);
+        app.tabs.push(output_tab);
+        app.cur_tab = app.tabs.len().saturating_sub(1);
+    }
+    app.throw_status_message("Success".to_string());
+}
+
 pub fn com_execn_f(app: &mut App, args: Vec<String>) {
     let mut same_tab: bool = false;
     let mut ignore_flag: bool = false;
@@ -312,16 +364,12 @@ pub fn com_execn_f(app: &mut App, args: Vec<String>) {
         },
         false => &args.join(" "),
     };
-    let com = if cfg!(target_os = "windows") {
-        Command::new("cmd")
-            .args([argline])
-            .output()
-            .expect("Error creating cmd")
-    } else {
-        Command::new("sh")
-            .args([argline])
-            .output()
-            .expect("Error running sh")
+    let com = match Command::new(app.config.shell()).args([argline]).output() {
+        Ok(c) => c,
+        Err(e) => {
+            app.throw_status_error(format!("Can't run {}: {}", app.config.shell(), e));
+            return;
+        }
     };
 
     let mut output_s: String = String::new();
@@ -335,16 +383,17 @@ pub fn com_execn_f(app: &mut App, args: Vec<String>) {
     if same_tab {
         if let Some(tab) = app.tabs.get_mut(app.cur_tab) {
             if (tab.changed && !ignore_flag) {
-                app.throw_status_message(
+                app.throw_status_warning(
                     "W: This tab has unsaved changes. ~ignore to ignore".to_owned(),
                 );
                 return;
             }
-            tab.buf = lines;
+            tab.replace_buf(TextBuffer::from_lines(&lines));
+            tab.cursor_xy = (0, 0);
         }
     } else {
         let mut output_tab = Tab::new(Some("Output".to_string()));
-        output_tab.buf = lines;
+        output_tab.buf = TextBuffer::from_lines(&lines);
         app.tabs.push(output_tab);
         app.cur_tab = app.tabs.len().saturating_sub(1);
     }
@@ -353,11 +402,11 @@ pub fn com_execn_f(app: &mut App, args: Vec<String>) {
     return;
 }
 
+const TAB_USAGE: &str = "Usage: !tab new, !tab goto num, !tab rm num, !tab next, !tab prev, !tab rename num name, !tab wrap [on/off], !tab autoreload [on/off], !tab showdiffn";
+
 pub fn com_tab(app: &mut App, args: Vec<String>) {
     if args.is_empty() {
-        app.throw_status_message(
-            "Usage: !tab new, !tab goto num, !tab rm num, !tab next, !tab prev, !tab rename num name".to_string(),
-        );
+        app.throw_status_message(TAB_USAGE.to_string());
         return;
     }
     let subcommand = args.get(0);
@@ -370,7 +419,7 @@ pub fn com_tab(app: &mut App, args: Vec<String>) {
         let ind: usize = match args[1].parse() {
             Ok(n) => n,
             Err(e) => {
-                app.throw_status_message(e.to_string());
+                app.throw_status_error(e.to_string());
                 return;
             }
         };
@@ -378,6 +427,7 @@ pub fn com_tab(app: &mut App, args: Vec<String>) {
             app.throw_status_message("Tab with specified indice not opened".to_string());
             return;
         }
+        app.push_jump();
         app.cur_tab = ind.saturating_sub(1);
         app.throw_status_message("Success".to_string());
         return;
@@ -386,7 +436,7 @@ pub fn com_tab(app: &mut App, args: Vec<String>) {
         let mut ind: usize = match args[1].parse() {
             Ok(n) => n,
             Err(e) => {
-                app.throw_status_message(e.to_string());
+                app.throw_status_error(e.to_string());
                 return;
             }
         };
@@ -395,6 +445,8 @@ pub fn com_tab(app: &mut App, args: Vec<String>) {
             app.throw_status_message("Tab with specified indice not opened".to_string());
             return;
         }
+        app.keep_global_marks(ind);
+        app.drop_swap(ind);
         app.tabs.remove(ind);
         if app.tabs.len() == 0 {
             let newtab = Tab::new(None);
@@ -428,7 +480,7 @@ pub fn com_tab(app: &mut App, args: Vec<String>) {
         let ind: usize = match args[1].parse() {
             Ok(n) => n,
             Err(e) => {
-                app.throw_status_message(e.to_string());
+                app.throw_status_error(e.to_string());
                 return;
             }
         };
@@ -441,14 +493,43 @@ pub fn com_tab(app: &mut App, args: Vec<String>) {
         app.throw_status_message("Success".to_string());
         return;
     }
-    if subcommand == Some(&"left".to_string()) {
-        app.left_area_open = !app.left_area_open;
-        app.throw_status_message("success".to_owned());
+    if subcommand == Some(&"wrap".to_string()) {
+        // !tab wrap toggles, !tab wrap on/off sets
+        let wraps = app.tab_wraps(&app.tabs[app.cur_tab]);
+        let new_mode = match args.get(1).map(|s| s.as_str()) {
+            None => !wraps,
+            Some("on") => true,
+            Some("off") => false,
+            Some(other) => {
+                app.throw_status_message(format!("Expected on or off, got {}", other));
+                return;
+            }
+        };
+        app.tabs[app.cur_tab].wrap = Some(new_mode);
+        app.scroll_to_cursor();
+        app.throw_status_message(match new_mode {
+            true => "Wrap on".to_string(),
+            false => "Wrap off".to_string(),
+        });
         return;
     }
-    if subcommand == Some(&"leftuse".to_string()) {
-        app.left_area_used = !app.left_area_used;
-        app.throw_status_message("success".to_owned());
+    if subcommand == Some(&"autoreload".to_string()) {
+        // !tab autoreload toggles, !tab autoreload on/off sets
+        let reloads = app.tab_autoreloads(&app.tabs[app.cur_tab]);
+        let new_mode = match args.get(1).map(|s| s.as_str()) {
+            None => !reloads,
+            Some("on") => true,
+            Some("off") => false,
+            Some(other) => {
+                app.throw_status_message(format!("Expected on or off, got {}", other));
+                return;
+            }
+        };
+        app.tabs[app.cur_tab].autoreload = Some(new_mode);
+        app.throw_status_message(match new_mode {
+            true => "Autoreload on".to_string(),
+            false => "Autoreload off".to_string(),
+        });
         return;
     }
     if subcommand == Some(&"showdiffn".to_string()) {
@@ -456,11 +537,11 @@ pub fn com_tab(app: &mut App, args: Vec<String>) {
         let cur_tab = match cur_tab_opt {
             Some(tab) => tab,
             None => {
-                app.throw_status_message("E: No tab opened!".to_string());
+                app.throw_status_error("E: No tab opened!".to_string());
                 return;
             }
         };
-        match cur_tab.edit_hist.last_mut() {
+        match cur_tab.edit_hist.last_edit() {
             Some(e) => {
                 let res_tab = e.dbg_show_edit();
                 app.tabs.push(res_tab);
@@ -474,18 +555,532 @@ pub fn com_tab(app: &mut App, args: Vec<String>) {
             }
         }
     }
-    app.throw_status_message(
-        "Usage: !tab new, !tab goto num, !tab rm num, !tab next, !tab prev, !tab rename num name"
-            .to_string(),
-    );
+    app.throw_status_message(TAB_USAGE.to_string());
     return;
 }
 
+pub fn com_undo(app: &mut App, args: Vec<String>) {
+    let count: usize = match args.first() {
+        Some(n) => match n.parse() {
+            Ok(c) => c,
+            Err(e) => {
+                app.throw_status_error(e.to_string());
+                return;
+            }
+        },
+        None => 1,
+    };
+    let curtab = &mut app.tabs[app.cur_tab];
+    let mut done: usize = 0;
+    while done < count && curtab.undo() {
+        done += 1;
+    }
+    match done {
+        0 => app.throw_status_message("Already at oldest change".to_string()),
+        n => app.throw_status_message(format!("{} change(s) undone", n)),
+    }
+}
+
+pub fn com_redo(app: &mut App, args: Vec<String>) {
+    let count: usize = match args.first() {
+        Some(n) => match n.parse() {
+            Ok(c) => c,
+            Err(e) => {
+                app.throw_status_error(e.to_string());
+                return;
+            }
+        },
+        None => 1,
+    };
+    let curtab = &mut app.tabs[app.cur_tab];
+    let mut done: usize = 0;
+    while done < count && curtab.redo() {
+        done += 1;
+    }
+    match done {
+        0 => app.throw_status_message("Already at newest change".to_string()),
+        n => app.throw_status_message(format!("{} change(s) redone", n)),
+    }
+}
+
 pub fn com_version(app: &mut App, args: Vec<String>) {
     app.throw_status_message(app.version.clone());
     return;
 }
 
+pub fn com_registers(app: &mut App, args: Vec<String>) {
+    let filename = app.tabs[app.cur_tab].filename.clone();
+    let mut lines: Vec<String> = vec!["Reg  Type  Content".to_string()];
+    for (reg, content) in app.registers.list(&filename) {
+        // only names given as args, if any
+        if !args.is_empty() && !args.iter().any(|a| a.contains(reg)) {
+            continue;
+        }
+        let kind = if content.linewise { "line" } else { "char" };
+        let text = content.text.replace('\n', "^J").replace('\t', "^I");
+        lines.push(format!("\"{}   {}  {}", reg, kind, text));
+    }
+    let mut reg_tab: Tab = Tab::new(Some("Registers".to_string()));
+    reg_tab.buf = TextBuffer::from_lines(&lines);
+    app.tabs.push(reg_tab);
+    app.cur_tab = app.tabs.len().saturating_sub(1);
+    app.throw_status_message("Registers displayed in new tab".to_string());
+}
+
+pub fn com_marks(app: &mut App, args: Vec<String>) {
+    let mut lines: Vec<String> = vec!["Mark  Line  Col  Text/File".to_string()];
+    for (name, line, x, text) in app.list_marks() {
+        // only names given as args, if any
+        if !args.is_empty() && !args.iter().any(|a| a.contains(name)) {
+            continue;
+        }
+        lines.push(format!(
+            "{:<5} {:<5} {:<4} {}",
+            name,
+            line + 1,
+            x,
+            text.trim()
+        ));
+    }
+    let mut marks_tab: Tab = Tab::new(Some("Marks".to_string()));
+    marks_tab.buf = TextBuffer::from_lines(&lines);
+    app.tabs.push(marks_tab);
+    app.cur_tab = app.tabs.len().saturating_sub(1);
+    app.throw_status_message("Marks displayed in new tab".to_string());
+}
+
+// Pipes lines of range through a shell command, replacing them with its output.
+// Without range uses last visual selection.
+pub fn com_filter(app: &mut App, range: Option<LineRange>, args: Vec<String>) {
+    if args.is_empty() {
+        app.throw_status_message("Usage: !filter command".to_string());
+        return;
+    }
+    let tab = &app.tabs[app.cur_tab];
+    let (first, last) = match range {
+        Some(r) => (r.start, r.end),
+        None => match (tab.marks.get(&'<'), tab.marks.get(&'>')) {
+            (Some(s), Some(e)) => (s.1, e.1.min(tab.buf.last_line())),
+            _ => {
+                app.throw_status_message("No visual selection to filter".to_string());
+                return;
+            }
+        },
+    };
+    if first > last {
+        app.throw_status_message("No visual selection to filter".to_string());
+        return;
+    }
+    let last_len = tab.buf.line_len(last);
+    let input = tab.buf.slice(first, 0, last, last_len) + "\n";
+
+    match pipe_through_shell(app.config.shell_command(&args.join(" ")), input) {
+        Ok(out) => {
+            replace_lines(app, first, last, &out);
+            app.throw_status_message(format!("{} line(s) filtered", last - first + 1));
+        }
+        Err(e) => app.throw_status_error(format!("Filter failed: {}", e)),
+    }
+}
+
+// Runs command with input on stdin, gives its stdout without trailing newline
+fn pipe_through_shell(mut com: Command, input: String) -> Result<String, String> {
+    let child = com
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = child.map_err(|e| e.to_string())?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // writing from another thread, so big outputs can't deadlock us
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr).to_string();
+        return Err(err.trim().to_string());
+    }
+    let mut out = String::from_utf8_lossy(&output.stdout).to_string();
+    if out.ends_with('\n') {
+        out.pop();
+    }
+    Ok(out)
+}
+
+// Replaces lines first..=last of current tab with text as one undoable change
+fn replace_lines(app: &mut App, first: usize, last: usize, text: &str) {
+    let tab = &mut app.tabs[app.cur_tab];
+    let last_len = tab.buf.line_len(last);
+    tab.edit_hist.seal();
+    tab.remove_text(first, 0, last, last_len, true);
+    tab.insert_text(first, 0, text, true);
+    tab.edit_hist.seal();
+    tab.cursor_xy = (0, first);
+}
+
+// !s/pattern/replacement/flags, see substitute.rs
+pub fn com_s(app: &mut App, range: Option<LineRange>, args: Vec<String>) {
+    if args.is_empty() {
+        app.throw_status_message("Usage: !s/pattern/replacement/flags".to_string());
+        return;
+    }
+    app.substitute(range, &args.join(" "));
+}
+
+// !d [reg] and !y [reg] on range of lines, current line by default: !10,20d a
+pub fn com_d(app: &mut App, range: Option<LineRange>, args: Vec<String>) {
+    lines_operator(app, Operator::Delete, range, args);
+}
+
+pub fn com_y(app: &mut App, range: Option<LineRange>, args: Vec<String>) {
+    lines_operator(app, Operator::Yank, range, args);
+}
+
+fn lines_operator(app: &mut App, op: Operator, range: Option<LineRange>, args: Vec<String>) {
+    let reg = match args.first().map(|a| a.chars().collect::<Vec<char>>()) {
+        None => None,
+        Some(chars) if chars.len() == 1 && Registers::is_valid_name(chars[0]) => Some(chars[0]),
+        Some(_) => {
+            app.throw_status_message(format!("Invalid register: {}", args[0]));
+            return;
+        }
+    };
+    let cursor = app.tabs[app.cur_tab].cursor_xy;
+    let r = range.unwrap_or(LineRange::single(cursor.1));
+    app.apply_operator(op, OpRange::lines(r.start, r.end), reg);
+    // unlike yy, yanking lines by range doesn't move cursor
+    if op == Operator::Yank {
+        app.tabs[app.cur_tab].cursor_xy = cursor;
+    }
+}
+
+pub fn com_nohl(app: &mut App, _args: Vec<String>) {
+    app.search.highlight = false;
+}
+
+// !ff crlf: line ending used on next write, no args shows current one
+pub fn com_ff(app: &mut App, args: Vec<String>) {
+    let curtab = &mut app.tabs[app.cur_tab];
+    let name = match args.first() {
+        Some(n) => n,
+        None => {
+            let msg = format!("Line ending: {}", curtab.format.ending);
+            app.throw_status_message(msg);
+            return;
+        }
+    };
+    match LineEnding::from_name(name) {
+        Some(ending) => {
+            if curtab.format.ending != ending {
+                curtab.format.ending = ending;
+                curtab.changed = true;
+            }
+            app.throw_status_message(format!("Line ending: {}", ending));
+        }
+        None => app.throw_status_message("Usage: !ff lf/crlf/cr".to_string()),
+    }
+}
+
+// !enc latin1: encoding used on next write, text in buffer stays the same
+pub fn com_enc(app: &mut App, args: Vec<String>) {
+    let curtab = &mut app.tabs[app.cur_tab];
+    let name = match args.first() {
+        Some(n) => n,
+        None => {
+            let msg = format!("Encoding: {}", curtab.format.encoding_name());
+            app.throw_status_message(msg);
+            return;
+        }
+    };
+    match fileformat::encoding_from_name(name) {
+        Some((encoding, bom)) => {
+            if (curtab.format.encoding, curtab.format.bom) != (encoding, bom) {
+                curtab.format.encoding = encoding;
+                curtab.format.bom = bom;
+                curtab.changed = true;
+            }
+            let msg = format!("Encoding: {}", curtab.format.encoding_name());
+            app.throw_status_message(msg);
+        }
+        None => app.throw_status_message(format!(
+            "Usage: !enc {}",
+            fileformat::encoding_names().join("/")
+        )),
+    }
+}
+
+// !eol off: no line break after the last line
+pub fn com_eol(app: &mut App, args: Vec<String>) {
+    let curtab = &mut app.tabs[app.cur_tab];
+    let final_newline = match args.first().map(|s| s.as_str()) {
+        None => !curtab.format.final_newline,
+        Some("on") => true,
+        Some("off") => false,
+        Some(_) => {
+            app.throw_status_message("Usage: !eol [on/off]".to_string());
+            return;
+        }
+    };
+    if curtab.format.final_newline != final_newline {
+        curtab.format.final_newline = final_newline;
+        curtab.changed = true;
+    }
+    app.throw_status_message(match final_newline {
+        true => "Final newline on".to_string(),
+        false => "Final newline off".to_string(),
+    });
+}
+
+// !filetype shows it, !filetype rust sets it for current tab, !filetype auto detects it again
+pub fn com_filetype(app: &mut App, args: Vec<String>) {
+    let cur_tab = app.cur_tab;
+    match args.first().map(|s| s.as_str()) {
+        None => {}
+        Some("auto") => app.tabs[cur_tab].hl.set_filetype(None),
+        Some(name) => {
+            if app.find_syntax(name).is_none() {
+                let names: Vec<&str> = app.syntaxes.iter().map(|s| s.name.as_str()).collect();
+                app.throw_status_message(format!(
+                    "No syntax {}; there are: {}",
+                    name,
+                    names.join(", ")
+                ));
+                return;
+            }
+            app.tabs[cur_tab].hl.set_filetype(Some(name.to_string()));
+        }
+    }
+    // detection runs before next draw
+    app.update_syntax();
+    let hl = &app.tabs[cur_tab].hl;
+    let msg = match (&hl.filetype, hl.manual) {
+        (Some(ft), true) => format!("filetype={} (set)", ft),
+        (Some(ft), false) => format!("filetype={}", ft),
+        (None, _) => "No filetype".to_string(),
+    };
+    app.throw_status_message(msg);
+}
+
+// !theme name switches theme, no args shows the current one and the others
+pub fn com_theme(app: &mut App, args: Vec<String>) {
+    match args.first() {
+        Some(name) => match app.set_theme(name) {
+            Ok(()) => app.throw_status_message(format!("Theme {}", name)),
+            Err(e) => app.throw_status_error(e),
+        },
+        None => {
+            let dir = app.themes_dir();
+            let names = theme::theme_names(Some(dir.as_path()));
+            let msg = format!("Theme {}; there are: {}", app.theme.name, names.join(", "));
+            app.throw_status_message(msg);
+        }
+    }
+}
+
+// !win split/vsplit [tab], close, only, focus h/j/k/l/next/prev/N, height/width [+-]N, equal
+pub fn com_win(app: &mut App, args: Vec<String>) {
+    let usage = "Usage: !win split [tab], !win vsplit [tab], !win close, !win only, !win focus h/j/k/l/next/prev/num, !win height [+-]num, !win width [+-]num, !win equal";
+    let arg = args.get(1).map(|s| s.as_str());
+    let res = match (args.first().map(|s| s.as_str()), arg) {
+        (None, _) => {
+            let ids = app.windows.layout.ids();
+            let pos = ids
+                .iter()
+                .position(|w| *w == app.windows.focus)
+                .unwrap_or(0);
+            app.throw_status_message(format!("Window {} of {}", pos + 1, ids.len()));
+            return;
+        }
+        (Some(cmd @ ("split" | "vsplit")), tab) => {
+            let dir = match cmd {
+                "split" => SplitDir::Rows,
+                _ => SplitDir::Cols,
+            };
+            match tab.map(|t| t.parse::<usize>()) {
+                None => app.split_window(dir, None),
+                Some(Ok(n)) if n >= 1 => app.split_window(dir, Some(n - 1)),
+                Some(_) => {
+                    app.throw_status_message(usage.to_string());
+                    return;
+                }
+            }
+        }
+        (Some("close"), _) => app.close_window(),
+        (Some("only"), _) => {
+            app.only_window();
+            Ok(())
+        }
+        (Some("focus"), Some(to)) => {
+            match (to, to.chars().next().and_then(FocusDir::from_char)) {
+                ("next" | "prev", _) => app.focus_next_window(None, to == "prev"),
+                (_, Some(dir)) if to.len() == 1 => app.focus_window_dir(dir, 1),
+                _ => match to.parse::<usize>() {
+                    Ok(n) if n >= 1 => app.focus_next_window(Some(n), false),
+                    _ => {
+                        app.throw_status_message(usage.to_string());
+                        return;
+                    }
+                },
+            }
+            Ok(())
+        }
+        (Some(cmd @ ("height" | "width")), Some(size)) => {
+            let dir = match cmd {
+                "height" => SplitDir::Rows,
+                _ => SplitDir::Cols,
+            };
+            match (size.parse::<isize>(), size.starts_with(['+', '-'])) {
+                (Ok(delta), true) => app.resize_window(dir, delta),
+                (Ok(n), false) if n >= 0 => app.set_window_size(dir, n as usize),
+                _ => {
+                    app.throw_status_message(usage.to_string());
+                    return;
+                }
+            }
+            Ok(())
+        }
+        (Some("equal"), _) => {
+            app.equalize_windows();
+            Ok(())
+        }
+        _ => {
+            app.throw_status_message(usage.to_string());
+            return;
+        }
+    };
+    if let Err(e) = res {
+        app.throw_status_error(e);
+    }
+}
+
+// !recover: asks about swap of a crashed frevi again; !recover list, !recover N
+pub fn com_recover(app: &mut App, args: Vec<String>) {
+    let res = match args.first().map(|s| s.as_str()) {
+        None => app.ask_recover_again(),
+        Some("list") => {
+            app.list_swaps();
+            Ok(())
+        }
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) => app.open_swap(n),
+            Err(_) => Err("Usage: !recover, !recover list, !recover N".to_string()),
+        },
+    };
+    if let Err(e) = res {
+        app.throw_status_error(e);
+    }
+}
+
+// !session save/load/loadi [name], !session list
+pub fn com_session(app: &mut App, args: Vec<String>) {
+    let name = args
+        .get(1)
+        .cloned()
+        .unwrap_or(session::DEFAULT_SESSION.to_string());
+    let res = match args.first().map(|s| s.as_str()) {
+        Some("save") => app
+            .save_session(&name)
+            .map(|_| format!("Session {} saved", name)),
+        Some("load") if app.tabs.iter().any(|t| t.changed) => {
+            let msg = "W: Some tabs have unsaved changes; !session loadi to ignore";
+            app.throw_status_warning(msg.to_string());
+            return;
+        }
+        Some("load") | Some("loadi") => app.load_session(&name).map(|_| String::new()),
+        Some("list") => match app.session_names() {
+            names if names.is_empty() => Ok("No sessions saved".to_string()),
+            names => Ok(names.join(", ")),
+        },
+        _ => Ok("Usage: !session save [name], !session load [name], !session list".to_string()),
+    };
+    match res {
+        Ok(msg) if msg.is_empty() => {}
+        Ok(msg) => app.throw_status_message(msg),
+        Err(e) => app.throw_status_error(e),
+    }
+}
+
+// !set tabstop=8 wrap: sets or shows options, no args lists all of them
+pub fn com_set(app: &mut App, args: Vec<String>) {
+    set_options(app, args, false);
+}
+
+// !setl: same for current tab only, `name<` makes it follow !set again
+pub fn com_setl(app: &mut App, args: Vec<String>) {
+    set_options(app, args, true);
+}
+
+fn set_options(app: &mut App, args: Vec<String>, local: bool) {
+    if args.is_empty() {
+        app.list_options();
+        return;
+    }
+    let mut res: Vec<String> = Vec::new();
+    for arg in args.iter() {
+        match app.set_option_arg(arg, local) {
+            Ok(msg) => res.push(msg),
+            Err(e) => {
+                app.throw_status_error(e);
+                return;
+            }
+        }
+    }
+    app.throw_status_message(res.join(", "));
+}
+
+// !map mode keys target; with mode and keys only shows the target
+pub fn com_map(app: &mut App, args: Vec<String>) {
+    match args.len() {
+        0 => com_maps(app, args),
+        1 => app.throw_status_message("Usage: !map mode keys target".to_string()),
+        2 => match app.mapped_to(&args[0], &args[1]) {
+            Ok(target) => app.throw_status_message(format!("{} {} {}", args[0], args[1], target)),
+            Err(e) => app.throw_status_error(e),
+        },
+        _ => {
+            let res = MapTarget::parse(&args[2..].join(" "))
+                .and_then(|target| app.add_map(&args[0], &args[1], target));
+            match res {
+                Ok(_) => app.throw_status_message("Success".to_string()),
+                Err(e) => app.throw_status_error(e),
+            }
+        }
+    }
+}
+
+pub fn com_unmap(app: &mut App, args: Vec<String>) {
+    if args.len() != 2 {
+        app.throw_status_message("Usage: !unmap mode keys".to_string());
+        return;
+    }
+    match app.remove_map(&args[0], &args[1]) {
+        Ok(_) => app.throw_status_message("Success".to_string()),
+        Err(e) => app.throw_status_error(e),
+    }
+}
+
+pub fn com_maps(app: &mut App, args: Vec<String>) {
+    let only = match args.first() {
+        Some(name) => match MapMode::from_name(name) {
+            Some(mode) => Some(mode),
+            None => {
+                app.throw_status_message(format!("Unknown mode {}", name));
+                return;
+            }
+        },
+        None => None,
+    };
+    let mut lines: Vec<String> = vec![format!("{:<8} {:<12} {}", "Mode", "Keys", "Target")];
+    lines.extend(app.map_lines(only));
+    let mut maps_tab: Tab = Tab::new(Some("Maps".to_string()));
+    maps_tab.buf = TextBuffer::from_lines(&lines);
+    app.tabs.push(maps_tab);
+    app.cur_tab = app.tabs.len().saturating_sub(1);
+    app.throw_status_message("Maps displayed in new tab".to_string());
+}
+
 pub fn com_alias(app: &mut App, args: Vec<String>) {
     if args.is_empty() {
         app.throw_status_message("Usage: !alias new / !alias rm".to_string());
@@ -569,7 +1164,7 @@ pub fn com_plugin(app: &mut App, args: Vec<String>) {
                 Ok(_) => {}
                 Err(e) => {
                     let _ = app.logger.log_msg(LogLevel::PluginFault, e);
-                    app.throw_status_message("Plugin load returned error. Check logs".to_string());
+                    app.throw_status_error("Plugin load returned error. Check logs".to_string());
                     return;
                 }
             }
@@ -688,7 +1283,7 @@ pub fn com_plugin(app: &mut App, args: Vec<String>) {
                 list_buf.push(curplug_info);
             }
             let mut list_tab: Tab = Tab::new(Some("Plugin list".to_string()));
-            list_tab.buf = list_buf;
+            list_tab.buf = TextBuffer::from_lines(&list_buf);
             app.tabs.push(list_tab);
             app.cur_tab = app.tabs.len().saturating_sub(1);
             app.throw_status_message("Plugin list displayed in new tab".to_string());
@@ -0,0 +1,148 @@
+// Line diff (Myers) shown as unified diff, for comparing a tab with its swap
+// file or with the file changed on disk.
+
+const CONTEXT: usize = 3;
+// Edit distance after which the rest is shown as replaced whole, keeps memory in check
+const MAX_D: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Same,
+    Del,
+    Add,
+}
+
+// Ops turning old into new, common prefix and suffix are cut off first
+fn diff_ops(old: &[String], new: &[String]) -> Vec<Op> {
+    let pre = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suf = old[pre..]
+        .iter()
+        .rev()
+        .zip(new[pre..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (&old[pre..old.len() - suf], &new[pre..new.len() - suf]);
+    let mut ops = vec![Op::Same; pre];
+    ops.extend(myers(a, b));
+    ops.extend(vec![Op::Same; suf]);
+    ops
+}
+
+fn myers(a: &[String], b: &[String]) -> Vec<Op> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let off = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // v of every round, only the diagonals it could reach
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut found = false;
+    for d in 0..=max.min(MAX_D) as isize {
+        trace.push(v[(off - d - 1) as usize..=(off + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = match k == -d
+                || (k != d && v[(off + k - 1) as usize] < v[(off + k + 1) as usize])
+            {
+                true => v[(off + k + 1) as usize],
+                false => v[(off + k - 1) as usize] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(off + k) as usize] = x;
+            if x >= n && y >= m {
+                found = true;
+                break;
+            }
+        }
+        if found {
+            break;
+        }
+    }
+    if !found {
+        let mut ops = vec![Op::Del; a.len()];
+        ops.extend(vec![Op::Add; b.len()]);
+        return ops;
+    }
+    // walk back from the end through saved rounds
+    let mut ops: Vec<Op> = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = match k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(Op::Same);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            match x == prev_x {
+                true => ops.push(Op::Add),
+                false => ops.push(Op::Del),
+            }
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    ops.reverse();
+    ops
+}
+
+// Unified diff of two texts split into lines, empty if they're the same
+pub fn unified(old: &[String], new: &[String], old_name: &str, new_name: &str) -> String {
+    let ops = diff_ops(old, new);
+    if ops.iter().all(|op| *op == Op::Same) {
+        return String::new();
+    }
+    // (old line, new line) before every op
+    let mut pos: Vec<(usize, usize)> = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for op in ops.iter() {
+        pos.push((i, j));
+        match op {
+            Op::Same => (i, j) = (i + 1, j + 1),
+            Op::Del => i += 1,
+            Op::Add => j += 1,
+        }
+    }
+    pos.push((i, j));
+
+    let mut res = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let changed: Vec<usize> = (0..ops.len()).filter(|&o| ops[o] != Op::Same).collect();
+    let mut h = 0;
+    while h < changed.len() {
+        // hunk takes changes closer than two contexts to each other
+        let mut last = h;
+        while last + 1 < changed.len() && changed[last + 1] - changed[last] <= 2 * CONTEXT + 1 {
+            last += 1;
+        }
+        let from = changed[h].saturating_sub(CONTEXT);
+        let to = (changed[last] + CONTEXT + 1).min(ops.len());
+        let (old_from, new_from) = pos[from];
+        let (old_to, new_to) = pos[to];
+        res.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_from + 1,
+            old_to - old_from,
+            new_from + 1,
+            new_to - new_from
+        ));
+        for o in from..to {
+            let (i, j) = pos[o];
+            match ops[o] {
+                Op::Same => res.push_str(&format!(" {}\n", old[i])),
+                Op::Del => res.push_str(&format!("-{}\n", old[i])),
+                Op::Add => res.push_str(&format!("+{}\n", new[j])),
+            }
+        }
+        h = last + 1;
+    }
+    res
+}
@@ -0,0 +1,361 @@
+use std::{
+    collections::HashSet,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::SystemTime,
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::app::App;
+use crate::buffer::TextBuffer;
+use crate::diff;
+use crate::fileformat::{self, FileFormat};
+use crate::prompt::Prompt;
+use crate::tabs::Tab;
+
+// Tabs remember how their file looked on disk when it was read or written.
+// When it changes behind frevi's back (noticed by a watcher on its dir, on
+// terminal focus-in and before !w) the tab asks whether to reload it.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiskStamp {
+    mtime: Option<SystemTime>,
+    size: u64,
+    hash: u64,
+}
+
+impl DiskStamp {
+    fn new(bytes: &[u8], meta: &fs::Metadata) -> DiskStamp {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        DiskStamp {
+            mtime: meta.modified().ok(),
+            size: bytes.len() as u64,
+            hash: hasher.finish(),
+        }
+    }
+
+    // Stamp of bytes just written to path
+    pub fn written(path: &str, bytes: &[u8]) -> Option<DiskStamp> {
+        let meta = fs::metadata(path).ok()?;
+        Some(DiskStamp::new(bytes, &meta))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiskInfo {
+    pub stamp: Option<DiskStamp>, // None if there was no file
+    asking: bool,                 // prompt about it is in the queue
+}
+
+impl DiskInfo {
+    pub fn new(stamp: Option<DiskStamp>) -> DiskInfo {
+        DiskInfo {
+            stamp,
+            asking: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiskChange {
+    Same,
+    Touched(DiskStamp), // mtime changed, contents didn't
+    Modified(DiskStamp),
+    Deleted,
+}
+
+// Reads file into buffer, with its format and stamp
+pub fn read_file(path: &str) -> io::Result<(TextBuffer, FileFormat, DiskStamp)> {
+    let bytes = fs::read(path)?;
+    let meta = fs::metadata(path)?;
+    let (buf, format) = fileformat::from_bytes(&bytes);
+    Ok((buf, format, DiskStamp::new(&bytes, &meta)))
+}
+
+// Size and mtime are looked at first, file is read only when they differ
+fn disk_change(path: &str, stamp: Option<&DiskStamp>) -> DiskChange {
+    let meta = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(_) => {
+            return match stamp {
+                Some(_) => DiskChange::Deleted,
+                None => DiskChange::Same,
+            };
+        }
+    };
+    if let Some(stamp) = stamp
+        && stamp.size == meta.len()
+        && stamp.mtime.is_some()
+        && stamp.mtime == meta.modified().ok()
+    {
+        return DiskChange::Same;
+    }
+    let Ok(bytes) = fs::read(path) else {
+        return DiskChange::Same;
+    };
+    let new = DiskStamp::new(&bytes, &meta);
+    match stamp {
+        Some(old) if old.hash == new.hash && old.size == new.size => DiskChange::Touched(new),
+        _ => DiskChange::Modified(new),
+    }
+}
+
+// Watches dirs of open files. Dirs and not files, since atomic saves
+// (ours and other tools') replace the file and a watch on it would be lost.
+#[derive(Debug)]
+pub struct DiskWatcher {
+    watcher: Option<RecommendedWatcher>,
+    rx: Receiver<notify::Result<notify::Event>>,
+    dirs: HashSet<PathBuf>,
+    files: Vec<String>, // absolute names of tabs' files dirs were last set for
+}
+
+impl DiskWatcher {
+    pub fn new() -> DiskWatcher {
+        let (tx, rx) = mpsc::channel();
+        DiskWatcher {
+            watcher: notify::recommended_watcher(tx).ok(),
+            rx,
+            dirs: HashSet::new(),
+            files: Vec::new(),
+        }
+    }
+
+    fn set_dirs(&mut self, dirs: HashSet<PathBuf>) {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return;
+        };
+        if dirs == self.dirs {
+            return;
+        }
+        for dir in self.dirs.difference(&dirs) {
+            let _ = watcher.unwatch(dir);
+        }
+        for dir in dirs.difference(&self.dirs) {
+            let _ = watcher.watch(dir, RecursiveMode::NonRecursive);
+        }
+        self.dirs = dirs;
+    }
+
+    // Paths touched since last call
+    fn changed_paths(&self) -> HashSet<PathBuf> {
+        let mut res: HashSet<PathBuf> = HashSet::new();
+        while let Ok(event) = self.rx.try_recv() {
+            if let Ok(event) = event
+                && !event.kind.is_access()
+            {
+                res.extend(event.paths);
+            }
+        }
+        res
+    }
+}
+
+impl App {
+    pub fn tab_autoreloads(&self, tab: &Tab) -> bool {
+        tab.autoreload.unwrap_or(self.config.autoreload)
+    }
+
+    // Main loop: keeps watcher on dirs of open files and checks the ones it saw changing
+    pub fn update_disk_watch(&mut self) {
+        let files: Vec<String> = self.tabs.iter_mut().map(|t| t.abs_filename()).collect();
+        if files != self.disk_watcher.files {
+            let dirs: HashSet<PathBuf> = files
+                .iter()
+                .filter(|f| !f.is_empty())
+                .filter_map(|f| Path::new(f).parent().map(|d| d.to_path_buf()))
+                .collect();
+            self.disk_watcher.set_dirs(dirs);
+            self.disk_watcher.files = files.clone();
+        }
+        let changed = self.disk_watcher.changed_paths();
+        if changed.is_empty() {
+            return;
+        }
+        for (idx, file) in files.iter().enumerate() {
+            if !file.is_empty() && changed.contains(Path::new(file)) {
+                self.check_disk(idx);
+            }
+        }
+    }
+
+    // Terminal got focus back: files could change while we were away
+    pub fn check_disk_all(&mut self) {
+        for idx in 0..self.tabs.len() {
+            self.check_disk(idx);
+        }
+    }
+
+    pub fn check_disk(&mut self, idx: usize) {
+        let tab: &Tab = &self.tabs[idx];
+        if tab.filename.is_empty() || tab.disk.asking {
+            return;
+        }
+        match disk_change(&tab.filename, tab.disk.stamp.as_ref()) {
+            DiskChange::Same => {}
+            DiskChange::Touched(stamp) => self.tabs[idx].disk.stamp = Some(stamp),
+            DiskChange::Deleted => {
+                self.tabs[idx].disk.stamp = None;
+                let msg = format!("W: {} was deleted on disk", self.tabs[idx].filename);
+                self.throw_status_warning(msg);
+            }
+            DiskChange::Modified(_) if !tab.changed && self.tab_autoreloads(tab) => {
+                match self.reload_tab(idx) {
+                    Ok(_) => {
+                        let msg = format!("{} changed on disk, reloaded", self.tabs[idx].filename);
+                        self.throw_status_message(msg);
+                    }
+                    Err(e) => self.throw_status_error(e.to_string()),
+                }
+            }
+            DiskChange::Modified(_) => self.ask_disk(idx, false),
+        }
+    }
+
+    // Before !w into tab's own file: false if it changed on disk, user is asked then
+    pub fn disk_unchanged_for_write(&mut self, idx: usize) -> bool {
+        let tab: &Tab = &self.tabs[idx];
+        match disk_change(&tab.filename, tab.disk.stamp.as_ref()) {
+            DiskChange::Modified(_) => {
+                self.ask_disk(idx, true);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    // File is as the tab last saw it, or only touched
+    pub fn disk_unchanged(&self, idx: usize) -> bool {
+        let tab: &Tab = &self.tabs[idx];
+        matches!(
+            disk_change(&tab.filename, tab.disk.stamp.as_ref()),
+            DiskChange::Same | DiskChange::Touched(_)
+        )
+    }
+
+    fn ask_disk(&mut self, idx: usize, writing: bool) {
+        let tab: &mut Tab = &mut self.tabs[idx];
+        tab.disk.asking = true;
+        let name = tab.filename.clone();
+        let prompt = match writing {
+            true => Prompt {
+                text: format!(
+                    "{} changed on disk since it was read: [w]rite anyway, [r]eload, [d]iff, Esc cancel",
+                    name
+                ),
+                choices: vec![('w', write_anyway), ('r', reload), ('d', diff_disk)],
+                on_esc: Some(keep_asking_later),
+                tab_id: tab.id,
+            },
+            false => Prompt {
+                text: format!("{} changed on disk: [r]eload, [k]eep yours, [d]iff", name),
+                choices: vec![('r', reload), ('k', keep), ('d', diff_disk)],
+                on_esc: Some(keep_asking_later),
+                tab_id: tab.id,
+            },
+        };
+        self.ask(prompt);
+    }
+
+    // Reads tab's file again as one undoable change
+    pub fn reload_tab(&mut self, idx: usize) -> io::Result<()> {
+        let tab: &mut Tab = &mut self.tabs[idx];
+        let (buf, format, stamp) = read_file(&tab.filename)?;
+        if buf.to_string() != tab.buf.to_string() {
+            tab.replace_buf(buf);
+        }
+        tab.format = format;
+        tab.disk = DiskInfo::new(Some(stamp));
+        tab.mark_saved();
+        let line = tab.cursor_xy.1.min(tab.buf.last_line());
+        let x = tab.cursor_xy.0.min(tab.buf.line_len(line));
+        tab.cursor_xy = (tab.buf.grapheme_start(line, x), line);
+        if idx == self.cur_tab {
+            self.clamp_normal_cursor();
+        }
+        Ok(())
+    }
+}
+
+fn reload(app: &mut App, tab_id: usize) {
+    let Some(idx) = app.tab_by_id(tab_id) else {
+        return;
+    };
+    match app.reload_tab(idx) {
+        Ok(_) => app.throw_status_message("Reloaded; u brings your version back".to_string()),
+        Err(e) => app.throw_status_error(e.to_string()),
+    }
+}
+
+// Disk version is taken as seen, it's asked again only if it changes once more
+fn keep(app: &mut App, tab_id: usize) {
+    let Some(idx) = app.tab_by_id(tab_id) else {
+        return;
+    };
+    let tab: &mut Tab = &mut app.tabs[idx];
+    let stamp = match disk_change(&tab.filename, tab.disk.stamp.as_ref()) {
+        DiskChange::Touched(s) | DiskChange::Modified(s) => Some(s),
+        DiskChange::Same => tab.disk.stamp,
+        DiskChange::Deleted => None,
+    };
+    tab.disk = DiskInfo::new(stamp);
+    tab.mark_unsaved();
+    app.throw_status_message("Kept your version; !w overwrites the file".to_string());
+}
+
+fn write_anyway(app: &mut App, tab_id: usize) {
+    keep(app, tab_id);
+    let Some(idx) = app.tab_by_id(tab_id) else {
+        return;
+    };
+    let cur = app.cur_tab;
+    app.cur_tab = idx;
+    crate::commands::com_w(app, None, Vec::new());
+    app.cur_tab = cur;
+}
+
+fn keep_asking_later(app: &mut App, tab_id: usize) {
+    if let Some(idx) = app.tab_by_id(tab_id) {
+        app.tabs[idx].disk.asking = false;
+    }
+    app.throw_status_message("File on disk differs; !w asks again, !ri reloads".to_string());
+}
+
+fn diff_disk(app: &mut App, tab_id: usize) {
+    let Some(idx) = app.tab_by_id(tab_id) else {
+        return;
+    };
+    app.tabs[idx].disk.asking = false;
+    let tab: &Tab = &app.tabs[idx];
+    let (disk_buf, _, _) = match read_file(&tab.filename) {
+        Ok(r) => r,
+        Err(e) => {
+            app.throw_status_error(e.to_string());
+            return;
+        }
+    };
+    let lines =
+        |buf: &TextBuffer| -> Vec<String> { (0..=buf.last_line()).map(|l| buf.line(l)).collect() };
+    let name = tab.filename.clone();
+    let res = diff::unified(
+        &lines(&tab.buf),
+        &lines(&disk_buf),
+        &name,
+        &format!("{} (disk)", name),
+    );
+    if res.is_empty() {
+        app.throw_status_message("File on disk has the same text".to_string());
+        return;
+    }
+    let mut diff_tab = Tab::new(Some(format!("diff {}", name)));
+    diff_tab.str_into_buf(res);
+    app.tabs.push(diff_tab);
+    app.push_jump();
+    app.cur_tab = app.tabs.len() - 1;
+    app.throw_status_message("!ri reloads the file, !w asks again".to_string());
+}
@@ -1,16 +1,125 @@
 use std::{
     fs::File,
     io::{BufRead, BufReader},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    process::Command,
 };
 
 use dirs;
 
+use crate::logger::LogLevel;
+use crate::save::Backup;
+use crate::wrap::LineNumbers;
+
+// Options !set knows. Tabs can override the first ones with !setl.
+pub const TAB_OPTIONS: &[&str] = &[
+    "tabstop",
+    "expandtab",
+    "numbers",
+    "wrap",
+    "autoreload",
+    "syntax",
+];
+pub const OPTIONS: &[&str] = &[
+    "tabstop",
+    "expandtab",
+    "numbers",
+    "wrap",
+    "autoreload",
+    "syntax",
+    "scrolloff",
+    "autosave",
+    "backup",
+    "autosession",
+    "theme",
+    "truecolor",
+    "log_level",
+    "maptimeout",
+    "shell.unix",
+    "shell.windows",
+    "clipboard.copy",
+    "clipboard.paste",
+];
+
+// Written when there's no config.toml yet
+const DEFAULT_CFG: &str = "# frevi options, !set changes them till exit; see commands.md
+# tabstop = 4
+# expandtab = false        # Tab key puts spaces
+# numbers = \"absolute\"     # absolute, relative, hybrid or off
+# wrap = true
+# scrolloff = 3
+# autoreload = false
+# syntax = true            # highlighting, syntaxes are built in and in ~/.frevi/syntax
+# autosave = 0             # seconds without keys before changed tabs are written, 0 is off
+# backup = \"off\"           # off, beside or a dir
+# autosession = \"\"         # session saved on quit and loaded when started without a file
+# theme = \"default\"        # default, dark or one of ~/.frevi/themes
+# truecolor = \"auto\"       # on, off or auto (COLORTERM tells); off gives themes 16 colors
+# log_level = \"info\"       # info, warning, error or critical
+# maptimeout = 1000        # ms a mapped key sequence waits for its next key
+# [shell]
+# unix = \"sh\"
+# windows = \"cmd\"
+# [clipboard]              # commands for + and * registers, OSC 52 without them
+# copy = \"wl-copy\"
+# paste = \"wl-paste -n\"
+# [keys.normal]            # also insert, visual, command; see !map in commands.md
+# \"<C-s>\" = \"<Cmd>w\"
+";
+
+pub fn parse_bool(val: &str) -> Result<bool, String> {
+    match val {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(format!("Expected on or off, got {}", val)),
+    }
+}
+
+pub fn parse_num(val: &str, min: usize, max: usize) -> Result<usize, String> {
+    match val.parse::<usize>() {
+        Ok(n) if n >= min && n <= max => Ok(n),
+        _ => Err(format!("Expected number {}..{}, got {}", min, max, val)),
+    }
+}
+
+pub fn parse_numbers(val: &str) -> Result<LineNumbers, String> {
+    LineNumbers::from_name(val).ok_or(format!(
+        "Expected absolute, relative, hybrid or off, got {}",
+        val
+    ))
+}
+
+pub fn on_off(val: bool) -> String {
+    match val {
+        true => "on".to_string(),
+        false => "off".to_string(),
+    }
+}
+
 #[derive(Debug)]
 pub struct FreviConfig {
     pub cfg_path: PathBuf,
     pub autoplugs: Vec<String>,
     defsh: (Option<String>, Option<String>), // first for windows, second for unix-like
+    pub clipboard_copy: Option<String>,      // command getting text on stdin, like wl-copy
+    pub clipboard_paste: Option<String>,     // command printing clipboard, like wl-paste -n
+    pub scrolloff: usize,                    // lines kept visible above and below cursor
+    pub tabstop: usize,                      // cells a tab char takes on screen at most
+    pub wrap: bool,                          // soft wrap long lines (tabs may override)
+    pub backup: Backup,                      // copy of old file made by !w
+    pub autoreload: bool,                    // unchanged tabs follow their file on disk
+    pub autosession: Option<String>,         // session saved on quit, loaded when no file given
+    pub expandtab: bool,                     // Tab key inserts spaces up to next tabstop
+    pub numbers: LineNumbers,                // line number style in the gutter
+    pub syntax: bool,                        // highlighting (tabs may override)
+    pub autosave: u64, // seconds without keys before changed tabs are written, 0 is off
+    pub theme: String,
+    pub truecolor: Option<bool>, // 24-bit colors for themes, None asks COLORTERM
+    pub log_level: LogLevel,
+    pub maptimeout: u64, // ms typed keys wait for the rest of a mapping
+    pub keymaps: Vec<(String, String, String)>, // (mode, keys, target) from [keys.mode] tables
+    pub errors: Vec<String>, // bad entries of config.toml, they go to the log
+    pub notices: Vec<String>, // old config files still in use, logged too
 }
 
 impl FreviConfig {
@@ -22,6 +131,25 @@ impl FreviConfig {
             autoplugs: plugsv,
             defsh: defshells,
             cfg_path: cfgpath,
+            clipboard_copy: None,
+            clipboard_paste: None,
+            scrolloff: 3,
+            tabstop: 4,
+            wrap: true,
+            backup: Backup::Off,
+            autoreload: false,
+            autosession: None,
+            expandtab: false,
+            numbers: LineNumbers::Absolute,
+            syntax: true,
+            autosave: 0,
+            theme: "default".to_string(),
+            truecolor: None,
+            log_level: LogLevel::Info,
+            maptimeout: 1000,
+            keymaps: Vec::new(),
+            errors: Vec::new(),
+            notices: Vec::new(),
         }
     }
     pub fn read_cfg(&mut self) -> Result<(), String> {
@@ -31,7 +159,12 @@ impl FreviConfig {
                 return Err("Can't get home dir!".to_string());
             }
         };
-        let cfg_dir = home_dir.join(".frevi/");
+        self.read_cfg_dir(home_dir.join(".frevi/"))
+    }
+
+    // Everything is read from (and missing files created in) cfg_dir, ~/.frevi
+    // outside of tests
+    fn read_cfg_dir(&mut self, cfg_dir: PathBuf) -> Result<(), String> {
         self.cfg_path = cfg_dir.clone();
         match cfg_dir.try_exists() {
             Ok(true) => {}
@@ -79,7 +212,445 @@ impl FreviConfig {
                 return Err(e.to_string());
             }
         }
+        let set = self.read_toml_cfg(cfg_dir.join("config.toml"));
+        self.read_old_cfgs(&cfg_dir, &set)?;
+
+        Ok(())
+    }
+
+    // Files from before config.toml, each holding one option. They're read only
+    // for options config.toml doesn't set, so moving an option there wins.
+    fn read_old_cfgs(&mut self, cfg_dir: &Path, set: &[String]) -> Result<(), String> {
+        let is_set = |name: &str| set.iter().any(|s| s == name);
+        let old: [(&str, bool); 3] = [
+            (
+                "clipboard",
+                is_set("clipboard.copy") || is_set("clipboard.paste"),
+            ),
+            ("backup", is_set("backup")),
+            ("autosession", is_set("autosession")),
+        ];
+        for (name, in_toml) in old {
+            let path = cfg_dir.join(name);
+            if in_toml || !path.exists() {
+                continue;
+            }
+            match name {
+                "clipboard" => self.read_clipboard_cfg(path)?,
+                "backup" => self.read_backup_cfg(path)?,
+                _ => self.read_autosession_cfg(path)?,
+            }
+            self.notices.push(format!(
+                "~/.frevi/{} is read since config.toml doesn't set it; move it there",
+                name
+            ));
+        }
+        Ok(())
+    }
+
+    // Optional file: first line is copy command, second one is paste command.
+    // Without it + and * registers use OSC 52 only.
+    fn read_clipboard_cfg(&mut self, path: PathBuf) -> Result<(), String> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(e.to_string());
+            }
+        };
+        let mut cmds: Vec<String> = Vec::new();
+        for line in BufReader::new(file).lines() {
+            match line {
+                Ok(l) => {
+                    if l.starts_with(';') {
+                        continue;
+                    }
+                    cmds.push(l.trim().to_string());
+                }
+                Err(e) => {
+                    return Err(e.to_string());
+                }
+            }
+        }
+        let mut cmds = cmds.into_iter().map(|c| Some(c).filter(|c| !c.is_empty()));
+        self.clipboard_copy = cmds.next().flatten();
+        self.clipboard_paste = cmds.next().flatten();
+        Ok(())
+    }
 
+    // Optional file, first line says where !w keeps the old version of a file:
+    // off (default), beside (file~) or a dir like ~/.frevi/backups
+    fn read_backup_cfg(&mut self, path: PathBuf) -> Result<(), String> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(e.to_string());
+            }
+        };
+        for line in BufReader::new(file).lines() {
+            match line {
+                Ok(l) => {
+                    if l.starts_with(';') {
+                        continue;
+                    }
+                    self.backup = Backup::from_cfg(&l);
+                    return Ok(());
+                }
+                Err(e) => {
+                    return Err(e.to_string());
+                }
+            }
+        }
         Ok(())
     }
+
+    // Optional file, first line is name of the session frevi saves on quit
+    // and restores when started without a file
+    fn read_autosession_cfg(&mut self, path: PathBuf) -> Result<(), String> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(e.to_string());
+            }
+        };
+        for line in BufReader::new(file).lines() {
+            match line {
+                Ok(l) => {
+                    if l.starts_with(';') {
+                        continue;
+                    }
+                    self.autosession = Some(l.trim().to_string()).filter(|n| !n.is_empty());
+                    return Ok(());
+                }
+                Err(e) => {
+                    return Err(e.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Main config. Entries are set one by one like with !set, the bad ones are
+    // collected in errors and the rest still apply. Returns names of options set.
+    fn read_toml_cfg(&mut self, path: PathBuf) -> Vec<String> {
+        let mut set: Vec<String> = Vec::new();
+        if !path.exists() {
+            let _ = std::fs::write(&path, DEFAULT_CFG);
+            return set;
+        }
+        let text = match std::fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(e) => {
+                self.errors.push(format!("config.toml: {}", e));
+                return set;
+            }
+        };
+        let table = match text.parse::<toml::Table>() {
+            Ok(t) => t,
+            Err(e) => {
+                self.errors.push(format!("config.toml: {}", e));
+                return set;
+            }
+        };
+        let mut table = table;
+        if let Some(keys) = table.remove("keys") {
+            self.read_keys_cfg(keys);
+        }
+        let mut entries: Vec<(String, toml::Value)> = Vec::new();
+        flat_entries(&table, "", &mut entries);
+        for (name, value) in entries {
+            let val = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Boolean(b) => on_off(b),
+                other => {
+                    self.errors.push(format!(
+                        "config.toml: {}: unsupported value {}",
+                        name,
+                        other.type_str()
+                    ));
+                    continue;
+                }
+            };
+            match self.set(&name, &val) {
+                Ok(()) => set.push(name),
+                Err(e) => self.errors.push(format!("config.toml: {}: {}", name, e)),
+            }
+        }
+        set
+    }
+
+    // [keys.normal] "<C-s>" = "<Cmd>w"; maps are checked when App applies them
+    fn read_keys_cfg(&mut self, keys: toml::Value) {
+        let toml::Value::Table(modes) = keys else {
+            self.errors
+                .push("config.toml: keys: expected tables like [keys.normal]".to_string());
+            return;
+        };
+        for (mode, maps) in modes {
+            let toml::Value::Table(maps) = maps else {
+                self.errors
+                    .push(format!("config.toml: keys.{}: expected a table", mode));
+                continue;
+            };
+            for (lhs, rhs) in maps {
+                match rhs {
+                    toml::Value::String(rhs) => self.keymaps.push((mode.clone(), lhs, rhs)),
+                    other => self.errors.push(format!(
+                        "config.toml: keys.{} {}: unsupported value {}",
+                        mode,
+                        lhs,
+                        other.type_str()
+                    )),
+                }
+            }
+        }
+    }
+
+    pub fn set(&mut self, name: &str, val: &str) -> Result<(), String> {
+        match name {
+            "tabstop" => self.tabstop = parse_num(val, 1, 32)?,
+            "expandtab" => self.expandtab = parse_bool(val)?,
+            "numbers" => self.numbers = parse_numbers(val)?,
+            "wrap" => self.wrap = parse_bool(val)?,
+            "autoreload" => self.autoreload = parse_bool(val)?,
+            "syntax" => self.syntax = parse_bool(val)?,
+            "scrolloff" => self.scrolloff = parse_num(val, 0, 999)?,
+            "autosave" => self.autosave = parse_num(val, 0, 86400)? as u64,
+            "backup" => self.backup = Backup::from_cfg(val),
+            "autosession" => {
+                self.autosession = Some(val.trim().to_string()).filter(|n| !n.is_empty())
+            }
+            "theme" => self.theme = val.to_string(),
+            "truecolor" => {
+                self.truecolor = match val {
+                    "auto" => None,
+                    v => Some(
+                        parse_bool(v)
+                            .map_err(|_| format!("Expected on, off or auto, got {}", v))?,
+                    ),
+                }
+            }
+            "log_level" => {
+                self.log_level = LogLevel::from_name(val).ok_or(format!(
+                    "Expected info, warning, error or critical, got {}",
+                    val
+                ))?
+            }
+            "maptimeout" => self.maptimeout = parse_num(val, 0, 10000)? as u64,
+            "shell.unix" => self.defsh.1 = Some(val.to_string()).filter(|s| !s.is_empty()),
+            "shell.windows" => self.defsh.0 = Some(val.to_string()).filter(|s| !s.is_empty()),
+            "clipboard.copy" => {
+                self.clipboard_copy = Some(val.trim().to_string()).filter(|c| !c.is_empty())
+            }
+            "clipboard.paste" => {
+                self.clipboard_paste = Some(val.trim().to_string()).filter(|c| !c.is_empty())
+            }
+            _ => return Err(format!("Unknown option {}", name)),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "tabstop" => self.tabstop.to_string(),
+            "expandtab" => on_off(self.expandtab),
+            "numbers" => self.numbers.name().to_string(),
+            "wrap" => on_off(self.wrap),
+            "autoreload" => on_off(self.autoreload),
+            "syntax" => on_off(self.syntax),
+            "scrolloff" => self.scrolloff.to_string(),
+            "autosave" => self.autosave.to_string(),
+            "backup" => self.backup.to_cfg(),
+            "autosession" => self.autosession.clone().unwrap_or_default(),
+            "theme" => self.theme.clone(),
+            "truecolor" => self.truecolor.map(on_off).unwrap_or("auto".to_string()),
+            "log_level" => self.log_level.name().to_string(),
+            "maptimeout" => self.maptimeout.to_string(),
+            "shell.unix" => self.defsh.1.clone().unwrap_or("sh".to_string()),
+            "shell.windows" => self.defsh.0.clone().unwrap_or("cmd".to_string()),
+            "clipboard.copy" => self.clipboard_copy.clone().unwrap_or_default(),
+            "clipboard.paste" => self.clipboard_paste.clone().unwrap_or_default(),
+            _ => return None,
+        })
+    }
+
+    // Shell ! commands run in, for this OS
+    pub fn shell(&self) -> String {
+        match cfg!(target_os = "windows") {
+            true => self.get("shell.windows"),
+            false => self.get("shell.unix"),
+        }
+        .unwrap_or_default()
+    }
+
+    // Command running argline in the shell: cmd takes it after /C, powershell after -Command
+    pub fn shell_command(&self, argline: &str) -> Command {
+        let shell = self.shell();
+        let name = Path::new(&shell)
+            .file_stem()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let flag = match name.as_str() {
+            "cmd" => "/C",
+            "powershell" | "pwsh" => "-Command",
+            _ => "-c",
+        };
+        let mut com = Command::new(shell);
+        com.args([flag, argline]);
+        com
+    }
+}
+
+// [shell] unix = "bash" becomes ("shell.unix", "bash")
+fn flat_entries(table: &toml::Table, prefix: &str, res: &mut Vec<(String, toml::Value)>) {
+    for (key, value) in table.iter() {
+        let name = format!("{}{}", prefix, key);
+        match value {
+            toml::Value::Table(t) => flat_entries(t, &format!("{}.", name), res),
+            v => res.push((name, v.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Empty dir of its own for every test, standing in for ~/.frevi
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("frevi_cfg_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn set_and_get() {
+        let mut cfg = FreviConfig::new();
+        // every option takes back the value it shows
+        for name in OPTIONS {
+            let val = cfg.get(name).unwrap();
+            assert_eq!(cfg.set(name, &val), Ok(()), "{}", name);
+        }
+        assert_eq!(cfg.set("tabstop", "8"), Ok(()));
+        assert_eq!(cfg.get("tabstop").as_deref(), Some("8"));
+        assert!(cfg.set("tabstop", "0").is_err());
+        assert!(cfg.set("tabstop", "x").is_err());
+        assert_eq!(cfg.set("wrap", "false"), Ok(()));
+        assert_eq!(cfg.get("wrap").as_deref(), Some("off"));
+        assert!(cfg.set("wrap", "maybe").is_err());
+        assert_eq!(cfg.set("truecolor", "auto"), Ok(()));
+        assert_eq!(cfg.truecolor, None);
+        assert!(cfg.set("log_level", "loud").is_err());
+        assert_eq!(cfg.set("backup", "beside"), Ok(()));
+        assert_eq!(cfg.backup, Backup::Beside);
+        assert_eq!(cfg.set("clipboard.copy", " wl-copy "), Ok(()));
+        assert_eq!(cfg.clipboard_copy.as_deref(), Some("wl-copy"));
+        assert_eq!(cfg.set("clipboard.copy", ""), Ok(()));
+        assert_eq!(cfg.clipboard_copy, None);
+        assert_eq!(cfg.set("nope", "1"), Err("Unknown option nope".to_string()));
+        assert_eq!(cfg.get("nope"), None);
+    }
+
+    #[test]
+    fn default_cfg_written() {
+        let dir = temp_dir("default");
+        let mut cfg = FreviConfig::new();
+        assert_eq!(cfg.read_cfg_dir(dir.clone()), Ok(()));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("config.toml")).unwrap(),
+            DEFAULT_CFG
+        );
+        assert!(dir.join("autoplug").exists());
+        // commented out template changes nothing
+        let mut again = FreviConfig::new();
+        assert_eq!(again.read_cfg_dir(dir.clone()), Ok(()));
+        assert!(again.errors.is_empty() && again.notices.is_empty());
+        assert_eq!(again.tabstop, FreviConfig::new().tabstop);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn toml_entries() {
+        let dir = temp_dir("toml");
+        let toml = "tabstop = 8\nwrap = false\nnumbers = \"relative\"\nscrolloff = 5000\n\
+                    unknown = 1\nratio = 0.5\n[shell]\nunix = \"bash\"\n[clipboard]\ncopy = \"c\"\n\
+                    [keys.normal]\n\"<C-s>\" = \"<Cmd>w\"\n\"x\" = 1\n";
+        std::fs::write(dir.join("config.toml"), toml).unwrap();
+        let mut cfg = FreviConfig::new();
+        assert_eq!(cfg.read_cfg_dir(dir.clone()), Ok(()));
+        assert_eq!(cfg.tabstop, 8);
+        assert!(!cfg.wrap);
+        assert_eq!(cfg.numbers, LineNumbers::Relative);
+        assert_eq!(cfg.defsh.1.as_deref(), Some("bash"));
+        assert_eq!(cfg.clipboard_copy.as_deref(), Some("c"));
+        assert_eq!(cfg.clipboard_paste, None);
+        assert_eq!(
+            cfg.keymaps,
+            [(
+                "normal".to_string(),
+                "<C-s>".to_string(),
+                "<Cmd>w".to_string()
+            )]
+        );
+        // bad entries are skipped, the rest still apply
+        assert_eq!(cfg.scrolloff, FreviConfig::new().scrolloff);
+        assert_eq!(cfg.errors.len(), 4, "{:?}", cfg.errors);
+        assert!(cfg.errors.iter().any(|e| e.contains("scrolloff")));
+        assert!(
+            cfg.errors
+                .iter()
+                .any(|e| e.contains("Unknown option unknown"))
+        );
+        assert!(
+            cfg.errors
+                .iter()
+                .any(|e| e.contains("ratio: unsupported value float"))
+        );
+        assert!(cfg.errors.iter().any(|e| e.contains("keys.normal x")));
+
+        std::fs::write(dir.join("config.toml"), "tabstop = ").unwrap();
+        let mut broken = FreviConfig::new();
+        assert_eq!(broken.read_cfg_dir(dir.clone()), Ok(()));
+        assert_eq!(broken.errors.len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn old_files_fallback() {
+        let dir = temp_dir("old");
+        std::fs::write(dir.join("config.toml"), "backup = \"beside\"\n").unwrap();
+        std::fs::write(dir.join("backup"), "~/.frevi/backups\n").unwrap();
+        std::fs::write(dir.join("clipboard"), "; comment\nwl-copy\nwl-paste -n\n").unwrap();
+        std::fs::write(dir.join("autosession"), "work\n").unwrap();
+        let mut cfg = FreviConfig::new();
+        assert_eq!(cfg.read_cfg_dir(dir.clone()), Ok(()));
+        // config.toml wins over the old file
+        assert_eq!(cfg.backup, Backup::Beside);
+        assert_eq!(cfg.clipboard_copy.as_deref(), Some("wl-copy"));
+        assert_eq!(cfg.clipboard_paste.as_deref(), Some("wl-paste -n"));
+        assert_eq!(cfg.autosession.as_deref(), Some("work"));
+        assert_eq!(cfg.notices.len(), 2);
+        assert!(cfg.notices[0].contains("clipboard"));
+        assert!(cfg.notices[1].contains("autosession"));
+
+        // one clipboard entry in config.toml is enough to skip the old file
+        std::fs::write(dir.join("config.toml"), "[clipboard]\npaste = \"p\"\n").unwrap();
+        let mut cfg = FreviConfig::new();
+        assert_eq!(cfg.read_cfg_dir(dir.clone()), Ok(()));
+        assert_eq!(cfg.clipboard_copy, None);
+        assert_eq!(cfg.clipboard_paste.as_deref(), Some("p"));
+        assert!(matches!(cfg.backup, Backup::Dir(_)));
+        assert_eq!(cfg.notices.len(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -1,51 +1,343 @@
-use std::char;
+use ropey::Rope;
 
-use crate::tabs::Tab;
+use crate::{buffer::TextBuffer, disk::DiskInfo, fileformat::FileFormat, tabs::Tab};
 
+// A single reversible change: `removed` was replaced by `inserted`
+// starting at (start_line, start_x). Both may contain '\n'.
+// Ropes are cheap to clone, so replacing a whole huge buffer costs nothing here.
 #[derive(Debug, Clone)]
 pub struct Edit {
     pub start_line: usize,
     pub start_x: usize,
-    pub end_line: usize,
-    pub end_x: usize,
-    pub diff: Vec<String>, // previous buffer
+    pub removed: Rope,
+    pub inserted: Rope,
 }
 
 impl Edit {
-    pub fn new_at_curs(cursor: (usize, usize)) -> Edit {
-        let start_l: usize = cursor.1;
-        let start_x: usize = cursor.0;
-        let end_line: usize = 0;
-        let end_x: usize = 0;
-        let diff_made: Vec<String> = vec![String::new()];
-
+    pub fn new_at_curs(cursor: (usize, usize), removed: Rope, inserted: Rope) -> Edit {
         Edit {
-            start_line: start_l,
-            start_x: start_x,
-            end_line: end_line,
-            end_x: end_x,
-            diff: diff_made,
+            start_line: cursor.1,
+            start_x: cursor.0,
+            removed,
+            inserted,
         }
     }
 
-    pub fn edit_at_curs(&mut self, cursor: (usize, usize), ch: char) {
-        let (col_n, line_n) = cursor;
-        let line = &mut self.diff[line_n];
-        let x_chars = col_n.clamp(0, line.chars().count());
-        let byte_index = line
-            .char_indices()
-            .nth(x_chars)
-            .map(|(i, _)| i)
-            .unwrap_or_else(|| line.len());
+    // Tries to glue `next` onto this edit, so a run of typed chars
+    // (or backspaces) becomes one edit. Returns false if not adjacent.
+    pub fn merge(&mut self, next: &Edit) -> bool {
+        if self.removed.len_chars() == 0 && next.removed.len_chars() == 0 {
+            let (end_line, end_x) = text_end(self.start_line, self.start_x, &self.inserted);
+            if next.start_line == end_line && next.start_x == end_x {
+                self.inserted.append(next.inserted.clone());
+                return true;
+            }
+        }
+        if self.inserted.len_chars() == 0 && next.inserted.len_chars() == 0 {
+            let (end_line, end_x) = text_end(next.start_line, next.start_x, &next.removed);
+            if end_line == self.start_line && end_x == self.start_x {
+                let mut removed = next.removed.clone();
+                removed.append(self.removed.clone());
+                self.removed = removed;
+                self.start_line = next.start_line;
+                self.start_x = next.start_x;
+                return true;
+            }
+        }
+        false
+    }
 
-        // todo once in future here...
+    // Reverts the edit on buffer
+    pub fn undo_edit(&self, buf: &mut TextBuffer) {
+        let (end_line, end_x) = text_end(self.start_line, self.start_x, &self.inserted);
+        buf.remove(self.start_line, self.start_x, end_line, end_x);
+        buf.insert_rope(self.start_line, self.start_x, &self.removed);
     }
 
-    pub fn undo_edit(&mut self, buf: &mut Vec<String>) {}
+    // Applies the edit again after it was undone
+    pub fn redo_edit(&self, buf: &mut TextBuffer) {
+        let (end_line, end_x) = text_end(self.start_line, self.start_x, &self.removed);
+        buf.remove(self.start_line, self.start_x, end_line, end_x);
+        buf.insert_rope(self.start_line, self.start_x, &self.inserted);
+    }
 
-    pub fn dbg_show_edit(&mut self) -> Tab {
+    pub fn dbg_show_edit(&self) -> Tab {
         let mut res: Tab = Tab::new(Some("Debug".to_string()));
-        res.buf = self.diff.clone();
+        let info = format!(
+            "At line {}, col {}\n--- removed:\n{}\n--- inserted:\n{}",
+            self.start_line + 1,
+            self.start_x,
+            self.removed,
+            self.inserted
+        );
+        res.str_into_buf(info);
         res
     }
 }
+
+// File a buffer belongs to, so undo of !r puts back the old one along with its text
+#[derive(Debug, Clone)]
+pub struct FileIdent {
+    pub filename: String,
+    pub format: FileFormat,
+    pub disk: DiskInfo,
+}
+
+// Edits that are undone/redone together
+#[derive(Debug, Clone)]
+pub struct EditGroup {
+    pub id: u64,
+    pub edits: Vec<Edit>,
+    pub file: Option<(FileIdent, FileIdent)>, // (before, after) if the group switched files
+}
+
+#[derive(Debug, Clone)]
+pub struct EditHist {
+    undo_stack: Vec<EditGroup>,
+    redo_stack: Vec<EditGroup>,
+    group_open: bool,
+    next_id: u64,
+    saved_id: u64, // id of the group on top of undo stack when saved; 0 is empty hist
+    version: u64,  // bumped on every change, undo and redo; swap files follow it
+}
+
+impl EditHist {
+    pub fn new() -> EditHist {
+        EditHist {
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            group_open: false,
+            next_id: 1,
+            saved_id: 0,
+            version: 0,
+        }
+    }
+
+    // Records an already applied edit.
+    // With coalesce, it joins the currently open group (typing session).
+    pub fn push(&mut self, edit: Edit, coalesce: bool) {
+        self.redo_stack.clear();
+        self.version += 1;
+        if !coalesce {
+            self.seal();
+        }
+        if self.group_open
+            && let Some(group) = self.undo_stack.last_mut()
+        {
+            if let Some(last) = group.edits.last_mut()
+                && last.merge(&edit)
+            {
+                return;
+            }
+            group.edits.push(edit);
+            return;
+        }
+        self.undo_stack.push(EditGroup {
+            id: self.next_id,
+            edits: vec![edit],
+            file: None,
+        });
+        self.next_id += 1;
+        self.group_open = coalesce;
+    }
+
+    // Closes current group, so next edit starts a new undo step
+    pub fn seal(&mut self) {
+        self.group_open = false;
+    }
+
+    // Returns position the cursor should go to, if something was undone
+    pub fn undo(&mut self, buf: &mut TextBuffer) -> Option<(usize, usize)> {
+        self.seal();
+        let group = self.undo_stack.pop()?;
+        self.version += 1;
+        for edit in group.edits.iter().rev() {
+            edit.undo_edit(buf);
+        }
+        let first = &group.edits[0];
+        let curs = (first.start_x, first.start_line);
+        self.redo_stack.push(group);
+        Some(curs)
+    }
+
+    pub fn redo(&mut self, buf: &mut TextBuffer) -> Option<(usize, usize)> {
+        self.seal();
+        let group = self.redo_stack.pop()?;
+        self.version += 1;
+        for edit in group.edits.iter() {
+            edit.redo_edit(buf);
+        }
+        let last = &group.edits[group.edits.len() - 1];
+        let curs = (last.start_x, last.start_line);
+        self.undo_stack.push(group);
+        Some(curs)
+    }
+
+    // Last group also switched the buffer from one file to another
+    pub fn set_file_change(&mut self, before: FileIdent, after: FileIdent) {
+        if let Some(group) = self.undo_stack.last_mut() {
+            group.file = Some((before, after));
+        }
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.seal();
+        self.saved_id = match self.undo_stack.last() {
+            Some(g) => g.id,
+            None => 0,
+        };
+    }
+
+    // File on disk matches no state of the history any more, undo and redo can't
+    // bring back a saved one
+    pub fn mark_unsaved(&mut self) {
+        self.saved_id = u64::MAX;
+    }
+
+    // True if buffer is in the same state it was on last save
+    pub fn is_saved(&self) -> bool {
+        let cur_id = match self.undo_stack.last() {
+            Some(g) => g.id,
+            None => 0,
+        };
+        cur_id == self.saved_id
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // Group just undone / redone, to replay its changes elsewhere
+    pub fn last_undone(&self) -> Option<&EditGroup> {
+        self.redo_stack.last()
+    }
+
+    pub fn last_redone(&self) -> Option<&EditGroup> {
+        self.undo_stack.last()
+    }
+
+    pub fn last_edit(&self) -> Option<&Edit> {
+        self.undo_stack.last().and_then(|g| g.edits.last())
+    }
+}
+
+// Position right after `text` if it was inserted at (line, x)
+pub fn text_end(line: usize, x: usize, text: &Rope) -> (usize, usize) {
+    let newlines = text.len_lines() - 1;
+    let last_len = text.line(newlines).len_chars();
+    if newlines == 0 {
+        return (line, x + last_len);
+    }
+    (line + newlines, last_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(line: usize, x: usize, removed: &str, inserted: &str) -> Edit {
+        Edit::new_at_curs((x, line), Rope::from_str(removed), Rope::from_str(inserted))
+    }
+
+    // Applies edit to buffer and records it, like Tab does
+    fn apply(buf: &mut TextBuffer, hist: &mut EditHist, edit: Edit, coalesce: bool) {
+        edit.redo_edit(buf);
+        hist.push(edit, coalesce);
+    }
+
+    fn text(buf: &TextBuffer) -> String {
+        buf.rope().to_string()
+    }
+
+    #[test]
+    fn merge_typing_and_backspaces() {
+        let mut typed = edit(0, 2, "", "a");
+        assert!(typed.merge(&edit(0, 3, "", "b\n")));
+        assert!(typed.merge(&edit(1, 0, "", "c")));
+        assert_eq!(typed.inserted.to_string(), "ab\nc");
+        // not right after the typed text
+        assert!(!typed.merge(&edit(0, 3, "", "x")));
+        assert!(!typed.merge(&edit(1, 1, "z", "")));
+
+        let mut deleted = edit(1, 0, "c", "");
+        assert!(deleted.merge(&edit(0, 2, "\n", "")));
+        assert_eq!((deleted.start_line, deleted.start_x), (0, 2));
+        assert_eq!(deleted.removed.to_string(), "\nc");
+        assert!(!deleted.merge(&edit(0, 5, "d", "")));
+    }
+
+    #[test]
+    fn groups_undo_together() {
+        let mut buf = TextBuffer::from_text("xy");
+        let mut hist = EditHist::new();
+        apply(&mut buf, &mut hist, edit(0, 1, "", "a"), true);
+        apply(&mut buf, &mut hist, edit(0, 2, "", "b"), true);
+        // not adjacent, same group still
+        apply(&mut buf, &mut hist, edit(0, 0, "", "c"), true);
+        hist.seal();
+        apply(&mut buf, &mut hist, edit(0, 5, "", "!"), true);
+        assert_eq!(text(&buf), "cxaby!");
+        assert_eq!(hist.undo(&mut buf), Some((5, 0)));
+        assert_eq!(text(&buf), "cxaby");
+        assert_eq!(hist.undo(&mut buf), Some((1, 0)));
+        assert_eq!(text(&buf), "xy");
+        assert_eq!(hist.undo(&mut buf), None);
+        assert_eq!(hist.redo(&mut buf), Some((0, 0)));
+        assert_eq!(text(&buf), "cxaby");
+        assert_eq!(hist.redo(&mut buf), Some((5, 0)));
+        assert_eq!(hist.redo(&mut buf), None);
+    }
+
+    #[test]
+    fn new_edit_drops_redo() {
+        let mut buf = TextBuffer::from_text("");
+        let mut hist = EditHist::new();
+        apply(&mut buf, &mut hist, edit(0, 0, "", "a"), false);
+        apply(&mut buf, &mut hist, edit(0, 1, "", "b"), false);
+        hist.undo(&mut buf);
+        apply(&mut buf, &mut hist, edit(0, 1, "", "c"), false);
+        assert_eq!(hist.redo(&mut buf), None);
+        assert_eq!(text(&buf), "ac");
+        hist.undo(&mut buf);
+        hist.undo(&mut buf);
+        assert_eq!(text(&buf), "");
+    }
+
+    #[test]
+    fn saved_state() {
+        let mut buf = TextBuffer::from_text("");
+        let mut hist = EditHist::new();
+        assert!(hist.is_saved());
+        apply(&mut buf, &mut hist, edit(0, 0, "", "a"), true);
+        assert!(!hist.is_saved());
+        hist.mark_saved();
+        assert!(hist.is_saved());
+        // typing after save starts a new group, undo gets back to the saved text
+        apply(&mut buf, &mut hist, edit(0, 1, "", "b"), true);
+        assert!(!hist.is_saved());
+        hist.undo(&mut buf);
+        assert!(hist.is_saved());
+        hist.undo(&mut buf);
+        assert!(!hist.is_saved());
+        hist.redo(&mut buf);
+        assert!(hist.is_saved());
+        // file on disk changed under the buffer: no state is saved any more
+        hist.mark_unsaved();
+        assert!(!hist.is_saved());
+        hist.undo(&mut buf);
+        assert!(!hist.is_saved());
+    }
+
+    #[test]
+    fn version_follows_changes() {
+        let mut buf = TextBuffer::from_text("");
+        let mut hist = EditHist::new();
+        apply(&mut buf, &mut hist, edit(0, 0, "", "a"), false);
+        let v = hist.version();
+        hist.undo(&mut buf);
+        assert!(hist.version() > v);
+        let v = hist.version();
+        hist.undo(&mut buf);
+        assert_eq!(hist.version(), v);
+    }
+}
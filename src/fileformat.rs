@@ -0,0 +1,358 @@
+use std::fmt;
+
+use crate::buffer::TextBuffer;
+
+// How a file is stored on disk. Buffer always has '\n' between lines and no
+// trailing one; these are applied back on write, so unchanged files are saved
+// byte for byte as they were read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileFormat {
+    pub ending: LineEnding,
+    pub encoding: Encoding,
+    pub bom: bool,
+    pub final_newline: bool,
+}
+
+impl Default for FileFormat {
+    // what new files get
+    fn default() -> FileFormat {
+        FileFormat {
+            ending: LineEnding::Lf,
+            encoding: Encoding::Utf8,
+            bom: false,
+            final_newline: true,
+        }
+    }
+}
+
+impl LineEnding {
+    pub fn from_name(name: &str) -> Option<LineEnding> {
+        match name.to_ascii_lowercase().as_str() {
+            "lf" | "unix" => Some(LineEnding::Lf),
+            "crlf" | "dos" => Some(LineEnding::CrLf),
+            "cr" | "mac" => Some(LineEnding::Cr),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LineEnding::Lf => "lf",
+            LineEnding::CrLf => "crlf",
+            LineEnding::Cr => "cr",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Encoding names for !enc and status line, with or without BOM
+const ENCODING_NAMES: &[(&str, Encoding, bool)] = &[
+    ("utf-8", Encoding::Utf8, false),
+    ("utf-8-bom", Encoding::Utf8, true),
+    ("utf-16le", Encoding::Utf16Le, true),
+    ("utf-16le-nobom", Encoding::Utf16Le, false),
+    ("utf-16be", Encoding::Utf16Be, true),
+    ("utf-16be-nobom", Encoding::Utf16Be, false),
+    ("latin1", Encoding::Latin1, false),
+];
+
+// Encoding and whether it has BOM by name; utf8, utf-16 and iso-8859-1 work too
+pub fn encoding_from_name(name: &str) -> Option<(Encoding, bool)> {
+    let name = match name.to_ascii_lowercase().as_str() {
+        "utf8" => "utf-8".to_string(),
+        "utf8-bom" => "utf-8-bom".to_string(),
+        "utf-16" | "utf16" => "utf-16le".to_string(),
+        "latin-1" | "iso-8859-1" => "latin1".to_string(),
+        other => other.to_string(),
+    };
+    ENCODING_NAMES
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|&(_, enc, bom)| (enc, bom))
+}
+
+pub fn encoding_names() -> Vec<&'static str> {
+    ENCODING_NAMES.iter().map(|(n, _, _)| *n).collect()
+}
+
+impl FileFormat {
+    pub fn encoding_name(&self) -> &'static str {
+        ENCODING_NAMES
+            .iter()
+            .find(|(_, enc, bom)| *enc == self.encoding && (*bom == self.bom))
+            .map(|(n, _, _)| *n)
+            .unwrap_or("latin1")
+    }
+}
+
+// Status line: "utf-8 crlf", "latin1 lf noeol"
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.encoding_name(), self.ending)?;
+        if !self.final_newline {
+            write!(f, " noeol")?;
+        }
+        Ok(())
+    }
+}
+
+// UTF-16 without BOM: ASCII text has zero bytes on every other position
+fn guess_utf16(bytes: &[u8]) -> Option<Encoding> {
+    let sample = &bytes[..bytes.len().min(512) & !1];
+    let pairs = sample.len() / 2;
+    if pairs < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let even_zeros = sample.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd_zeros = sample
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .filter(|b| **b == 0)
+        .count();
+    match (even_zeros * 10 > pairs * 4, odd_zeros * 10 > pairs * 4) {
+        (false, true) if even_zeros == 0 => Some(Encoding::Utf16Le),
+        (true, false) if odd_zeros == 0 => Some(Encoding::Utf16Be),
+        _ => None,
+    }
+}
+
+fn decode_utf16(bytes: &[u8], encoding: Encoding) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = bytes.chunks_exact(2).map(|p| match encoding {
+        Encoding::Utf16Be => u16::from_be_bytes([p[0], p[1]]),
+        _ => u16::from_le_bytes([p[0], p[1]]),
+    });
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .ok()
+}
+
+// Text of the file with its encoding and BOM. Anything that isn't valid
+// UTF-8 or UTF-16 is read as Latin-1, which maps every byte to a char and back.
+fn decode(bytes: &[u8]) -> (String, Encoding, bool) {
+    if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF")
+        && let Ok(text) = std::str::from_utf8(rest)
+    {
+        return (text.to_string(), Encoding::Utf8, true);
+    }
+    for (bom, encoding) in [
+        (b"\xFF\xFE", Encoding::Utf16Le),
+        (b"\xFE\xFF", Encoding::Utf16Be),
+    ] {
+        if let Some(rest) = bytes.strip_prefix(bom)
+            && let Some(text) = decode_utf16(rest, encoding)
+        {
+            return (text, encoding, true);
+        }
+    }
+    if let Some(encoding) = guess_utf16(bytes)
+        && let Some(text) = decode_utf16(bytes, encoding)
+    {
+        return (text, encoding, false);
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => (text.to_string(), Encoding::Utf8, false),
+        Err(_) => (
+            bytes.iter().map(|&b| b as char).collect(),
+            Encoding::Latin1,
+            false,
+        ),
+    }
+}
+
+// CRLF only if every line ends with it, so files with mixed endings keep
+// their stray '\r' in text and are written back as they were
+fn detect_ending(text: &str) -> LineEnding {
+    let lf = text.matches('\n').count();
+    if lf == 0 {
+        return match text.contains('\r') {
+            true => LineEnding::Cr,
+            false => LineEnding::Lf,
+        };
+    }
+    match text.matches("\r\n").count() == lf {
+        true => LineEnding::CrLf,
+        false => LineEnding::Lf,
+    }
+}
+
+// File contents into buffer and the format to write it back with
+pub fn from_bytes(bytes: &[u8]) -> (TextBuffer, FileFormat) {
+    let (text, encoding, bom) = decode(bytes);
+    let ending = detect_ending(&text);
+    let mut text = match ending {
+        LineEnding::Lf => text,
+        LineEnding::CrLf => text.replace("\r\n", "\n"),
+        LineEnding::Cr => text.replace('\r', "\n"),
+    };
+    let final_newline = text.ends_with('\n');
+    if final_newline {
+        text.pop();
+    }
+    let format = FileFormat {
+        ending,
+        encoding,
+        bom,
+        final_newline,
+    };
+    (TextBuffer::from_text(&text), format)
+}
+
+// Buffer into bytes to be written. Fails on chars Latin-1 can't hold.
+pub fn to_bytes(buf: &TextBuffer, format: &FileFormat) -> Result<Vec<u8>, String> {
+    let mut text = String::with_capacity(buf.rope().len_bytes() + 1);
+    for chunk in buf.rope().chunks() {
+        text.push_str(chunk);
+    }
+    if format.final_newline {
+        text.push('\n');
+    }
+    if format.ending != LineEnding::Lf {
+        text = text.replace('\n', format.ending.as_str());
+    }
+    let mut res: Vec<u8> = Vec::with_capacity(text.len() + 3);
+    match format.encoding {
+        Encoding::Utf8 => {
+            if format.bom {
+                res.extend_from_slice(b"\xEF\xBB\xBF");
+            }
+            res.extend_from_slice(text.as_bytes());
+        }
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let be = format.encoding == Encoding::Utf16Be;
+            let units = format
+                .bom
+                .then_some(0xFEFF)
+                .into_iter()
+                .chain(text.encode_utf16());
+            for unit in units {
+                match be {
+                    true => res.extend_from_slice(&unit.to_be_bytes()),
+                    false => res.extend_from_slice(&unit.to_le_bytes()),
+                }
+            }
+        }
+        Encoding::Latin1 => {
+            for (i, line) in buf.rope().lines().enumerate() {
+                if let Some(ch) = line.chars().find(|c| *c as u32 > 0xFF) {
+                    return Err(format!("Can't write {:?} (line {}) as latin1", ch, i + 1));
+                }
+            }
+            res.extend(text.chars().map(|c| c as u8));
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reads bytes, checks the text and format, then writes them back unchanged
+    fn round_trip(bytes: &[u8], text: &str, format: FileFormat) {
+        let (buf, read) = from_bytes(bytes);
+        assert_eq!(buf.rope().to_string(), text);
+        assert_eq!(read, format);
+        assert_eq!(to_bytes(&buf, &read).unwrap(), bytes);
+    }
+
+    fn utf8(ending: LineEnding, final_newline: bool) -> FileFormat {
+        FileFormat {
+            ending,
+            final_newline,
+            ..FileFormat::default()
+        }
+    }
+
+    #[test]
+    fn line_endings() {
+        round_trip(b"a\nb\n", "a\nb", utf8(LineEnding::Lf, true));
+        round_trip(b"a\r\nb\r\n", "a\nb", utf8(LineEnding::CrLf, true));
+        round_trip(b"a\rb\r", "a\nb", utf8(LineEnding::Cr, true));
+    }
+
+    #[test]
+    fn mixed_endings_keep_stray_cr() {
+        round_trip(b"a\r\nb\nc\n", "a\r\nb\nc", utf8(LineEnding::Lf, true));
+    }
+
+    #[test]
+    fn no_final_newline() {
+        round_trip(b"a\nb", "a\nb", utf8(LineEnding::Lf, false));
+        round_trip(b"a\r\nb", "a\nb", utf8(LineEnding::CrLf, false));
+        round_trip(b"", "", utf8(LineEnding::Lf, false));
+    }
+
+    #[test]
+    fn utf8_bom() {
+        let format = FileFormat {
+            bom: true,
+            ..FileFormat::default()
+        };
+        round_trip("\u{FEFF}ä\n".as_bytes(), "ä", format);
+        assert_eq!(format.encoding_name(), "utf-8-bom");
+    }
+
+    #[test]
+    fn utf16() {
+        let le = FileFormat {
+            encoding: Encoding::Utf16Le,
+            bom: true,
+            ..FileFormat::default()
+        };
+        round_trip(b"\xFF\xFEa\0\n\0", "a", le);
+        let be = FileFormat {
+            encoding: Encoding::Utf16Be,
+            bom: true,
+            ending: LineEnding::CrLf,
+            ..FileFormat::default()
+        };
+        round_trip(b"\xFE\xFF\0a\0\r\0\n", "a", be);
+        // no BOM, guessed from zero bytes
+        let nobom = FileFormat {
+            encoding: Encoding::Utf16Le,
+            final_newline: false,
+            ..FileFormat::default()
+        };
+        round_trip(b"a\0b\0c\0", "abc", nobom);
+    }
+
+    #[test]
+    fn latin1_fallback() {
+        let format = FileFormat {
+            encoding: Encoding::Latin1,
+            ..FileFormat::default()
+        };
+        round_trip(b"caf\xE9\n", "café", format);
+        let buf = TextBuffer::from_text("€");
+        assert!(to_bytes(&buf, &format).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Cursor x stays a char offset (that's what the rope and motions use), but it is
+// always kept at the start of a grapheme cluster: letters with combining marks,
+// flags and emoji sequences are stepped over and deleted as one.
+// On screen every cluster takes its display width in cells, tab goes to next tabstop,
+// other control chars are drawn as ^M, ^[ and such.
+
+// Char offsets where clusters of text start, plus text length in chars at the end
+pub fn starts(text: &str) -> Vec<usize> {
+    let mut res: Vec<usize> = Vec::new();
+    let mut x = 0;
+    for g in text.graphemes(true) {
+        res.push(x);
+        x += g.chars().count();
+    }
+    res.push(x);
+    res
+}
+
+// Start of n-th cluster after the one holding x, stops at text end
+pub fn next(text: &str, x: usize, n: usize) -> usize {
+    let st = starts(text);
+    let i = match st.binary_search(&x) {
+        Ok(i) => i,
+        Err(i) => i.saturating_sub(1),
+    };
+    st[(i + n).min(st.len() - 1)]
+}
+
+// Start of n-th cluster before x
+pub fn prev(text: &str, x: usize, n: usize) -> usize {
+    let st = starts(text);
+    let i = match st.binary_search(&x) {
+        Ok(i) => i,
+        Err(i) => i,
+    };
+    st[i.saturating_sub(n)]
+}
+
+// Caret form of a control char: '\r' is ^M, DEL is ^?
+fn caret(g: &str) -> Option<String> {
+    let mut chars = g.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c != '\t' && (c as u32) < 0x20 => {
+            Some(format!("^{}", (c as u8 + b'@') as char))
+        }
+        (Some('\x7f'), None) => Some("^?".to_string()),
+        _ => None,
+    }
+}
+
+// Cells taken by cluster drawn at display column col
+pub fn cell_width(g: &str, col: usize, tabstop: usize) -> usize {
+    match g {
+        "\t" => tabstop.max(1) - col % tabstop.max(1),
+        _ if caret(g).is_some() => 2,
+        _ => g.width(),
+    }
+}
+
+// Display column where char x of text is drawn
+pub fn display_col(text: &str, x: usize, tabstop: usize) -> usize {
+    let mut col = 0;
+    let mut chars = 0;
+    for g in text.graphemes(true) {
+        if chars >= x {
+            break;
+        }
+        col += cell_width(g, col, tabstop);
+        chars += g.chars().count();
+    }
+    col
+}
+
+// Start of cluster drawn over cell col, text length if line is shorter
+pub fn x_at_col(text: &str, col: usize, tabstop: usize) -> usize {
+    let (mut x, mut cur) = (0, 0);
+    for g in text.graphemes(true) {
+        cur += cell_width(g, cur, tabstop);
+        if cur > col {
+            return x;
+        }
+        x += g.chars().count();
+    }
+    x
+}
+
+// Clusters of text with their first char offset, tabs turned into spaces
+// and control chars into carets
+pub fn expand(text: &str, tabstop: usize) -> Vec<(usize, String)> {
+    let mut res: Vec<(usize, String)> = Vec::new();
+    let (mut x, mut col) = (0, 0);
+    for g in text.graphemes(true) {
+        let width = cell_width(g, col, tabstop);
+        let shown = match g {
+            "\t" => " ".repeat(width),
+            _ => caret(g).unwrap_or_else(|| g.to_string()),
+        };
+        res.push((x, shown));
+        x += g.chars().count();
+        col += width;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // e with combining acute, wide CJK char, emoji with skin tone
+    const MIXED: &str = "e\u{301}日👍🏽x";
+
+    #[test]
+    fn cluster_steps() {
+        assert_eq!(starts(MIXED), [0, 2, 3, 5, 6]);
+        assert_eq!(next(MIXED, 0, 1), 2);
+        assert_eq!(next(MIXED, 1, 1), 2);
+        assert_eq!(next(MIXED, 2, 2), 5);
+        assert_eq!(next(MIXED, 5, 3), 6);
+        assert_eq!(prev(MIXED, 6, 1), 5);
+        assert_eq!(prev(MIXED, 5, 1), 3);
+        assert_eq!(prev(MIXED, 4, 1), 3);
+        assert_eq!(prev(MIXED, 3, 9), 0);
+        assert_eq!(next("", 0, 1), 0);
+        assert_eq!(prev("", 0, 1), 0);
+    }
+
+    #[test]
+    fn wide_and_combining_columns() {
+        assert_eq!(display_col(MIXED, 2, 4), 1);
+        assert_eq!(display_col(MIXED, 3, 4), 3);
+        assert_eq!(display_col(MIXED, 5, 4), 5);
+        assert_eq!(display_col(MIXED, 6, 4), 6);
+        // both cells of wide char lead to its start
+        assert_eq!(x_at_col(MIXED, 1, 4), 2);
+        assert_eq!(x_at_col(MIXED, 2, 4), 2);
+        assert_eq!(x_at_col(MIXED, 4, 4), 3);
+        assert_eq!(x_at_col(MIXED, 99, 4), 6);
+    }
+
+    #[test]
+    fn tabs_and_carets() {
+        assert_eq!(display_col("a\tb", 1, 4), 1);
+        assert_eq!(display_col("a\tb", 2, 4), 4);
+        assert_eq!(display_col("abcd\tb", 5, 4), 8);
+        assert_eq!(display_col("\t\t", 2, 8), 16);
+        assert_eq!(display_col("日\t", 2, 4), 4);
+        assert_eq!(x_at_col("a\tb", 3, 4), 1);
+        assert_eq!(x_at_col("a\tb", 4, 4), 2);
+        assert_eq!(display_col("\r\x7fa", 2, 4), 4);
+        let shown: Vec<String> = expand("a\t\r日", 4).into_iter().map(|c| c.1).collect();
+        assert_eq!(shown, ["a", "   ", "^M", "日"]);
+    }
+}
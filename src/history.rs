@@ -0,0 +1,274 @@
+use std::{fs, path::PathBuf};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_width::UnicodeWidthStr;
+
+use crate::app::App;
+use crate::save::{self, Backup};
+
+// Command line history. It's kept in ~/.frevi/history (oldest first, a command
+// per line) after every command, so it outlives frevi and running instances share it.
+// Commands are stored without the leading '!': ":w" and "!w" are one entry.
+
+const HISTORY_SIZE: usize = 1000;
+
+fn bare(line: &str) -> &str {
+    line.strip_prefix('!').unwrap_or(line)
+}
+
+// Ctrl-R in command line: looks for typed text in older and older commands
+#[derive(Debug)]
+pub struct RevSearch {
+    query: String,
+    found: Option<usize>,
+    saved: String, // command line from before, Esc brings it back
+}
+
+#[derive(Debug)]
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+    ctr: usize,     // entries.len() unless walking with Up/Down
+    prefix: String, // command line when Up was pressed first, only entries starting with it are shown
+    pub rev_search: Option<RevSearch>,
+}
+
+impl History {
+    pub fn new(path: Option<PathBuf>) -> History {
+        let mut hist = History {
+            entries: Vec::new(),
+            path,
+            ctr: 0,
+            prefix: String::new(),
+            rev_search: None,
+        };
+        hist.entries = hist.read_file().unwrap_or_default();
+        hist.ctr = hist.entries.len();
+        hist
+    }
+
+    fn read_file(&self) -> Option<Vec<String>> {
+        let text = fs::read_to_string(self.path.as_ref()?).ok()?;
+        Some(
+            text.lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(String::from)
+                .collect(),
+        )
+    }
+
+    // Moves command to the end if it was there already; commands other instances
+    // added meanwhile are picked up from the file first
+    pub fn add(&mut self, line: &str) {
+        let line = bare(line.trim()).to_string();
+        if line.is_empty() {
+            return;
+        }
+        if let Some(entries) = self.read_file() {
+            self.entries = entries;
+        }
+        self.entries.retain(|e| *e != line);
+        self.entries.push(line);
+        let extra = self.entries.len().saturating_sub(HISTORY_SIZE);
+        self.entries.drain(..extra);
+        self.ctr = self.entries.len();
+        if let Some(path) = &self.path {
+            let text = self.entries.join("\n") + "\n";
+            let _ = save::write_file(&path.to_string_lossy(), text.as_bytes(), &Backup::Off);
+        }
+    }
+
+    pub fn stop_browsing(&mut self) {
+        self.ctr = self.entries.len();
+    }
+
+    fn matches_prefix(&self, entry: &str) -> bool {
+        entry.starts_with(bare(&self.prefix))
+    }
+
+    // Entry as it goes into command line: with '!' if the line was opened with it
+    fn with_lead(&self, entry: &str, line: &str) -> String {
+        match line.starts_with('!') {
+            true => format!("!{}", entry),
+            false => entry.to_string(),
+        }
+    }
+
+    // Up: next older command starting with what was typed, None if there's none
+    pub fn older(&mut self, line: &str) -> Option<String> {
+        if self.ctr == self.entries.len() {
+            self.prefix = line.to_string();
+        }
+        let idx = self.entries[..self.ctr]
+            .iter()
+            .rposition(|e| self.matches_prefix(e))?;
+        self.ctr = idx;
+        Some(self.with_lead(&self.entries[idx], &self.prefix))
+    }
+
+    // Down: next newer one, past the newest gives back the typed text
+    pub fn newer(&mut self) -> Option<String> {
+        if self.ctr >= self.entries.len() {
+            return None;
+        }
+        let after = self.ctr + 1;
+        match self.entries[after..]
+            .iter()
+            .position(|e| self.matches_prefix(e))
+        {
+            Some(i) => {
+                self.ctr = after + i;
+                Some(self.with_lead(&self.entries[self.ctr], &self.prefix))
+            }
+            None => {
+                self.ctr = self.entries.len();
+                Some(self.prefix.clone())
+            }
+        }
+    }
+
+    // Newest entry before `before` having query in it
+    fn find(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|e| e.contains(query))
+    }
+
+    // Status line while in Ctrl-R search and cell the cursor goes to
+    pub fn rev_search_line(&self) -> Option<(String, usize)> {
+        let search = self.rev_search.as_ref()?;
+        let head = match search.found.is_none() && !search.query.is_empty() {
+            true => format!("(failing reverse-i-search)`{}", search.query),
+            false => format!("(reverse-i-search)`{}", search.query),
+        };
+        let found = search.found.map_or("", |i| self.entries[i].as_str());
+        Some((format!("{}': {}", head, found), head.width()))
+    }
+}
+
+impl App {
+    pub fn start_rev_search(&mut self) {
+        self.command_hist.rev_search = Some(RevSearch {
+            query: String::new(),
+            found: None,
+            saved: self.command_buf.clone(),
+        });
+    }
+
+    // Found command goes into command line, to be run or changed
+    fn accept_rev_search(&mut self, search: RevSearch) {
+        if let Some(idx) = search.found {
+            let entry = self.command_hist.entries[idx].clone();
+            self.command_buf = self.command_hist.with_lead(&entry, &search.saved);
+        }
+        self.cursor_pos_xy.0 = self.command_buf.chars().count() as u16;
+    }
+
+    fn cancel_rev_search(&mut self, search: RevSearch) {
+        self.command_buf = search.saved;
+        self.cursor_pos_xy.0 = self.command_buf.chars().count() as u16;
+    }
+
+    pub fn handle_rev_search_key(&mut self, key: KeyEvent) {
+        let Some(mut search) = self.command_hist.rev_search.take() else {
+            return;
+        };
+        let hist = &self.command_hist;
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('r') if ctrl => {
+                // older match of the same text
+                let before = search.found.unwrap_or(hist.entries.len());
+                if let Some(idx) = hist.find(&search.query, before) {
+                    search.found = Some(idx);
+                }
+            }
+            KeyCode::Char('g') if ctrl => return self.cancel_rev_search(search),
+            KeyCode::Char(ch) if !ctrl => {
+                search.query.push(ch);
+                // current match is kept while it still has the text
+                let before = search.found.map_or(hist.entries.len(), |i| i + 1);
+                search.found = hist.find(&search.query, before);
+            }
+            KeyCode::Backspace => {
+                search.query.pop();
+                search.found = match search.query.is_empty() {
+                    true => None,
+                    false => hist.find(&search.query, hist.entries.len()),
+                };
+            }
+            KeyCode::Esc => return self.cancel_rev_search(search),
+            KeyCode::Enter => {
+                self.accept_rev_search(search);
+                return self.handle_command_key(key);
+            }
+            // other keys leave found command in the line for editing
+            _ => return self.accept_rev_search(search),
+        }
+        self.command_hist.rev_search = Some(search);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hist(lines: &[&str]) -> History {
+        let mut hist = History::new(None);
+        for line in lines {
+            hist.add(line);
+        }
+        hist
+    }
+
+    #[test]
+    fn dedup_and_bare() {
+        let hist = hist(&["w", "!q", "  ", "!w", "q"]);
+        assert_eq!(hist.entries, ["w", "q"]);
+    }
+
+    #[test]
+    fn prefix_filtering() {
+        let mut hist = hist(&["w a", "q", "w b", "set x"]);
+        assert_eq!(hist.older("w").as_deref(), Some("w b"));
+        assert_eq!(hist.older("w b").as_deref(), Some("w a"));
+        assert_eq!(hist.older("w a"), None);
+        assert_eq!(hist.newer().as_deref(), Some("w b"));
+        assert_eq!(hist.newer().as_deref(), Some("w"));
+        assert_eq!(hist.newer(), None);
+        // line opened with ! gets it back on entries
+        assert_eq!(hist.older("!s").as_deref(), Some("!set x"));
+        hist.stop_browsing();
+        assert_eq!(hist.older("x"), None);
+        assert_eq!(hist.older("").as_deref(), Some("set x"));
+    }
+
+    #[test]
+    fn reverse_search() {
+        let hist = hist(&["e one", "w two", "e three"]);
+        assert_eq!(hist.find("e", 3), Some(2));
+        assert_eq!(hist.find("e", 2), Some(0));
+        assert_eq!(hist.find("w", 3), Some(1));
+        assert_eq!(hist.find("one", 3), Some(0));
+        assert_eq!(hist.find("x", 3), None);
+    }
+
+    #[test]
+    fn size_cap_and_file() {
+        let path = std::env::temp_dir().join(format!("frevi_history_{}", std::process::id()));
+        let mut hist = History::new(Some(path.clone()));
+        for i in 0..HISTORY_SIZE + 5 {
+            hist.add(&format!("e {}", i));
+        }
+        assert_eq!(hist.entries.len(), HISTORY_SIZE);
+        assert_eq!(hist.entries[0], "e 5");
+        // other instance sees them, and its commands are picked up on add
+        let mut other = History::new(Some(path.clone()));
+        assert_eq!(other.entries, hist.entries);
+        other.add("q");
+        hist.add("w");
+        let _ = fs::remove_file(&path);
+        assert_eq!(hist.entries[HISTORY_SIZE - 2..], ["q", "w"]);
+        assert_eq!(hist.entries[0], "e 7");
+    }
+}
@@ -0,0 +1,515 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use mlua::Function;
+
+use crate::app::{App, Mode};
+use crate::logger::LogLevel;
+use crate::macros::{keys_to_text, text_to_keys};
+use crate::plugin::PlugLoaders;
+use crate::tabs::Tab;
+
+// Keys typed in a mode are looked up in its map before the mode sees them.
+// A map entry turns a key sequence into other keys, a command line, a built-in
+// action or a plugin function. While typed keys are the start of a longer entry,
+// they wait for the rest (maptimeout ms at most); keys matching nothing go on as typed.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapMode {
+    Normal,
+    Insert,
+    Visual,
+    Command, // search prompt too
+}
+
+pub const MAP_MODES: &[MapMode] = &[
+    MapMode::Normal,
+    MapMode::Insert,
+    MapMode::Visual,
+    MapMode::Command,
+];
+
+impl MapMode {
+    pub fn from_name(name: &str) -> Option<MapMode> {
+        match name {
+            "n" | "normal" => Some(MapMode::Normal),
+            "i" | "insert" => Some(MapMode::Insert),
+            "v" | "visual" => Some(MapMode::Visual),
+            "c" | "command" => Some(MapMode::Command),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MapMode::Normal => "normal",
+            MapMode::Insert => "insert",
+            MapMode::Visual => "visual",
+            MapMode::Command => "command",
+        }
+    }
+
+    // Questions and !s confirmations take their keys as they are
+    fn of(mode: Mode) -> Option<MapMode> {
+        match mode {
+            Mode::Normal => Some(MapMode::Normal),
+            Mode::Insert => Some(MapMode::Insert),
+            Mode::Visual(_) => Some(MapMode::Visual),
+            Mode::Command | Mode::Search { .. } => Some(MapMode::Command),
+            Mode::Confirm | Mode::Prompt => None,
+        }
+    }
+}
+
+// Built-in things keys can do, written after <Action> in a map
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Undo,
+    Redo,
+    Scroll(isize), // lines, cursor stays on screen
+    ScrollTop,
+    ScrollBottom,
+    Tab(usize), // 1-based, opens new tab if there's no such one
+    TabNext,
+    TabPrev,
+}
+
+impl Action {
+    fn parse(text: &str) -> Option<Action> {
+        let mut words = text.split_whitespace();
+        let action = match (words.next()?, words.next()) {
+            ("undo", None) => Action::Undo,
+            ("redo", None) => Action::Redo,
+            ("scroll", Some(n)) => Action::Scroll(n.parse().ok()?),
+            ("scroll_top", None) => Action::ScrollTop,
+            ("scroll_bottom", None) => Action::ScrollBottom,
+            ("tab", Some(n)) => Action::Tab(n.parse().ok().filter(|n| *n > 0)?),
+            ("tab_next", None) => Action::TabNext,
+            ("tab_prev", None) => Action::TabPrev,
+            _ => return None,
+        };
+        match words.next() {
+            Some(_) => None,
+            None => Some(action),
+        }
+    }
+
+    fn to_text(self) -> String {
+        match self {
+            Action::Undo => "undo".to_string(),
+            Action::Redo => "redo".to_string(),
+            Action::Scroll(n) => format!("scroll {}", n),
+            Action::ScrollTop => "scroll_top".to_string(),
+            Action::ScrollBottom => "scroll_bottom".to_string(),
+            Action::Tab(n) => format!("tab {}", n),
+            Action::TabNext => "tab_next".to_string(),
+            Action::TabPrev => "tab_prev".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MapTarget {
+    Keys(Vec<KeyEvent>),  // fed as typed, but not looked up in maps again
+    Command(String),      // <Cmd>w: command line, run without leaving the mode
+    Action(Action),       // <Action>tab 2
+    Lua(Function, usize), // plugin function, plugin id
+}
+
+impl MapTarget {
+    pub fn parse(text: &str) -> Result<MapTarget, String> {
+        if let Some(cmd) = text.strip_prefix("<Cmd>") {
+            let cmd = cmd.strip_suffix("<CR>").unwrap_or(cmd).trim();
+            if cmd.is_empty() {
+                return Err("<Cmd> needs a command".to_string());
+            }
+            return Ok(MapTarget::Command(cmd.to_string()));
+        }
+        if let Some(action) = text.strip_prefix("<Action>") {
+            return Action::parse(action)
+                .map(MapTarget::Action)
+                .ok_or(format!("Unknown action {}", action.trim()));
+        }
+        let keys = text_to_keys(text);
+        match keys.is_empty() {
+            true => Err("Nothing to map to".to_string()),
+            false => Ok(MapTarget::Keys(keys)),
+        }
+    }
+}
+
+// Keys compare by code and modifiers; Shift is a part of the char already
+fn same_key(a: &KeyEvent, b: &KeyEvent) -> bool {
+    let mods = |k: &KeyEvent| match k.code {
+        KeyCode::Char(_) => k.modifiers - KeyModifiers::SHIFT,
+        _ => k.modifiers,
+    };
+    a.code == b.code && mods(a) == mods(b)
+}
+
+fn starts_with(keys: &[KeyEvent], start: &[KeyEvent]) -> bool {
+    keys.len() >= start.len() && keys.iter().zip(start).all(|(a, b)| same_key(a, b))
+}
+
+pub fn parse_lhs(text: &str) -> Result<Vec<KeyEvent>, String> {
+    let keys = text_to_keys(text);
+    match keys.is_empty() {
+        true => Err("No keys to map".to_string()),
+        false => Ok(keys),
+    }
+}
+
+#[derive(Debug)]
+pub struct Keymaps {
+    maps: HashMap<MapMode, Vec<(Vec<KeyEvent>, MapTarget)>>,
+    pub pending: Vec<KeyEvent>, // typed keys waiting to see if they make a mapped sequence
+    pending_since: Instant,
+    pub noremap: usize, // >0 while fed keys skip maps
+}
+
+impl Keymaps {
+    // Keys that used to work in every mode, now just default maps
+    pub fn new() -> Keymaps {
+        let mut keymaps = Keymaps {
+            maps: HashMap::new(),
+            pending: Vec::new(),
+            pending_since: Instant::now(),
+            noremap: 0,
+        };
+        let mut defaults: Vec<(KeyEvent, Action)> = vec![
+            (ctrl('z'), Action::Undo),
+            (ctrl('y'), Action::Redo),
+            (plain(KeyCode::PageUp), Action::Scroll(-10)),
+            (plain(KeyCode::PageDown), Action::Scroll(10)),
+            (plain(KeyCode::Home), Action::ScrollTop),
+            (plain(KeyCode::End), Action::ScrollBottom),
+        ];
+        for n in 1..=12 {
+            defaults.push((plain(KeyCode::F(n)), Action::Tab(n as usize)));
+        }
+        for mode in [MapMode::Normal, MapMode::Insert, MapMode::Visual] {
+            for (key, action) in defaults.iter() {
+                keymaps.set(mode, vec![*key], MapTarget::Action(*action));
+            }
+        }
+        keymaps
+    }
+
+    pub fn entries(&self, mode: MapMode) -> &[(Vec<KeyEvent>, MapTarget)] {
+        self.maps.get(&mode).map_or(&[], |m| m.as_slice())
+    }
+
+    pub fn set(&mut self, mode: MapMode, lhs: Vec<KeyEvent>, target: MapTarget) {
+        let map = self.maps.entry(mode).or_default();
+        match map
+            .iter_mut()
+            .find(|(keys, _)| keys.len() == lhs.len() && starts_with(keys, &lhs))
+        {
+            Some(entry) => entry.1 = target,
+            None => map.push((lhs, target)),
+        }
+    }
+
+    pub fn remove(&mut self, mode: MapMode, lhs: &[KeyEvent]) -> bool {
+        let map = self.maps.entry(mode).or_default();
+        let len = map.len();
+        map.retain(|(keys, _)| !(keys.len() == lhs.len() && starts_with(keys, lhs)));
+        map.len() != len
+    }
+}
+
+fn plain(code: KeyCode) -> KeyEvent {
+    KeyEvent::new(code, KeyModifiers::NONE)
+}
+
+fn ctrl(ch: char) -> KeyEvent {
+    KeyEvent::new(KeyCode::Char(ch), KeyModifiers::CONTROL)
+}
+
+impl App {
+    // Typed key goes through maps of current mode
+    pub fn map_key(&mut self, key: KeyEvent) {
+        self.keymaps.pending.push(key);
+        self.keymaps.pending_since = Instant::now();
+        self.resolve_pending(false);
+    }
+
+    // Main loop: keys waiting for the rest of a sequence go on after maptimeout
+    pub fn update_keymap_timeout(&mut self) {
+        let timeout = Duration::from_millis(self.config.maptimeout);
+        if !self.keymaps.pending.is_empty() && self.keymaps.pending_since.elapsed() >= timeout {
+            self.resolve_pending(true);
+            self.scroll_to_cursor();
+        }
+    }
+
+    // Fed keys are over, nothing more is coming for the waiting ones
+    pub fn flush_pending_keys(&mut self) {
+        self.resolve_pending(true);
+    }
+
+    fn resolve_pending(&mut self, timed_out: bool) {
+        while !self.keymaps.pending.is_empty() {
+            let Some(mode) = MapMode::of(self.mode) else {
+                for key in std::mem::take(&mut self.keymaps.pending) {
+                    self.dispatch_key(key);
+                }
+                return;
+            };
+            let pending = &self.keymaps.pending;
+            let entries = self.keymaps.entries(mode);
+            let longer = entries
+                .iter()
+                .any(|(lhs, _)| lhs.len() > pending.len() && starts_with(lhs, pending));
+            if longer && !timed_out {
+                return;
+            }
+            // longest entry the typed keys start with
+            let found = entries
+                .iter()
+                .filter(|(lhs, _)| starts_with(pending, lhs))
+                .max_by_key(|(lhs, _)| lhs.len())
+                .map(|(lhs, target)| (lhs.len(), target.clone()));
+            match found {
+                Some((len, target)) => {
+                    self.keymaps.pending.drain(..len);
+                    self.run_map_target(target);
+                }
+                None => {
+                    let key = self.keymaps.pending.remove(0);
+                    self.dispatch_key(key);
+                }
+            }
+        }
+    }
+
+    fn run_map_target(&mut self, target: MapTarget) {
+        match target {
+            MapTarget::Keys(keys) => self.feed_keys_noremap(&keys, 1),
+            MapTarget::Command(line) => self.run_mapped_command(&line),
+            MapTarget::Action(action) => self.run_action(action),
+            MapTarget::Lua(func, _) => {
+                if let Err(e) = func.call::<()>(()) {
+                    self.throw_status_error(e.to_string());
+                }
+            }
+        }
+    }
+
+    // Insert mode stays on after the command, other modes end like after Enter
+    fn run_mapped_command(&mut self, line: &str) {
+        let was_insert = self.mode == Mode::Insert;
+        match self.mode {
+            Mode::Insert => self.seal_edit(),
+            // '<,'> marks are set for the command
+            Mode::Visual(_) => self.exit_visual(),
+            _ => {}
+        }
+        self.command_buf = line.to_string();
+        self.run_command_buf();
+        if was_insert && self.mode == Mode::Normal {
+            self.mode = Mode::Insert;
+        }
+    }
+
+    fn run_action(&mut self, action: Action) {
+        match action {
+            Action::Undo => self.tab_undo(),
+            Action::Redo => self.tab_redo(),
+            Action::Scroll(n) => self.tab_update_scroll_delta(n),
+            Action::ScrollTop => self.tab_update_scroll(0),
+            // clamped inside
+            Action::ScrollBottom => self.tab_update_scroll(usize::MAX),
+            Action::Tab(n) => {
+                if matches!(self.mode, Mode::Visual(_)) {
+                    self.exit_visual();
+                }
+                if n > self.tabs.len() {
+                    self.tabs.push(Tab::new(None));
+                    self.cur_tab = self.tabs.len() - 1;
+                    return;
+                }
+                self.push_jump();
+                self.cur_tab = n - 1;
+            }
+            Action::TabNext | Action::TabPrev => {
+                if matches!(self.mode, Mode::Visual(_)) {
+                    self.exit_visual();
+                }
+                self.push_jump();
+                let len = self.tabs.len();
+                self.cur_tab = match action == Action::TabNext {
+                    true => (self.cur_tab + 1) % len,
+                    false => (self.cur_tab + len - 1) % len,
+                };
+            }
+        }
+    }
+
+    fn target_text(&self, target: &MapTarget) -> String {
+        match target {
+            MapTarget::Keys(keys) => keys_to_text(keys),
+            MapTarget::Command(line) => format!("<Cmd>{}", line),
+            MapTarget::Action(action) => format!("<Action>{}", action.to_text()),
+            MapTarget::Lua(_, id) => {
+                let PlugLoaders::LuaL(lualoader) = &self.plugin_subsys;
+                match lualoader.plugins.get(*id) {
+                    Some(plug) => format!("<Lua>{}", plug.name),
+                    None => "<Lua>".to_string(),
+                }
+            }
+        }
+    }
+
+    // !map mode keys target, also for config and plugins
+    pub fn add_map(&mut self, mode: &str, lhs: &str, target: MapTarget) -> Result<(), String> {
+        let mode = MapMode::from_name(mode).ok_or(format!("Unknown mode {}", mode))?;
+        let lhs = parse_lhs(lhs)?;
+        self.keymaps.set(mode, lhs, target);
+        Ok(())
+    }
+
+    pub fn remove_map(&mut self, mode: &str, lhs: &str) -> Result<(), String> {
+        let mode = MapMode::from_name(mode).ok_or(format!("Unknown mode {}", mode))?;
+        match self.keymaps.remove(mode, &parse_lhs(lhs)?) {
+            true => Ok(()),
+            false => Err(format!("No {} map for {}", mode.name(), lhs)),
+        }
+    }
+
+    // What keys are mapped to in mode, as written in !map
+    pub fn mapped_to(&self, mode: &str, lhs: &str) -> Result<String, String> {
+        let mode = MapMode::from_name(mode).ok_or(format!("Unknown mode {}", mode))?;
+        let lhs = parse_lhs(lhs)?;
+        self.keymaps
+            .entries(mode)
+            .iter()
+            .find(|(keys, _)| keys.len() == lhs.len() && starts_with(keys, &lhs))
+            .map(|(_, target)| self.target_text(target))
+            .ok_or(format!("No {} map for {}", mode.name(), keys_to_text(&lhs)))
+    }
+
+    // Lines of !maps: mode, keys and what they do
+    pub fn map_lines(&self, only: Option<MapMode>) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        for mode in MAP_MODES.iter().filter(|m| only.is_none_or(|o| o == **m)) {
+            for (lhs, target) in self.keymaps.entries(*mode) {
+                lines.push(format!(
+                    "{:<8} {:<12} {}",
+                    mode.name(),
+                    keys_to_text(lhs),
+                    self.target_text(target)
+                ));
+            }
+        }
+        lines
+    }
+
+    // Maps from [keys.mode] tables of config.toml; bad ones are logged like other entries
+    pub fn apply_config_maps(&mut self) {
+        for (mode, lhs, rhs) in self.config.keymaps.clone() {
+            let res = MapTarget::parse(&rhs).and_then(|t| self.add_map(&mode, &lhs, t));
+            if let Err(e) = res {
+                let msg = format!("config.toml: keys.{} {}: {}", mode, lhs, e);
+                let _ = self.logger.log_msg(LogLevel::Warning, msg.clone());
+                self.config.errors.push(msg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // App in insert mode on an empty buffer, with maps of insert mode
+    fn insert_app(maps: &[(&str, &str)]) -> App {
+        let mut app = App::new_test();
+        for (lhs, rhs) in maps {
+            app.add_map("insert", lhs, MapTarget::parse(rhs).unwrap())
+                .unwrap();
+        }
+        app.mode = Mode::Insert;
+        app
+    }
+
+    fn type_keys(app: &mut App, keys: &str) {
+        for key in text_to_keys(keys) {
+            app.map_key(key);
+        }
+    }
+
+    fn line(app: &App) -> String {
+        app.tabs[app.cur_tab].buf.line(0)
+    }
+
+    #[test]
+    fn prefix_waits_for_rest() {
+        let mut app = insert_app(&[("jk", "<Esc>")]);
+        type_keys(&mut app, "aj");
+        // j could still become jk
+        assert_eq!(line(&app), "a");
+        assert_eq!(app.keymaps.pending.len(), 1);
+        type_keys(&mut app, "k");
+        assert_eq!(line(&app), "a");
+        assert!(app.keymaps.pending.is_empty());
+        assert_eq!(app.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn no_match_goes_on_as_typed() {
+        let mut app = insert_app(&[("jk", "<Esc>")]);
+        type_keys(&mut app, "jxjj");
+        // first j of jj is typed, second one waits
+        assert_eq!(line(&app), "jxj");
+        assert_eq!(app.keymaps.pending.len(), 1);
+        type_keys(&mut app, "k");
+        assert_eq!(line(&app), "jxj");
+        assert_eq!(app.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn timeout_takes_shorter_map() {
+        let mut app = insert_app(&[("j", "J"), ("jkl", "<Esc>")]);
+        type_keys(&mut app, "jk");
+        assert_eq!(line(&app), "");
+        assert_eq!(app.keymaps.pending.len(), 2);
+        // not long enough yet
+        app.config.maptimeout = 60_000;
+        app.update_keymap_timeout();
+        assert_eq!(app.keymaps.pending.len(), 2);
+        // j runs its own map, k matches nothing
+        app.config.maptimeout = 0;
+        app.update_keymap_timeout();
+        assert_eq!(line(&app), "Jk");
+        assert!(app.keymaps.pending.is_empty());
+        assert_eq!(app.mode, Mode::Insert);
+    }
+
+    #[test]
+    fn longest_map_wins() {
+        let mut app = insert_app(&[("a", "1"), ("ab", "2"), ("abc", "3")]);
+        type_keys(&mut app, "abcab");
+        assert_eq!(line(&app), "3");
+        app.flush_pending_keys();
+        assert_eq!(line(&app), "32");
+        type_keys(&mut app, "ax");
+        app.flush_pending_keys();
+        assert_eq!(line(&app), "321x");
+    }
+
+    #[test]
+    fn mapped_keys_not_mapped_again() {
+        let mut app = insert_app(&[("a", "b"), ("b", "a")]);
+        type_keys(&mut app, "ab");
+        assert_eq!(line(&app), "ba");
+        // normal mode doesn't see insert maps, a appends there
+        app.mode = Mode::Normal;
+        type_keys(&mut app, "a");
+        assert_eq!(app.mode, Mode::Insert);
+        assert_eq!(line(&app), "ba");
+    }
+}
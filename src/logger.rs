@@ -2,7 +2,7 @@ use std::{fs::File, io::Write};
 
 use chrono::{DateTime, Local};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LogLevel {
     Info,
     Warning,
@@ -11,9 +11,41 @@ pub enum LogLevel {
     PluginFault,
 }
 
+impl LogLevel {
+    // Names log_level option takes, plugin faults count as errors
+    pub fn from_name(name: &str) -> Option<LogLevel> {
+        match name {
+            "info" => Some(LogLevel::Info),
+            "warning" => Some(LogLevel::Warning),
+            "error" => Some(LogLevel::Error),
+            "critical" => Some(LogLevel::CriticalError),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Info => "info",
+            LogLevel::Warning => "warning",
+            LogLevel::Error | LogLevel::PluginFault => "error",
+            LogLevel::CriticalError => "critical",
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            LogLevel::Info => 0,
+            LogLevel::Warning => 1,
+            LogLevel::Error | LogLevel::PluginFault => 2,
+            LogLevel::CriticalError => 3,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Logger {
-    log_file: File,
+    log_file: Option<File>, // None in tests
+    pub level: LogLevel,    // less severe messages are dropped
 }
 
 impl Logger {
@@ -24,10 +56,24 @@ impl Logger {
                 return Err(e.to_string());
             }
         };
-        let res = Logger { log_file: log };
+        let res = Logger {
+            log_file: Some(log),
+            level: LogLevel::Info,
+        };
         Ok(res)
     }
+
+    #[cfg(test)]
+    pub fn none() -> Logger {
+        Logger {
+            log_file: None,
+            level: LogLevel::Info,
+        }
+    }
     pub fn log_msg(&mut self, ltype: LogLevel, msg: String) -> Result<(), String> {
+        if ltype.rank() < self.level.rank() {
+            return Ok(());
+        }
         let now: DateTime<Local> = Local::now();
         let res_msg: String = match ltype {
             LogLevel::Info => {
@@ -66,7 +112,10 @@ impl Logger {
                 )
             }
         };
-        match self.log_file.write_all(&res_msg.into_bytes()) {
+        let Some(log_file) = &mut self.log_file else {
+            return Ok(());
+        };
+        match log_file.write_all(&res_msg.into_bytes()) {
             Ok(_) => {}
             Err(e) => {
                 return Err(e.to_string());
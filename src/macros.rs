@@ -0,0 +1,240 @@
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+
+use crate::app::{App, Mode};
+use crate::normal::{InsertKind, NormalCmd, NormalInput};
+
+// Stops macros calling themselves forever
+const REPLAY_DEPTH_MAX: usize = 100;
+
+// Last change for `.`: the command and keys typed in insert mode it started
+#[derive(Debug, Clone)]
+pub struct DotCmd {
+    pub input: NormalInput,
+    pub inserted: Vec<KeyEvent>,
+}
+
+// How special keys are written in macro text, like <Esc> in vim
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Esc", KeyCode::Esc),
+    ("CR", KeyCode::Enter),
+    ("BS", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("S-Tab", KeyCode::BackTab),
+    ("Del", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("lt", KeyCode::Char('<')),
+    ("Space", KeyCode::Char(' ')),
+];
+
+// Keys into editable text: "ihello<Esc>j0"
+pub fn keys_to_text(keys: &[KeyEvent]) -> String {
+    let mut res = String::new();
+    for key in keys {
+        match key.code {
+            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                res.push_str(&format!("<C-{}>", c))
+            }
+            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::ALT) => {
+                res.push_str(&format!("<A-{}>", c))
+            }
+            KeyCode::Char('<') => res.push_str("<lt>"),
+            KeyCode::Char(c) => res.push(c),
+            KeyCode::F(n) => res.push_str(&format!("<F{}>", n)),
+            code => {
+                // keys without a name (media keys and such) are dropped
+                if let Some((name, _)) = KEY_NAMES.iter().find(|(_, k)| *k == code) {
+                    res.push_str(&format!("<{}>", name));
+                }
+            }
+        }
+    }
+    res
+}
+
+fn parse_key_name(name: &str) -> Option<KeyEvent> {
+    if let Some((_, code)) = KEY_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        return Some(KeyEvent::new(*code, KeyModifiers::NONE));
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next(), chars.next()) {
+        (Some(m), Some('-'), Some(c), None) => {
+            let modifiers = match m.to_ascii_uppercase() {
+                'C' => KeyModifiers::CONTROL,
+                'A' | 'M' => KeyModifiers::ALT,
+                _ => return None,
+            };
+            Some(KeyEvent::new(KeyCode::Char(c), modifiers))
+        }
+        (Some('F') | Some('f'), Some(_), ..) => {
+            let n: u8 = name[1..].parse().ok()?;
+            Some(KeyEvent::new(KeyCode::F(n), KeyModifiers::NONE))
+        }
+        _ => None,
+    }
+}
+
+// Text back into keys. Unknown <...> is taken literally, newline is Enter.
+pub fn text_to_keys(text: &str) -> Vec<KeyEvent> {
+    let mut res: Vec<KeyEvent> = Vec::new();
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '<'
+            && let Some(end) = rest.find('>')
+            && let Some(key) = parse_key_name(&rest[1..end])
+        {
+            res.push(key);
+            rest = &rest[end + 1..];
+            continue;
+        }
+        let code = match ch {
+            '\n' => KeyCode::Enter,
+            c => KeyCode::Char(c),
+        };
+        res.push(KeyEvent::new(code, KeyModifiers::NONE));
+        rest = &rest[ch.len_utf8()..];
+    }
+    res
+}
+
+impl App {
+    // q{reg}
+    pub fn start_recording(&mut self, reg: char) {
+        if !reg.is_ascii_alphanumeric() {
+            self.throw_status_message(format!("Invalid register {}", reg));
+            return;
+        }
+        self.recording = Some(reg);
+        self.macro_keys.clear();
+    }
+
+    // q while recording
+    pub fn stop_recording(&mut self) {
+        let reg = match self.recording.take() {
+            Some(r) => r,
+            None => return,
+        };
+        // the q that stopped it
+        self.macro_keys.pop();
+        let text = keys_to_text(&self.macro_keys);
+        self.macro_keys.clear();
+        if let Err(e) = self.registers.record(reg, text) {
+            self.throw_status_error(e);
+        }
+    }
+
+    // @{reg}, @@ replays the last one; count replays it more times
+    pub fn replay_macro(&mut self, reg: char, count: Option<usize>) {
+        let reg = match (reg, self.last_macro) {
+            ('@', Some(r)) => r,
+            ('@', None) => {
+                self.throw_status_message("No previously used register".to_string());
+                return;
+            }
+            (r, _) => r,
+        };
+        if self.replay_depth >= REPLAY_DEPTH_MAX {
+            self.throw_status_message("Macro calls itself too deep".to_string());
+            return;
+        }
+        let filename = self.tabs[self.cur_tab].filename.clone();
+        let text = match self.registers.get(Some(reg), &filename) {
+            Some(content) => content.text,
+            None => {
+                self.throw_status_message(format!("Register {} is empty", reg));
+                return;
+            }
+        };
+        self.last_macro = Some(reg);
+        self.feed_keys(&text_to_keys(&text), count.unwrap_or(1).max(1));
+    }
+
+    // Synthetic input, goes the same way as typed keys but isn't recorded into macro.
+    // Typed keys still waiting for a mapping wait till fed ones are done.
+    fn feed_keys(&mut self, keys: &[KeyEvent], times: usize) {
+        let typed = std::mem::take(&mut self.keymaps.pending);
+        self.replay_depth += 1;
+        for _ in 0..times {
+            for &key in keys {
+                self.handle_input(Event::Key(key));
+            }
+        }
+        self.flush_pending_keys();
+        self.replay_depth -= 1;
+        self.keymaps.pending = typed;
+    }
+
+    // Keys from maps and `.` are done already, maps aren't looked at for them
+    pub fn feed_keys_noremap(&mut self, keys: &[KeyEvent], times: usize) {
+        self.keymaps.noremap += 1;
+        self.feed_keys(keys, times);
+        self.keymaps.noremap -= 1;
+    }
+
+    // Remembers command for `.` after it ran; if it started insert mode,
+    // typed keys are added till it ends
+    pub fn remember_change(&mut self, input: NormalInput) {
+        let dot = DotCmd {
+            input,
+            inserted: Vec::new(),
+        };
+        match self.mode {
+            Mode::Insert => self.dot_pending = Some(dot),
+            _ => self.dot = Some(dot),
+        }
+    }
+
+    // Every key of insert session started by a change goes here
+    pub fn record_dot_key(&mut self, key: KeyEvent) {
+        let mut dot = match self.dot_pending.take() {
+            Some(d) => d,
+            None => return,
+        };
+        if key.code == KeyCode::Esc || key.code == KeyCode::Insert {
+            self.repeat_insert(&dot);
+        }
+        dot.inserted.push(key);
+        self.dot_pending = Some(dot);
+    }
+
+    // 3ihi<Esc> types "hi" two more times before leaving insert mode, 3o opens 3 lines
+    fn repeat_insert(&mut self, dot: &DotCmd) {
+        let kind = match dot.input.cmd {
+            NormalCmd::Insert(kind) => kind,
+            _ => return,
+        };
+        let count = dot.input.count.unwrap_or(1);
+        if count <= 1 {
+            return;
+        }
+        let mut keys: Vec<KeyEvent> = Vec::new();
+        if kind == InsertKind::NewLineBelow || kind == InsertKind::NewLineAbove {
+            keys.push(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        }
+        keys.extend(dot.inserted.iter().copied());
+        self.feed_keys_noremap(&keys, count - 1);
+    }
+
+    // `.`: count replaces the one of repeated command
+    pub fn dot_repeat(&mut self, count: Option<usize>) {
+        let dot = match self.dot.clone() {
+            Some(d) => d,
+            None => return,
+        };
+        let mut input = dot.input;
+        if count.is_some() {
+            input.count = count;
+        }
+        self.run_normal_cmd(input);
+        if self.mode == Mode::Insert {
+            self.feed_keys_noremap(&dot.inserted, 1);
+        }
+    }
+}
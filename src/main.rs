@@ -1,27 +1,60 @@
 mod app;
+mod buffer;
 mod commands;
+mod diff;
+mod disk;
 mod dotfiles;
 mod edits;
+mod fileformat;
+mod grapheme;
+mod history;
+mod keymap;
 mod logger;
+mod macros;
+mod marks;
+mod motions;
+mod normal;
+mod operators;
+mod options;
 mod plugin;
+mod prompt;
+mod range;
+mod registers;
+mod save;
+mod search;
+mod session;
+mod substitute;
+mod swap;
+mod syntax;
 mod tabs;
-use crate::app::App;
+mod textobj;
+mod theme;
+mod view;
+mod visual;
+mod window;
+mod wrap;
+use crate::app::{App, Mode};
+use crate::buffer::TextBuffer;
+use crate::visual::VisualKind;
+use crate::window::WinArea;
 use crossterm::{
     ExecutableCommand,
-    event::{self, DisableMouseCapture, EnableMouseCapture, read},
+    event::{self, DisableFocusChange, DisableMouseCapture, EnableFocusChange},
 };
 use ratatui::{
     self, Frame,
     crossterm::execute,
     layout::{
         Constraint::{Fill, Length, Min},
-        Layout, Position,
+        Layout, Position, Rect,
     },
     prelude::Stylize,
-    style::{Color, Style},
-    text::Text,
+    style::Style,
+    text::{Line, Span, Text},
     widgets::{Block, Borders, Paragraph, Tabs},
 };
+use std::time::Duration;
+use unicode_width::UnicodeWidthStr;
 
 fn main() {
     let mut app = App::new();
@@ -29,39 +62,62 @@ fn main() {
     match std::io::stdout().execute(crossterm::event::EnableMouseCapture) {
         Ok(_) => {}
         Err(e) => {
-            app.throw_status_message(
+            app.throw_status_warning(
                 "Mouse capture may not be available on this terminal".to_string(),
             );
         }
     }
 
+    // FocusGained makes tabs check their files on disk
+    let _ = std::io::stdout().execute(EnableFocusChange);
+
     match std::env::args().nth(1) {
         // opening file from cli
         Some(s) => match app.tabs[0].readf(s.clone()) {
             Ok(()) => {}
             Err(e) => {
                 let err_msg: String = "While opening file: ".to_string() + &e.to_string();
-                app.throw_status_message(err_msg.clone());
+                app.throw_status_error(err_msg.clone());
             }
         },
-        None => {}
+        None => app.restore_autosession(),
     };
+    app.tell_orphan_swaps();
+    app.check_swap(0);
 
     let mut terminal = ratatui::init();
     while app.running {
+        app.update_windows();
+        app.update_syntax();
         terminal
             .draw(|f| draw(f, &app))
             .expect("failed to render frame");
-        let event = event::read().expect("failed to read event");
-        app.handle_input(event);
+        // keys are waited for with timeout, so swaps get written when typing stops;
+        // keys of an unfinished mapping need it shorter
+        let wait = match app.keymaps.pending.is_empty() {
+            true => 500,
+            false => 50,
+        };
+        if event::poll(Duration::from_millis(wait)).expect("failed to poll event") {
+            let event = event::read().expect("failed to read event");
+            app.handle_input(event);
+        }
+        app.update_keymap_timeout();
+        app.update_swaps();
+        app.update_autosave();
+        app.update_disk_watch();
         app.recv_msg();
     }
+    app.store_autosession();
+    app.drop_swaps();
     std::io::stdout().execute(DisableMouseCapture).unwrap();
+    let _ = std::io::stdout().execute(DisableFocusChange);
     ratatui::restore();
 }
 
 fn draw(frame: &mut Frame, app: &App) {
     let curtab = &app.tabs[app.cur_tab];
+    let theme = &app.theme;
 
     let mut vert_length: u16 = 2;
     let stat_length: u16 = 1;
@@ -70,18 +126,8 @@ fn draw(frame: &mut Frame, app: &App) {
     }
 
     let vertical = Layout::vertical([Length(vert_length), Min(0), Length(stat_length)]);
-    let [title_area, main_area, status_area] = vertical.areas(frame.area());
-    let (left_area, right_area);
-    if app.left_area_open {
-        let horizontal = Layout::horizontal([Fill(1); 2]);
-        let [l, r] = horizontal.areas(main_area);
-        left_area = Some(l);
-        right_area = r;
-    } else {
-        right_area = main_area;
-        left_area = None;
-    }
-    let available_length: u16 = frame.area().height - vert_length - stat_length;
+    let [title_area, _, status_area] = vertical.areas(frame.area());
+    let main_area = app.main_area(frame.area());
 
     let title_area_chunks = Layout::vertical([Fill(1), Length(vert_length - 1)]).split(title_area);
     let tabs = Tabs::new(
@@ -90,8 +136,8 @@ fn draw(frame: &mut Frame, app: &App) {
             .map(|cur_tab| cur_tab.displayed_name.as_str()),
     )
     .select(app.cur_tab)
-    .style(Style::default())
-    .highlight_style(Style::default().fg(Color::LightCyan).bold())
+    .style(theme.style("tabline"))
+    .highlight_style(theme.style("tabline_selected"))
     .divider("|");
 
     let title_text = match curtab.changed {
@@ -102,69 +148,335 @@ fn draw(frame: &mut Frame, app: &App) {
         .block(
             Block::default()
                 .borders(Borders::BOTTOM)
-                .border_style(Style::default().fg(Color::White)),
+                .border_style(theme.style("title_border")),
         )
-        .style(Style::default().fg(Color::Yellow).bold());
+        .style(theme.style("title"));
     frame.render_widget(title, title_area_chunks[1]);
     frame.render_widget(tabs, title_area_chunks[0]);
 
-    let buf_len = curtab.buf.len();
-    let start_line = curtab.scroll_offset.min(buf_len);
-    let end_line = (curtab.scroll_offset + available_length as usize).min(buf_len);
+    let (wins, seps) = app.win_areas(main_area);
+    let mut cursor_pos: Option<Position> = None;
+    for win in wins.iter() {
+        let pos = draw_window(frame, app, win);
+        if win.focused {
+            cursor_pos = pos;
+        }
+        if let Some(bar) = win.bar {
+            let tab = &app.tabs[win.tab];
+            let name = match tab.changed {
+                true => format!(" {} *", tab.displayed_name),
+                false => format!(" {}", tab.displayed_name),
+            };
+            let style = match win.focused {
+                true => theme.style("winbar_focused"),
+                false => theme.style("winbar"),
+            };
+            frame.render_widget(Paragraph::new(name).style(style), bar);
+        }
+    }
+    for sep in seps {
+        let line = Block::default()
+            .borders(Borders::LEFT)
+            .border_style(theme.style("separator"));
+        frame.render_widget(line, sep);
+    }
+
+    let mut status_str = app.command_buf.clone();
+    // cursor_pos_xy.0 counts chars of command line, screen wants cells
+    let command_col = grapheme::display_col(
+        &app.command_buf,
+        app.cursor_pos_xy.0 as usize,
+        app.config.tabstop,
+    ) as u16;
+
+    let normal_keys = app.normal_keys.replace(normal::CTRL_W, "^W");
+    match app.mode {
+        Mode::Search { backward } => {
+            status_str.insert(0, if backward { '?' } else { '/' });
+            frame.set_cursor_position(Position::new(
+                status_area.x + command_col + 1,
+                status_area.y,
+            ));
+        }
+        Mode::Insert | Mode::Normal | Mode::Visual(_) | Mode::Confirm => {
+            if app.mode == Mode::Insert {
+                status_str.push_str("\t -- INSERT -- \t");
+            } else if let Mode::Visual(kind) = app.mode {
+                let name = match kind {
+                    VisualKind::Char => "VISUAL",
+                    VisualKind::Line => "VISUAL LINE",
+                    VisualKind::Block => "VISUAL BLOCK",
+                };
+                status_str.push_str(&format!("\t -- {} -- \t{}", name, normal_keys));
+            } else if !normal_keys.is_empty() {
+                status_str.push_str(&format!("\t{}", normal_keys));
+            }
+            if !app.keymaps.pending.is_empty() {
+                status_str.push_str(&format!("\t{}", macros::keys_to_text(&app.keymaps.pending)));
+            }
+            if let Some(reg) = app.recording {
+                status_str.push_str(&format!("\trecording @{}", reg));
+            }
+
+            if let Some(pos) = cursor_pos {
+                frame.set_cursor_position(pos);
+            }
+        }
+        Mode::Command => match app.command_hist.rev_search_line() {
+            Some((line, col)) => {
+                status_str = line;
+                frame.set_cursor_position(Position::new(status_area.x + col as u16, status_area.y));
+            }
+            None => {
+                frame.set_cursor_position(Position::new(status_area.x + command_col, status_area.y))
+            }
+        },
+        Mode::Prompt => {
+            if let Some(prompt) = app.prompts.first() {
+                status_str = prompt.text.clone();
+            }
+            let end = (status_str.width() as u16).min(status_area.width.saturating_sub(1));
+            frame.set_cursor_position(Position::new(status_area.x + end, status_area.y));
+        }
+    }
+
+    // file format at the right end of status line, if command line leaves room for it
+    let format_str = match app.mode {
+        Mode::Command | Mode::Search { .. } | Mode::Prompt => String::new(),
+        _ => curtab.format.to_string(),
+    };
+    let format_width = format_str.width() as u16;
+    let status_width = status_str.replace('\t', "    ").width() as u16;
+    if !format_str.is_empty() && status_width + format_width < status_area.width {
+        let format_area = Rect::new(
+            status_area.x + status_area.width - format_width,
+            status_area.y,
+            format_width,
+            1,
+        );
+        frame.render_widget(
+            Text::styled(format_str, theme.style("fileformat")),
+            format_area,
+        );
+    }
+
+    // messages get colors of their level, typed command line is plain
+    let status_style = match app.mode {
+        Mode::Normal | Mode::Insert | Mode::Visual(_) | Mode::Confirm if app.status_message => {
+            theme
+                .style("status")
+                .patch(theme.style(app.status_level.group()))
+        }
+        _ => theme.style("status"),
+    };
+    let status_text = Text::styled(status_str, status_style);
+    frame.render_widget(status_text, status_area);
+}
+
+// Text of a window with its gutter; returns where the cursor is on screen
+fn draw_window(frame: &mut Frame, app: &App, win: &WinArea) -> Option<Position> {
+    let theme = &app.theme;
+    let tab = &app.tabs[win.tab];
+    let area = win.text;
+    let buf_len = tab.buf.len_lines();
+    let start_line = win.scroll.0.min(buf_len);
+    let tabstop = app.tab_tabstop(tab);
+    let wraps = app.tab_wraps(tab);
+    let numbers = app.tab_numbers(tab);
+    let cursor_line = win.cursor.1;
 
-    let visible_text = curtab.buf[start_line..end_line]
-        .iter()
-        .enumerate()
-        .map(|(i, line)| format!("{}: {}", i + start_line + 1, line))
-        .collect::<Vec<String>>()
-        .join("\n");
+    let sel_style = theme.style("selection");
+    let match_style = theme.style("search");
+    let break_style = theme.style("showbreak");
+    let cursorline_style = match win.focused {
+        true => theme.style("cursorline"),
+        false => Style::default(),
+    };
+    // one Line per screen row, wrapped buffer lines take several
+    let mut visible_text: Vec<Line> = Vec::new();
+    let mut cursor_line_row = None;
+    let mut i = start_line;
+    while i < buf_len && visible_text.len() < area.height as usize {
+        let gutter_str = wrap::gutter(i, cursor_line, numbers);
+        let gutter = gutter_str.len();
+        let width = (area.width as usize).saturating_sub(gutter);
+        // only the part of line that can be on screen is segmented and highlighted
+        let reach = match wraps {
+            true => (area.height as usize - visible_text.len()) * width.max(1),
+            false => win.scroll.1 + width,
+        };
+        let text = shown_text(&tab.buf, i, reach, tabstop);
+        let mut ranges: Vec<(usize, usize, Style)> = app.syntax_ranges(tab, i, &text);
+        ranges.extend(
+            app.search_matches_in(&text)
+                .into_iter()
+                .map(|(from, to)| (from, to, match_style)),
+        );
+        if win.focused
+            && let Some((from, to)) = app.visual_cols(i)
+        {
+            ranges.push((from, to, sel_style));
+        }
+        let len = text.chars().count();
+        if len < tab.buf.line_len(i) {
+            // cut line has no newline cell to style
+            for range in ranges.iter_mut() {
+                range.1 = range.1.min(len);
+            }
+        }
+        let cells = styled_cells(&text, &ranges, tabstop);
+        let gutter_style = match i == cursor_line {
+            true => theme.style("gutter_current"),
+            false => theme.style("gutter"),
+        };
+        let first_row = visible_text.len();
+        if i == cursor_line {
+            cursor_line_row = Some(first_row);
+        }
+        if wraps {
+            let rows = wrap::line_rows(&text, width, tabstop);
+            for (r, &from) in rows.starts.iter().enumerate() {
+                let to = rows.starts.get(r + 1).copied().unwrap_or(usize::MAX);
+                // window too narrow for showbreak keeps only the indent
+                let break_width = wrap::SHOWBREAK.width();
+                let mut spans = match r {
+                    0 => vec![Span::styled(gutter_str.clone(), gutter_style)],
+                    _ if rows.indent < break_width => {
+                        vec![Span::raw(" ".repeat(gutter + rows.indent))]
+                    }
+                    _ => vec![
+                        Span::raw(" ".repeat(gutter + rows.indent - break_width)),
+                        Span::styled(wrap::SHOWBREAK, break_style),
+                    ],
+                };
+                spans.extend(cells_to_spans(
+                    cells.iter().filter(|c| c.0 >= from && c.0 < to).cloned(),
+                ));
+                visible_text.push(Line::from(spans));
+            }
+        } else {
+            let mut spans = vec![Span::styled(gutter_str, gutter_style)];
+            spans.extend(cells_to_spans(clip_cells(cells, win.scroll.1)));
+            visible_text.push(Line::from(spans));
+        }
+        if i == cursor_line && cursorline_style != Style::default() {
+            for line in visible_text.iter_mut().skip(first_row) {
+                fill_row(line, area.width as usize, cursorline_style);
+            }
+        }
+        i += 1;
+    }
 
     let paragraph = Paragraph::new(visible_text)
-        .wrap(ratatui::widgets::Wrap { trim: true })
+        .style(theme.style("normal"))
         .alignment(ratatui::layout::Alignment::Left);
-    frame.render_widget(paragraph, right_area);
+    frame.render_widget(paragraph, area);
 
-    if (app.left_area_open) {
-        let left_tab = &app.left_area;
-        let buf_len_left = left_tab.buf.len();
-        let start_line_left = left_tab.scroll_offset.min(buf_len_left);
-        let end_line_left = (left_tab.scroll_offset + available_length as usize).min(buf_len_left);
+    let (x, line) = win.cursor;
+    let text = tab.buf.line_head(line, tab.buf.next_grapheme(line, x, 1));
+    let gutter = wrap::gutter_width(line, line, numbers);
+    let (row, cell) = match wraps {
+        true => {
+            let width = (area.width as usize).saturating_sub(gutter);
+            wrap::cursor_cell(&text, x, width, tabstop)
+        }
+        false => {
+            let col = grapheme::display_col(&text, x, tabstop);
+            (0, col.saturating_sub(win.scroll.1))
+        }
+    };
+    Some(Position::new(
+        area.x + (gutter + cell) as u16,
+        area.y + (cursor_line_row? + row) as u16,
+    ))
+}
 
-        let visible_text_left = curtab.buf[start_line_left..end_line_left]
-            .iter()
-            .enumerate()
-            .map(|(i, line)| format!("{}: {}", i + start_line + 1, line))
-            .collect::<Vec<String>>()
-            .join("\n");
-
-        let paragraph_left = Paragraph::new(visible_text_left)
-            .wrap(ratatui::widgets::Wrap { trim: true })
-            .alignment(ratatui::layout::Alignment::Left);
-        frame.render_widget(paragraph_left, left_area.unwrap());
-    }
+// Chars of the line after the last shown one that are still read, so patterns and
+// syntax tokens don't stop right at the screen edge
+const SHOWN_MARGIN: usize = 64;
 
-    let mut status_str = app.command_buf.clone();
+// Start of line covering `cells` display cells and SHOWN_MARGIN chars more,
+// or whole line if it's shorter
+fn shown_text(buf: &TextBuffer, line: usize, cells: usize, tabstop: usize) -> String {
+    let len = buf.line_len(line);
+    let mut chars = cells + SHOWN_MARGIN;
+    loop {
+        let text = buf.line_head(line, chars);
+        if chars >= len || grapheme::x_at_col(&text, cells, tabstop) + SHOWN_MARGIN <= chars {
+            return text;
+        }
+        chars *= 2;
+    }
+}
 
-    if app.insert_mode {
-        status_str.push_str("\t -- INSERT -- \t");
-
-        let digits_ctr =
-            num_decimal_digits(curtab.scroll_offset + (curtab.cursor_xy.1 as usize) + 1) as u16;
-        frame.set_cursor_position(Position::new(
-            right_area.x + (curtab.cursor_xy.0 as u16) + 2 + digits_ctr, //adding y for line counter
-            right_area.y + curtab.cursor_xy.1 as u16,
-        ));
-    } else if !app.command_buf.is_empty() {
-        frame.set_cursor_position(Position::new(
-            status_area.x + app.cursor_pos_xy.0,
-            status_area.y,
-        ));
+// Drawn pieces of line: (char x, text, style), chars in [from, to) ranges get range style.
+// Later ranges are laid over earlier ones (selection keeps syntax colors); `to` past line end styles an extra cell
+// for the newline. Goes by grapheme clusters (cluster takes style of its first char),
+// tabs become spaces up to the next tabstop.
+fn styled_cells(
+    text: &str,
+    ranges: &[(usize, usize, Style)],
+    tabstop: usize,
+) -> Vec<(usize, String, Style)> {
+    let len = text.chars().count();
+    let mut styles: Vec<Style> = vec![Style::default(); len + 1];
+    for &(from, to, style) in ranges {
+        for st in styles.iter_mut().take(to.min(len + 1)).skip(from) {
+            *st = st.patch(style);
+        }
+    }
+    let mut cells: Vec<(usize, String, Style)> = grapheme::expand(text, tabstop)
+        .into_iter()
+        .map(|(x, shown)| (x, shown, styles[x]))
+        .collect();
+    if styles[len] != Style::default() {
+        cells.push((len, " ".to_string(), styles[len]));
     }
+    cells
+}
 
-    let status_text = Text::raw(status_str);
-    frame.render_widget(status_text, status_area);
+// Cursor line style reaches the right edge, not only the end of text
+fn fill_row(line: &mut Line, width: usize, style: Style) {
+    let pad = width.saturating_sub(line.width());
+    line.spans.push(Span::raw(" ".repeat(pad)));
+    line.style = style;
+}
+
+// Without wrap: drops cells left of the first shown column,
+// wide char cut by the edge leaves spaces
+fn clip_cells(
+    cells: Vec<(usize, String, Style)>,
+    left_col: usize,
+) -> impl Iterator<Item = (usize, String, Style)> {
+    let mut col = 0;
+    cells.into_iter().filter_map(move |(x, shown, style)| {
+        let w = shown.width();
+        let start = col;
+        col += w;
+        if start >= left_col {
+            Some((x, shown, style))
+        } else if start + w > left_col {
+            Some((x, " ".repeat(start + w - left_col), style))
+        } else {
+            None
+        }
+    })
 }
 
-fn num_decimal_digits<T: std::fmt::Display>(n: T) -> usize {
-    n.to_string().chars().filter(|c| c.is_digit(10)).count()
+// Joins cells with the same style into spans
+fn cells_to_spans(cells: impl Iterator<Item = (usize, String, Style)>) -> Vec<Span<'static>> {
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut piece = String::new();
+    let mut piece_style = Style::default();
+    for (_, shown, style) in cells {
+        if style != piece_style && !piece.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut piece), piece_style));
+        }
+        piece_style = style;
+        piece.push_str(&shown);
+    }
+    if !piece.is_empty() {
+        spans.push(Span::styled(piece, piece_style));
+    }
+    spans
 }
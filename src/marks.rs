@@ -0,0 +1,237 @@
+use crate::app::App;
+use crate::motions;
+use crate::tabs::Tab;
+
+const JUMPS_MAX: usize = 100;
+
+// A-Z mark, remembers its file so it works after the tab is closed
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalMark {
+    pub filename: String,
+    pub pos: (usize, usize), // (x, line)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Jump {
+    pub tab_id: usize,
+    pub pos: (usize, usize),
+}
+
+// Positions before big moves, walked with Ctrl-O / Ctrl-I
+#[derive(Debug)]
+pub struct JumpList {
+    entries: Vec<Jump>,
+    idx: usize, // entries.len() unless walking through the list
+}
+
+impl JumpList {
+    pub fn new() -> JumpList {
+        JumpList {
+            entries: Vec::new(),
+            idx: 0,
+        }
+    }
+
+    // Adds jump to the end, older one on the same line goes away
+    pub fn push(&mut self, jump: Jump) {
+        self.entries
+            .retain(|j| !(j.tab_id == jump.tab_id && j.pos.1 == jump.pos.1));
+        self.entries.push(jump);
+        if self.entries.len() > JUMPS_MAX {
+            self.entries.remove(0);
+        }
+        self.idx = self.entries.len();
+    }
+
+    // Drops jumps into closed tabs
+    fn prune(&mut self, tabs: &[Tab]) {
+        let alive = |j: &Jump| tabs.iter().any(|t| t.id == j.tab_id);
+        let idx = self.idx.min(self.entries.len());
+        self.idx = self.entries[..idx].iter().filter(|j| alive(j)).count();
+        self.entries.retain(alive);
+    }
+}
+
+pub fn is_global(name: char) -> bool {
+    name.is_ascii_uppercase()
+}
+
+impl App {
+    // Remembers cursor before a big move: for Ctrl-O and the '' mark
+    pub fn push_jump(&mut self) {
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        cur_tab.marks.insert('\'', cur_tab.cursor_xy);
+        self.jumps.push(Jump {
+            tab_id: cur_tab.id,
+            pos: cur_tab.cursor_xy,
+        });
+    }
+
+    // m{a-zA-Z}, m' / m`
+    pub fn set_mark(&mut self, name: char) {
+        let name = if name == '`' { '\'' } else { name };
+        if !name.is_ascii_alphabetic() && name != '\'' {
+            self.throw_status_message(format!("Invalid mark name: {}", name));
+            return;
+        }
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let pos = cur_tab.cursor_xy;
+        if is_global(name) {
+            let filename = cur_tab.filename.clone();
+            // global mark lives in one tab only
+            for tab in self.tabs.iter_mut() {
+                tab.marks.remove(&name);
+            }
+            self.global_marks.insert(name, GlobalMark { filename, pos });
+        }
+        self.tabs[self.cur_tab].marks.insert(name, pos);
+    }
+
+    // Position of a mark set in current tab
+    pub fn local_mark(&self, name: char) -> Option<(usize, usize)> {
+        let name = if name == '`' { '\'' } else { name };
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let (x, line) = *cur_tab.marks.get(&name)?;
+        let line = line.min(cur_tab.buf.last_line());
+        Some((x.min(cur_tab.buf.line_len(line)), line))
+    }
+
+    // 'a goes to the line of mark, `a to its exact position.
+    // A-Z switch to the tab holding the mark or open its file in a new one.
+    pub fn goto_mark(&mut self, name: char, exact: bool) {
+        self.seal_edit();
+        let pos = match self.local_mark(name) {
+            Some(pos) => {
+                self.push_jump();
+                pos
+            }
+            None if is_global(name) => match self.open_global_mark(name) {
+                Ok(pos) => pos,
+                Err(e) => {
+                    self.throw_status_error(e);
+                    return;
+                }
+            },
+            None => {
+                self.throw_status_message(format!("Mark '{} is not set", name));
+                return;
+            }
+        };
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        cur_tab.cursor_xy = match exact {
+            true => pos,
+            false => (motions::first_non_blank(&cur_tab.buf, pos.1), pos.1),
+        };
+        self.clamp_normal_cursor();
+        self.scroll_to_cursor();
+    }
+
+    // Makes tab with global mark current, returns mark position
+    fn open_global_mark(&mut self, name: char) -> Result<(usize, usize), String> {
+        if let Some(i) = self.tabs.iter().position(|t| t.marks.contains_key(&name)) {
+            self.push_jump();
+            self.cur_tab = i;
+            return self.local_mark(name).ok_or(String::new());
+        }
+        let mark = match self.global_marks.get(&name) {
+            Some(m) if !m.filename.is_empty() => m.clone(),
+            _ => return Err(format!("Mark '{} is not set", name)),
+        };
+        self.push_jump();
+        match self.tabs.iter().position(|t| t.filename == mark.filename) {
+            Some(i) => self.cur_tab = i,
+            None => {
+                let mut tab = Tab::new(Some(mark.filename.clone()));
+                if let Err(e) = tab.readf(mark.filename.clone()) {
+                    return Err(format!("While opening file: {}", e));
+                }
+                self.tabs.push(tab);
+                self.cur_tab = self.tabs.len() - 1;
+                self.check_swap(self.cur_tab);
+            }
+        }
+        self.tabs[self.cur_tab].marks.insert(name, mark.pos);
+        self.local_mark(name).ok_or(String::new())
+    }
+
+    // Saves A-Z marks of a tab that is going to be closed
+    pub fn keep_global_marks(&mut self, tab_idx: usize) {
+        let tab: &Tab = &self.tabs[tab_idx];
+        for (&name, &pos) in tab.marks.iter().filter(|(n, _)| is_global(**n)) {
+            self.global_marks.insert(
+                name,
+                GlobalMark {
+                    filename: tab.filename.clone(),
+                    pos,
+                },
+            );
+        }
+    }
+
+    // Ctrl-O (older = true) and Ctrl-I
+    pub fn jump_walk(&mut self, count: usize, older: bool) {
+        self.seal_edit();
+        self.jumps.prune(&self.tabs);
+        if older && self.jumps.idx >= self.jumps.entries.len() {
+            // so Ctrl-I can come back here
+            let cur_tab: &Tab = &self.tabs[self.cur_tab];
+            self.jumps.push(Jump {
+                tab_id: cur_tab.id,
+                pos: cur_tab.cursor_xy,
+            });
+            self.jumps.idx = self.jumps.entries.len() - 1;
+        }
+        let target = match older {
+            true => self.jumps.idx.checked_sub(count),
+            false => Some(self.jumps.idx + count).filter(|&i| i < self.jumps.entries.len()),
+        };
+        let jump = match target {
+            Some(i) => {
+                self.jumps.idx = i;
+                self.jumps.entries[i]
+            }
+            None => {
+                self.throw_status_message(match older {
+                    true => "Already at oldest jump".to_string(),
+                    false => "Already at newest jump".to_string(),
+                });
+                return;
+            }
+        };
+        if let Some(i) = self.tabs.iter().position(|t| t.id == jump.tab_id) {
+            self.cur_tab = i;
+        }
+        self.tabs[self.cur_tab].cursor_xy = jump.pos;
+        self.clamp_normal_cursor();
+        self.scroll_to_cursor();
+    }
+
+    // Lines for !marks: current tab ones, then global ones from other files
+    pub fn list_marks(&self) -> Vec<(char, usize, usize, String)> {
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let mut res: Vec<(char, usize, usize, String)> = cur_tab
+            .marks
+            .iter()
+            .map(|(&name, &(x, line))| {
+                let text = match line <= cur_tab.buf.last_line() {
+                    true => cur_tab.buf.line(line),
+                    false => String::new(),
+                };
+                (name, line, x, text)
+            })
+            .collect();
+        for tab in self.tabs.iter().filter(|t| t.id != cur_tab.id) {
+            for (&name, &(x, line)) in tab.marks.iter().filter(|(n, _)| is_global(**n)) {
+                res.push((name, line, x, tab.displayed_name.clone()));
+            }
+        }
+        for (&name, mark) in self.global_marks.iter() {
+            if !res.iter().any(|r| r.0 == name) && !mark.filename.is_empty() {
+                res.push((name, mark.pos.1, mark.pos.0, mark.filename.clone()));
+            }
+        }
+        // '' '< '> first, then a-z, A-Z
+        res.sort_by_key(|r| (r.0.is_ascii_alphabetic(), r.0.is_ascii_uppercase(), r.0));
+        res
+    }
+}
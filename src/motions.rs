@@ -0,0 +1,363 @@
+use crate::buffer::TextBuffer;
+
+// Cursor motions of normal mode.
+// Positions here are in cursor_xy form: (x, line), x counted in chars
+// (h and l step over whole grapheme clusters).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordFwd,
+    WordBack,
+    WordEnd,
+    BigWordFwd,
+    BigWordBack,
+    BigWordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FileStart, // gg
+    FileEnd,   // G
+    ParagraphFwd,
+    ParagraphBack,
+    FindChar(char, FindKind),
+    RepeatFind,
+    RepeatFindRev,
+    MatchPair,
+    Mark(char, bool), // 'a goes to line, `a (exact = true) to position
+}
+
+impl Motion {
+    // Operators on these work with whole lines
+    pub fn is_linewise(&self) -> bool {
+        matches!(
+            self,
+            Motion::Up
+                | Motion::Down
+                | Motion::FileStart
+                | Motion::FileEnd
+                | Motion::Mark(_, false)
+        )
+    }
+
+    // These are remembered in jump list
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Motion::FileStart
+                | Motion::FileEnd
+                | Motion::ParagraphFwd
+                | Motion::ParagraphBack
+                | Motion::MatchPair
+                | Motion::Mark(..)
+        )
+    }
+
+    // Operators on these include the char under target position
+    pub fn is_inclusive(&self) -> bool {
+        match self {
+            Motion::WordEnd | Motion::BigWordEnd | Motion::LineEnd | Motion::MatchPair => true,
+            Motion::FindChar(_, kind) => kind.forward,
+            _ => false,
+        }
+    }
+}
+
+// f, t, F, T
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FindKind {
+    pub forward: bool,
+    pub till: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CharClass {
+    Blank,
+    Word,
+    Punct,
+}
+
+fn char_class(ch: char, big: bool) -> CharClass {
+    if ch.is_whitespace() {
+        return CharClass::Blank;
+    }
+    if big || ch.is_alphanumeric() || ch == '_' {
+        return CharClass::Word;
+    }
+    CharClass::Punct
+}
+
+// Computes where the motion moves the cursor, count times.
+// `count` is None when no count was typed (gg/G care about that).
+// Returns None if motion can't be done (e.g. f didn't find the char).
+pub fn apply_motion(
+    buf: &TextBuffer,
+    pos: (usize, usize),
+    motion: Motion,
+    count: Option<usize>,
+) -> Option<(usize, usize)> {
+    let n = count.unwrap_or(1).max(1);
+    let (x, line) = pos;
+    match motion {
+        Motion::Left => Some((buf.prev_grapheme(line, x, n), line)),
+        Motion::Right => Some((buf.next_grapheme(line, x, n), line)),
+        Motion::Up => {
+            let new_line = line.saturating_sub(n);
+            Some((x.min(buf.line_len(new_line)), new_line))
+        }
+        Motion::Down => {
+            let new_line = (line + n).min(buf.last_line());
+            Some((x.min(buf.line_len(new_line)), new_line))
+        }
+        Motion::WordFwd | Motion::BigWordFwd => {
+            let big = motion == Motion::BigWordFwd;
+            let mut idx = buf.pos_to_char(line, x);
+            for _ in 0..n {
+                idx = next_word_start(buf, idx, big);
+            }
+            Some(swap(buf.char_to_pos(idx)))
+        }
+        Motion::WordBack | Motion::BigWordBack => {
+            let big = motion == Motion::BigWordBack;
+            let mut idx = buf.pos_to_char(line, x);
+            for _ in 0..n {
+                idx = prev_word_start(buf, idx, big);
+            }
+            Some(swap(buf.char_to_pos(idx)))
+        }
+        Motion::WordEnd | Motion::BigWordEnd => {
+            let big = motion == Motion::BigWordEnd;
+            let mut idx = buf.pos_to_char(line, x);
+            for _ in 0..n {
+                idx = next_word_end(buf, idx, big);
+            }
+            Some(swap(buf.char_to_pos(idx)))
+        }
+        Motion::LineStart => Some((0, line)),
+        Motion::FirstNonBlank => Some((first_non_blank(buf, line), line)),
+        Motion::LineEnd => {
+            let new_line = (line + n - 1).min(buf.last_line());
+            Some((buf.line_len(new_line).saturating_sub(1), new_line))
+        }
+        Motion::FileStart => {
+            let new_line = count.unwrap_or(1).saturating_sub(1).min(buf.last_line());
+            Some((first_non_blank(buf, new_line), new_line))
+        }
+        Motion::FileEnd => {
+            let new_line = match count {
+                Some(c) => c.saturating_sub(1).min(buf.last_line()),
+                None => buf.last_line(),
+            };
+            Some((first_non_blank(buf, new_line), new_line))
+        }
+        Motion::ParagraphFwd => {
+            let mut l = line;
+            for _ in 0..n {
+                while l < buf.last_line() && is_blank_line(buf, l) {
+                    l += 1;
+                }
+                while l < buf.last_line() && !is_blank_line(buf, l) {
+                    l += 1;
+                }
+            }
+            if l == buf.last_line() && !is_blank_line(buf, l) {
+                return Some((buf.line_len(l).saturating_sub(1), l));
+            }
+            Some((0, l))
+        }
+        Motion::ParagraphBack => {
+            let mut l = line;
+            for _ in 0..n {
+                while l > 0 && is_blank_line(buf, l) {
+                    l -= 1;
+                }
+                while l > 0 && !is_blank_line(buf, l) {
+                    l -= 1;
+                }
+            }
+            Some((0, l))
+        }
+        Motion::FindChar(ch, kind) => find_in_line(buf, pos, ch, kind, n, false),
+        // these are resolved by App, as it remembers the last f/t and marks
+        Motion::RepeatFind | Motion::RepeatFindRev | Motion::Mark(..) => None,
+        Motion::MatchPair => match_pair(buf, pos),
+    }
+}
+
+// f/t/F/T on current line.
+// `repeat` is set for ; and , so t/T don't get stuck before the same char.
+pub fn find_in_line(
+    buf: &TextBuffer,
+    pos: (usize, usize),
+    ch: char,
+    kind: FindKind,
+    count: usize,
+    repeat: bool,
+) -> Option<(usize, usize)> {
+    let (x, line) = pos;
+    let chars: Vec<char> = buf.line(line).chars().collect();
+    let mut found: usize = x;
+    let mut left = count;
+    if kind.forward {
+        let mut i = x + 1;
+        if kind.till && repeat {
+            i += 1;
+        }
+        while i < chars.len() {
+            if chars[i] == ch {
+                found = i;
+                left -= 1;
+                if left == 0 {
+                    break;
+                }
+            }
+            i += 1;
+        }
+        if left > 0 {
+            return None;
+        }
+        if kind.till {
+            found -= 1;
+        }
+    } else {
+        let mut i = x as isize - 1;
+        if kind.till && repeat {
+            i -= 1;
+        }
+        while i >= 0 {
+            if chars[i as usize] == ch {
+                found = i as usize;
+                left -= 1;
+                if left == 0 {
+                    break;
+                }
+            }
+            i -= 1;
+        }
+        if left > 0 {
+            return None;
+        }
+        if kind.till {
+            found += 1;
+        }
+    }
+    Some((found, line))
+}
+
+pub fn first_non_blank(buf: &TextBuffer, line: usize) -> usize {
+    let text = buf.line(line);
+    let blanks = text.chars().take_while(|c| c.is_whitespace()).count();
+    blanks.min(text.chars().count().saturating_sub(1))
+}
+
+pub fn is_blank_line(buf: &TextBuffer, line: usize) -> bool {
+    buf.line(line).trim().is_empty()
+}
+
+// (line, x) <-> (x, line)
+pub fn swap(pos: (usize, usize)) -> (usize, usize) {
+    (pos.1, pos.0)
+}
+
+fn next_word_start(buf: &TextBuffer, idx: usize, big: bool) -> usize {
+    let rope = buf.rope();
+    let len = rope.len_chars();
+    if idx >= len {
+        return len;
+    }
+    let mut i = idx;
+    let start_class = char_class(rope.char(i), big);
+    if start_class != CharClass::Blank {
+        while i < len && char_class(rope.char(i), big) == start_class {
+            i += 1;
+        }
+    }
+    while i < len && char_class(rope.char(i), big) == CharClass::Blank {
+        // empty line counts as a word
+        if i > idx && rope.char(i) == '\n' && rope.char(i - 1) == '\n' {
+            break;
+        }
+        i += 1;
+    }
+    i
+}
+
+fn next_word_end(buf: &TextBuffer, idx: usize, big: bool) -> usize {
+    let rope = buf.rope();
+    let len = rope.len_chars();
+    let mut i = idx + 1;
+    while i < len && char_class(rope.char(i), big) == CharClass::Blank {
+        i += 1;
+    }
+    if i >= len {
+        return len.saturating_sub(1);
+    }
+    let class = char_class(rope.char(i), big);
+    while i + 1 < len && char_class(rope.char(i + 1), big) == class {
+        i += 1;
+    }
+    i
+}
+
+fn prev_word_start(buf: &TextBuffer, idx: usize, big: bool) -> usize {
+    let rope = buf.rope();
+    if idx == 0 {
+        return 0;
+    }
+    let mut i = idx.min(rope.len_chars()) - 1;
+    while i > 0 && char_class(rope.char(i), big) == CharClass::Blank {
+        if rope.char(i) == '\n' && rope.char(i - 1) == '\n' {
+            return i;
+        }
+        i -= 1;
+    }
+    let class = char_class(rope.char(i), big);
+    while i > 0 && char_class(rope.char(i - 1), big) == class {
+        i -= 1;
+    }
+    i
+}
+
+// % : jumps from bracket under (or after) cursor to its pair
+fn match_pair(buf: &TextBuffer, pos: (usize, usize)) -> Option<(usize, usize)> {
+    let (x, line) = pos;
+    let line_chars: Vec<char> = buf.line(line).chars().collect();
+    let start_x = (x..line_chars.len()).find(|&i| "()[]{}".contains(line_chars[i]))?;
+    let open = line_chars[start_x];
+    let (pair, forward) = match open {
+        '(' => (')', true),
+        '[' => (']', true),
+        '{' => ('}', true),
+        ')' => ('(', false),
+        ']' => ('[', false),
+        '}' => ('{', false),
+        _ => return None,
+    };
+    let rope = buf.rope();
+    let mut idx = buf.pos_to_char(line, start_x);
+    let mut depth: usize = 0;
+    loop {
+        let ch = rope.char(idx);
+        if ch == open {
+            depth += 1;
+        } else if ch == pair {
+            depth -= 1;
+            if depth == 0 {
+                return Some(swap(buf.char_to_pos(idx)));
+            }
+        }
+        if forward {
+            idx += 1;
+            if idx >= rope.len_chars() {
+                return None;
+            }
+        } else {
+            if idx == 0 {
+                return None;
+            }
+            idx -= 1;
+        }
+    }
+}
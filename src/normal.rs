@@ -0,0 +1,460 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::app::{App, Mode};
+use crate::motions::{self, FindKind, Motion};
+use crate::operators::{OpTarget, Operator};
+use crate::registers::Registers;
+use crate::tabs::Tab;
+use crate::textobj::{self, TextObj};
+use crate::view::ScrollTo;
+use crate::visual::VisualKind;
+use crate::window::WinCmd;
+
+// Ctrl-W in pending keys, it starts window commands
+pub const CTRL_W: char = '\u{17}';
+
+// Result of parsing pending normal mode keys
+#[derive(Debug, Clone, PartialEq)]
+pub enum Parsed<T> {
+    Pending,
+    Invalid,
+    Done(T),
+}
+
+// Ways to enter insert mode from normal one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InsertKind {
+    Before,       // i
+    After,        // a
+    LineStart,    // I
+    LineEnd,      // A
+    NewLineBelow, // o
+    NewLineAbove, // O
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalCmd {
+    Move(Motion),
+    Operate(Operator, OpTarget),
+    Insert(InsertKind),
+    CommandLine(char), // : or !
+    Undo,
+    Put { before: bool }, // p, P
+    Visual(VisualKind),
+    Search { backward: bool },     // / ?
+    SearchNext { reverse: bool },  // n N
+    SearchWord { backward: bool }, // * #
+    SetMark(char),                 // m
+    Record(char),                  // q{reg}
+    Replay(char),                  // @{reg}
+    Repeat,                        // .
+    Scroll(ScrollTo),              // zt zz zb
+    Window(WinCmd),                // Ctrl-W s, Ctrl-W j...
+}
+
+// Fully parsed normal mode command: "a2dw is reg a, count 2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NormalInput {
+    pub reg: Option<char>,
+    pub count: Option<usize>,
+    pub cmd: NormalCmd,
+}
+
+// Splits leading count ("12" in "12w") off the keys.
+// Leading 0 is a motion, not a count.
+pub fn split_count(keys: &str) -> (Option<usize>, &str) {
+    let digits = keys
+        .char_indices()
+        .take_while(|(i, c)| c.is_ascii_digit() && !(*i == 0 && *c == '0'))
+        .count();
+    if digits == 0 {
+        return (None, keys);
+    }
+    (keys[..digits].parse().ok(), &keys[digits..])
+}
+
+pub fn parse_motion(keys: &str) -> Parsed<Motion> {
+    let mut chars = keys.chars();
+    let first = match chars.next() {
+        Some(c) => c,
+        None => return Parsed::Pending,
+    };
+    let second = chars.next();
+    let motion = match first {
+        'h' => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'k' => Motion::Up,
+        'j' => Motion::Down,
+        'w' => Motion::WordFwd,
+        'b' => Motion::WordBack,
+        'e' => Motion::WordEnd,
+        'W' => Motion::BigWordFwd,
+        'B' => Motion::BigWordBack,
+        'E' => Motion::BigWordEnd,
+        '0' => Motion::LineStart,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::LineEnd,
+        'G' => Motion::FileEnd,
+        '}' => Motion::ParagraphFwd,
+        '{' => Motion::ParagraphBack,
+        ';' => Motion::RepeatFind,
+        ',' => Motion::RepeatFindRev,
+        '%' => Motion::MatchPair,
+        '\'' | '`' => match second {
+            None => return Parsed::Pending,
+            Some(ch) => Motion::Mark(ch, first == '`'),
+        },
+        'g' => match second {
+            None => return Parsed::Pending,
+            Some('g') => Motion::FileStart,
+            Some(_) => return Parsed::Invalid,
+        },
+        'f' | 't' | 'F' | 'T' => {
+            let kind = FindKind {
+                forward: first == 'f' || first == 't',
+                till: first == 't' || first == 'T',
+            };
+            match second {
+                None => return Parsed::Pending,
+                Some(ch) => Motion::FindChar(ch, kind),
+            }
+        }
+        _ => return Parsed::Invalid,
+    };
+    Parsed::Done(motion)
+}
+
+// Counts before and after operator multiply: 2d3w deletes 6 words
+fn mul_counts(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(1) * b.unwrap_or(1)),
+    }
+}
+
+// Keys after an operator: motion, text object or the operator again
+fn parse_op_target(op_char: char, keys: &str) -> Parsed<(Option<usize>, OpTarget)> {
+    let (count, rest) = split_count(keys);
+    let mut chars = rest.chars();
+    let first = match chars.next() {
+        Some(c) => c,
+        None => return Parsed::Pending,
+    };
+    if first == op_char {
+        return Parsed::Done((count, OpTarget::Lines));
+    }
+    if first == 'i' || first == 'a' {
+        return match chars.next() {
+            None => Parsed::Pending,
+            Some(ch) => match textobj::parse_obj_kind(ch) {
+                Some(kind) => Parsed::Done((
+                    count,
+                    OpTarget::Object(TextObj {
+                        inner: first == 'i',
+                        kind,
+                    }),
+                )),
+                None => Parsed::Invalid,
+            },
+        };
+    }
+    match parse_motion(rest) {
+        Parsed::Done(m) => Parsed::Done((count, OpTarget::Motion(m))),
+        Parsed::Pending => Parsed::Pending,
+        Parsed::Invalid => Parsed::Invalid,
+    }
+}
+
+// Register prefix, "x. Count may come before or after it.
+pub fn parse_register(keys: &str) -> Parsed<(Option<char>, Option<usize>, &str)> {
+    let (count, rest) = split_count(keys);
+    let after = match rest.strip_prefix('"') {
+        Some(a) => a,
+        None => return Parsed::Done((None, count, rest)),
+    };
+    let mut chars = after.chars();
+    match chars.next() {
+        None => Parsed::Pending,
+        Some(reg) if Registers::is_valid_name(reg) => {
+            let (count2, rest) = split_count(chars.as_str());
+            Parsed::Done((Some(reg), mul_counts(count, count2), rest))
+        }
+        Some(_) => Parsed::Invalid,
+    }
+}
+
+pub fn parse_normal(keys: &str) -> Parsed<NormalInput> {
+    let (reg, count, rest) = match parse_register(keys) {
+        Parsed::Done(r) => r,
+        Parsed::Pending => return Parsed::Pending,
+        Parsed::Invalid => return Parsed::Invalid,
+    };
+    let mut rest_chars = rest.chars();
+    if let Some(op_char) = rest_chars.next()
+        && let Some(op) = Operator::from_char(op_char)
+    {
+        return match parse_op_target(op_char, rest_chars.as_str()) {
+            Parsed::Done((count2, target)) => Parsed::Done(NormalInput {
+                reg,
+                count: mul_counts(count, count2),
+                cmd: NormalCmd::Operate(op, target),
+            }),
+            Parsed::Pending => Parsed::Pending,
+            Parsed::Invalid => Parsed::Invalid,
+        };
+    }
+    // commands taking a char: ma, qa, @a, zt, Ctrl-W j
+    let mut arg_chars = rest.chars();
+    if let (Some(c @ ('m' | 'q' | '@' | 'z' | CTRL_W)), Some(arg), None) =
+        (arg_chars.next(), arg_chars.next(), arg_chars.next())
+    {
+        let cmd = match c {
+            'm' => NormalCmd::SetMark(arg),
+            'q' => NormalCmd::Record(arg),
+            'z' => match ScrollTo::from_char(arg) {
+                Some(to) => NormalCmd::Scroll(to),
+                None => return Parsed::Invalid,
+            },
+            CTRL_W => match WinCmd::from_char(arg) {
+                Some(cmd) => NormalCmd::Window(cmd),
+                None => return Parsed::Invalid,
+            },
+            _ => NormalCmd::Replay(arg),
+        };
+        return Parsed::Done(NormalInput { reg, count, cmd });
+    }
+    let cmd = match rest {
+        "" => return Parsed::Pending,
+        // shortcuts for common operator uses
+        "x" => NormalCmd::Operate(Operator::Delete, OpTarget::Motion(Motion::Right)),
+        "X" => NormalCmd::Operate(Operator::Delete, OpTarget::Motion(Motion::Left)),
+        "D" => NormalCmd::Operate(Operator::Delete, OpTarget::Motion(Motion::LineEnd)),
+        "C" => NormalCmd::Operate(Operator::Change, OpTarget::Motion(Motion::LineEnd)),
+        "s" => NormalCmd::Operate(Operator::Change, OpTarget::Motion(Motion::Right)),
+        "S" => NormalCmd::Operate(Operator::Change, OpTarget::Lines),
+        "Y" => NormalCmd::Operate(Operator::Yank, OpTarget::Lines),
+        "i" => NormalCmd::Insert(InsertKind::Before),
+        "a" => NormalCmd::Insert(InsertKind::After),
+        "I" => NormalCmd::Insert(InsertKind::LineStart),
+        "A" => NormalCmd::Insert(InsertKind::LineEnd),
+        "o" => NormalCmd::Insert(InsertKind::NewLineBelow),
+        "O" => NormalCmd::Insert(InsertKind::NewLineAbove),
+        ":" => NormalCmd::CommandLine(':'),
+        "!" => NormalCmd::CommandLine('!'),
+        "u" => NormalCmd::Undo,
+        "p" => NormalCmd::Put { before: false },
+        "P" => NormalCmd::Put { before: true },
+        "v" => NormalCmd::Visual(VisualKind::Char),
+        "V" => NormalCmd::Visual(VisualKind::Line),
+        "/" => NormalCmd::Search { backward: false },
+        "?" => NormalCmd::Search { backward: true },
+        "n" => NormalCmd::SearchNext { reverse: false },
+        "N" => NormalCmd::SearchNext { reverse: true },
+        "*" => NormalCmd::SearchWord { backward: false },
+        "#" => NormalCmd::SearchWord { backward: true },
+        "m" | "q" | "@" | "z" | "\u{17}" => return Parsed::Pending,
+        "." => NormalCmd::Repeat,
+        _ => match parse_motion(rest) {
+            Parsed::Done(m) => NormalCmd::Move(m),
+            Parsed::Pending => return Parsed::Pending,
+            Parsed::Invalid => return Parsed::Invalid,
+        },
+    };
+    Parsed::Done(NormalInput { reg, count, cmd })
+}
+
+impl App {
+    pub fn handle_normal_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.normal_keys.clear();
+                self.tab_redo();
+            }
+            KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.normal_keys.clear();
+                self.enter_visual(VisualKind::Block);
+            }
+            KeyCode::Char('d') | KeyCode::Char('u')
+                if key.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                self.normal_keys.clear();
+                self.scroll_half_page(key.code == KeyCode::Char('d'));
+            }
+            // terminals send Ctrl-I as Tab
+            KeyCode::Char('o') | KeyCode::Char('i') | KeyCode::Tab
+                if key.code == KeyCode::Tab || key.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                let count = split_count(&self.normal_keys).0.unwrap_or(1);
+                self.normal_keys.clear();
+                self.jump_walk(count, key.code == KeyCode::Char('o'));
+            }
+            KeyCode::Char('q') if self.recording.is_some() && self.normal_keys.is_empty() => {
+                self.stop_recording();
+            }
+            KeyCode::Char('w') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.push_normal_key(CTRL_W)
+            }
+            KeyCode::Char(ch) => self.push_normal_key(ch),
+            KeyCode::Esc => {
+                self.normal_keys.clear();
+            }
+            KeyCode::Insert => {
+                self.normal_keys.clear();
+                self.run_normal_cmd(NormalInput {
+                    reg: None,
+                    count: None,
+                    cmd: NormalCmd::Insert(InsertKind::Before),
+                });
+            }
+            KeyCode::Left | KeyCode::Backspace => self.do_motion(Motion::Left, None),
+            KeyCode::Right => self.do_motion(Motion::Right, None),
+            KeyCode::Up => self.do_motion(Motion::Up, None),
+            KeyCode::Down => self.do_motion(Motion::Down, None),
+            KeyCode::Enter => {
+                self.do_motion(Motion::Down, None);
+                self.do_motion(Motion::FirstNonBlank, None);
+            }
+            _ => {}
+        }
+    }
+
+    fn push_normal_key(&mut self, ch: char) {
+        self.normal_keys.push(ch);
+        let keys = self.normal_keys.clone();
+        match parse_normal(&keys) {
+            Parsed::Pending => {}
+            Parsed::Invalid => self.normal_keys.clear(),
+            Parsed::Done(input) => {
+                self.normal_keys.clear();
+                self.run_normal_cmd(input);
+            }
+        }
+    }
+
+    pub fn run_normal_cmd(&mut self, input: NormalInput) {
+        let count = input.count;
+        let is_change = match input.cmd {
+            NormalCmd::Operate(op, _) => op != Operator::Yank,
+            NormalCmd::Put { .. } | NormalCmd::Insert(_) => true,
+            _ => false,
+        };
+        match input.cmd {
+            NormalCmd::Move(Motion::Mark(name, exact)) => self.goto_mark(name, exact),
+            NormalCmd::Move(m) => self.do_motion(m, count),
+            NormalCmd::SetMark(name) => self.set_mark(name),
+            NormalCmd::Record(reg) => self.start_recording(reg),
+            NormalCmd::Replay(reg) => self.replay_macro(reg, count),
+            NormalCmd::Repeat => self.dot_repeat(count),
+            NormalCmd::Scroll(to) => self.scroll_cursor_to(to, count),
+            NormalCmd::Window(cmd) => self.run_win_cmd(cmd, count),
+            NormalCmd::Operate(op, target) => self.run_operator(op, target, count, input.reg),
+            NormalCmd::Put { before } => self.put_register(input.reg, count, before),
+            NormalCmd::Visual(kind) => self.enter_visual(kind),
+            NormalCmd::Search { backward } => self.start_search(backward),
+            NormalCmd::SearchNext { reverse } => self.search_next(reverse, count),
+            NormalCmd::SearchWord { backward } => self.search_word(backward, count),
+            NormalCmd::Insert(kind) => self.enter_insert(kind),
+            NormalCmd::CommandLine(prefix) => {
+                self.mode = Mode::Command;
+                self.status_message = false;
+                self.command_buf.clear();
+                if prefix == '!' {
+                    self.command_buf.push('!');
+                }
+                self.cursor_pos_xy.0 = self.command_buf.chars().count() as u16;
+            }
+            NormalCmd::Undo => {
+                for _ in 0..count.unwrap_or(1) {
+                    self.tab_undo();
+                }
+                self.clamp_normal_cursor();
+            }
+        }
+        if is_change {
+            self.remember_change(input);
+        }
+    }
+
+    pub fn do_motion(&mut self, motion: Motion, count: Option<usize>) {
+        self.seal_edit();
+        if let Some((pos, m)) = self.motion_target(motion, count) {
+            if m.is_jump() && pos != self.tabs[self.cur_tab].cursor_xy {
+                self.push_jump();
+            }
+            self.tabs[self.cur_tab].cursor_xy = pos;
+        }
+        self.clamp_normal_cursor();
+    }
+
+    // Where the motion would move the cursor, plus the motion actually done
+    // (; and , turn into the remembered f/t)
+    pub fn motion_target(
+        &mut self,
+        motion: Motion,
+        count: Option<usize>,
+    ) -> Option<((usize, usize), Motion)> {
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        match motion {
+            Motion::RepeatFind | Motion::RepeatFindRev => {
+                let (ch, kind) = self.last_find?;
+                let kind = match motion {
+                    Motion::RepeatFindRev => FindKind {
+                        forward: !kind.forward,
+                        till: kind.till,
+                    },
+                    _ => kind,
+                };
+                let n = count.unwrap_or(1).max(1);
+                let pos =
+                    motions::find_in_line(&cur_tab.buf, cur_tab.cursor_xy, ch, kind, n, true)?;
+                Some((pos, Motion::FindChar(ch, kind)))
+            }
+            // operators work with marks of current tab only
+            Motion::Mark(name, _) => Some((self.local_mark(name)?, motion)),
+            Motion::FindChar(ch, kind) => {
+                self.last_find = Some((ch, kind));
+                let cur_tab: &Tab = &self.tabs[self.cur_tab];
+                let pos = motions::apply_motion(&cur_tab.buf, cur_tab.cursor_xy, motion, count)?;
+                Some((pos, motion))
+            }
+            _ => {
+                let pos = motions::apply_motion(&cur_tab.buf, cur_tab.cursor_xy, motion, count)?;
+                Some((pos, motion))
+            }
+        }
+    }
+
+    fn enter_insert(&mut self, kind: InsertKind) {
+        self.seal_edit();
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let (x, line) = cur_tab.cursor_xy;
+        let line_len = cur_tab.buf.line_len(line);
+        match kind {
+            InsertKind::Before => {}
+            InsertKind::After => cur_tab.cursor_xy.0 = cur_tab.buf.next_grapheme(line, x, 1),
+            InsertKind::LineStart => {
+                cur_tab.cursor_xy.0 = motions::first_non_blank(&cur_tab.buf, line);
+            }
+            InsertKind::LineEnd => cur_tab.cursor_xy.0 = line_len,
+            InsertKind::NewLineBelow => {
+                cur_tab.insert_text(line, line_len, "\n", true);
+                cur_tab.cursor_xy = (0, line + 1);
+            }
+            InsertKind::NewLineAbove => {
+                cur_tab.insert_text(line, 0, "\n", true);
+                cur_tab.cursor_xy = (0, line);
+            }
+        }
+        self.mode = Mode::Insert;
+    }
+
+    // In normal mode cursor stays on a char, not after the last one
+    pub fn clamp_normal_cursor(&mut self) {
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        cur_tab.cursor_xy.1 = cur_tab.cursor_xy.1.min(cur_tab.buf.last_line());
+        let max_x = cur_tab.buf.line_len(cur_tab.cursor_xy.1).saturating_sub(1);
+        cur_tab.cursor_xy.0 = cur_tab
+            .buf
+            .grapheme_start(cur_tab.cursor_xy.1, cur_tab.cursor_xy.0.min(max_x));
+    }
+}
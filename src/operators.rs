@@ -0,0 +1,312 @@
+use crate::app::{App, Mode};
+use crate::motions::{self, Motion};
+use crate::registers::RegContent;
+use crate::tabs::Tab;
+use crate::textobj::{self, TextObj};
+
+// Spaces added/removed by > and <
+pub const SHIFT_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Dedent,
+}
+
+impl Operator {
+    pub fn from_char(ch: char) -> Option<Operator> {
+        match ch {
+            'd' => Some(Operator::Delete),
+            'c' => Some(Operator::Change),
+            'y' => Some(Operator::Yank),
+            '>' => Some(Operator::Indent),
+            '<' => Some(Operator::Dedent),
+            _ => None,
+        }
+    }
+}
+
+// What the operator acts on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpTarget {
+    Motion(Motion),
+    Object(TextObj),
+    Lines, // dd, cc, yy, >>, <<
+}
+
+// Region of the buffer an operator works on.
+// Positions are (x, line); end is exclusive for charwise ranges,
+// for linewise ones only lines of start and end matter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpRange {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub linewise: bool,
+}
+
+impl OpRange {
+    pub fn chars(start: (usize, usize), end: (usize, usize)) -> OpRange {
+        OpRange {
+            start,
+            end,
+            linewise: false,
+        }
+    }
+
+    pub fn lines(first: usize, last: usize) -> OpRange {
+        OpRange {
+            start: (0, first),
+            end: (0, last),
+            linewise: true,
+        }
+    }
+}
+
+impl App {
+    // Region covered by the motion/object from the cursor
+    fn op_range(
+        &mut self,
+        op: Operator,
+        target: OpTarget,
+        count: Option<usize>,
+    ) -> Option<OpRange> {
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let cursor = cur_tab.cursor_xy;
+        match target {
+            OpTarget::Lines => {
+                let last = (cursor.1 + count.unwrap_or(1).max(1) - 1).min(cur_tab.buf.last_line());
+                Some(OpRange::lines(cursor.1, last))
+            }
+            OpTarget::Object(obj) => textobj::select(&cur_tab.buf, cursor, obj),
+            OpTarget::Motion(motion) => {
+                let mut motion = motion;
+                // cw on a word works like ce
+                if op == Operator::Change {
+                    let line = cur_tab.buf.line(cursor.1);
+                    let on_blank = line.chars().nth(cursor.0).is_none_or(|c| c.is_whitespace());
+                    if !on_blank {
+                        motion = match motion {
+                            Motion::WordFwd => Motion::WordEnd,
+                            Motion::BigWordFwd => Motion::BigWordEnd,
+                            m => m,
+                        };
+                    }
+                }
+                let (target_pos, motion) = self.motion_target(motion, count)?;
+                if motion.is_linewise() {
+                    let (a, b) = (cursor.1.min(target_pos.1), cursor.1.max(target_pos.1));
+                    return Some(OpRange::lines(a, b));
+                }
+                let (mut start, mut end) = (cursor, target_pos);
+                if (end.1, end.0) < (start.1, start.0) {
+                    std::mem::swap(&mut start, &mut end);
+                }
+                if motion.is_inclusive() {
+                    end.0 = self.tabs[self.cur_tab].buf.next_grapheme(end.1, end.0, 1);
+                } else if end.1 > start.1 && end.0 == 0 {
+                    // exclusive motion ending at line start (dw on last word) stops at previous line end
+                    let buf = &self.tabs[self.cur_tab].buf;
+                    end = (buf.line_len(end.1 - 1), end.1 - 1);
+                }
+                Some(OpRange::chars(start, end))
+            }
+        }
+    }
+
+    pub fn run_operator(
+        &mut self,
+        op: Operator,
+        target: OpTarget,
+        count: Option<usize>,
+        reg: Option<char>,
+    ) {
+        self.seal_edit();
+        let range = match self.op_range(op, target, count) {
+            Some(r) => r,
+            None => return,
+        };
+        self.apply_operator(op, range, reg);
+    }
+
+    // Runs operator on an already known range (from a motion or visual selection)
+    pub fn apply_operator(&mut self, op: Operator, range: OpRange, reg: Option<char>) {
+        if reg == Some('%') && op != Operator::Indent && op != Operator::Dedent {
+            self.throw_status_message("Register % is read only".to_string());
+            return;
+        }
+        match op {
+            Operator::Yank => {
+                let text = self.range_text(range);
+                self.store_register(reg, text, range.linewise, false);
+                let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+                if range.linewise {
+                    cur_tab.cursor_xy.1 = range.start.1;
+                } else {
+                    cur_tab.cursor_xy = range.start;
+                }
+                self.clamp_normal_cursor();
+            }
+            Operator::Delete => {
+                let text = self.delete_range(range, false);
+                self.store_register(reg, text, range.linewise, true);
+                if range.linewise {
+                    let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+                    let line = range.start.1.min(cur_tab.buf.last_line());
+                    cur_tab.cursor_xy = (motions::first_non_blank(&cur_tab.buf, line), line);
+                }
+                self.seal_edit();
+                self.clamp_normal_cursor();
+            }
+            Operator::Change => {
+                let text = self.delete_range(range, true);
+                self.store_register(reg, text, range.linewise, true);
+                // typed text joins the same undo step
+                self.mode = Mode::Insert;
+            }
+            Operator::Indent | Operator::Dedent => {
+                self.shift_lines(range.start.1, range.end.1, op == Operator::Indent, 1);
+            }
+        }
+    }
+
+    // > and < on lines first..=last, `times` shift widths
+    pub fn shift_lines(&mut self, first: usize, last: usize, indent: bool, times: usize) {
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        for line in first..=last {
+            for _ in 0..times {
+                shift_line(cur_tab, line, indent);
+            }
+        }
+        cur_tab.cursor_xy = (motions::first_non_blank(&cur_tab.buf, first), first);
+        self.seal_edit();
+    }
+
+    pub fn store_register(
+        &mut self,
+        reg: Option<char>,
+        text: String,
+        linewise: bool,
+        deleted: bool,
+    ) {
+        // nothing was yanked or deleted (x on empty line)
+        if text.is_empty() {
+            return;
+        }
+        let content = RegContent { text, linewise };
+        let res = match deleted {
+            true => self.registers.delete(reg, content),
+            false => self.registers.yank(reg, content),
+        };
+        if let Err(e) = res {
+            self.throw_status_error(e);
+        }
+    }
+
+    // p and P: puts register text count times after/before cursor
+    pub fn put_register(&mut self, reg: Option<char>, count: Option<usize>, before: bool) {
+        self.seal_edit();
+        let filename = self.tabs[self.cur_tab].filename.clone();
+        let content = match self.registers.get(reg, &filename) {
+            Some(c) if !c.text.is_empty() => c,
+            _ => {
+                self.throw_status_message(format!("Register {} is empty", reg.unwrap_or('"')));
+                return;
+            }
+        };
+        let n = count.unwrap_or(1).max(1);
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let (x, line) = cur_tab.cursor_xy;
+        if content.linewise {
+            let mut text = content.text;
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            let text = text.repeat(n);
+            let target = if before { line } else { line + 1 };
+            if target <= cur_tab.buf.last_line() {
+                cur_tab.insert_text(target, 0, &text, true);
+            } else {
+                // after the last line there is no line start to insert at
+                let len = cur_tab.buf.line_len(line);
+                let text = format!("\n{}", &text[..text.len() - 1]);
+                cur_tab.insert_text(line, len, &text, true);
+            }
+            cur_tab.cursor_xy = (motions::first_non_blank(&cur_tab.buf, target), target);
+        } else {
+            let text = content.text.repeat(n);
+            let at = if before {
+                x
+            } else {
+                cur_tab.buf.next_grapheme(line, x, 1)
+            };
+            let (end_line, end_x) = cur_tab.insert_text(line, at, &text, true);
+            // cursor goes on the last put char
+            cur_tab.cursor_xy = (end_x.saturating_sub(1), end_line);
+        }
+        self.seal_edit();
+        self.clamp_normal_cursor();
+    }
+
+    fn range_text(&self, range: OpRange) -> String {
+        let buf = &self.tabs[self.cur_tab].buf;
+        if range.linewise {
+            let last = range.end.1;
+            let mut text = buf.slice(range.start.1, 0, last, buf.line_len(last));
+            text.push('\n');
+            return text;
+        }
+        buf.slice(range.start.1, range.start.0, range.end.1, range.end.0)
+    }
+
+    // Removes range from buffer, returns removed text.
+    // With keep_line, linewise delete leaves one empty line (for c).
+    fn delete_range(&mut self, range: OpRange, keep_line: bool) -> String {
+        let text = self.range_text(range);
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let (first, last) = (range.start.1, range.end.1);
+        if !range.linewise {
+            cur_tab.remove_text(range.start.1, range.start.0, range.end.1, range.end.0, true);
+            cur_tab.cursor_xy = range.start;
+            return text;
+        }
+        let last_len = cur_tab.buf.line_len(last);
+        if keep_line {
+            cur_tab.remove_text(first, 0, last, last_len, true);
+        } else if last < cur_tab.buf.last_line() {
+            cur_tab.remove_text(first, 0, last + 1, 0, true);
+        } else if first > 0 {
+            let prev_len = cur_tab.buf.line_len(first - 1);
+            cur_tab.remove_text(first - 1, prev_len, last, last_len, true);
+        } else {
+            cur_tab.remove_text(first, 0, last, last_len, true);
+        }
+        cur_tab.cursor_xy = (0, first.min(cur_tab.buf.last_line()));
+        text
+    }
+}
+
+// Adds or removes one shift width of indentation
+fn shift_line(tab: &mut Tab, line: usize, indent: bool) {
+    let text = tab.buf.line(line);
+    if indent {
+        if text.is_empty() {
+            return;
+        }
+        tab.insert_text(line, 0, &" ".repeat(SHIFT_WIDTH), true);
+        return;
+    }
+    let removable = match text.chars().next() {
+        Some('\t') => 1,
+        _ => text
+            .chars()
+            .take(SHIFT_WIDTH)
+            .take_while(|c| *c == ' ')
+            .count(),
+    };
+    if removable > 0 {
+        tab.remove_text(line, 0, line, removable, true);
+    }
+}
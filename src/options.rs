@@ -0,0 +1,130 @@
+use crate::app::App;
+use crate::dotfiles::{self, OPTIONS, TAB_OPTIONS, on_off};
+use crate::tabs::Tab;
+use crate::wrap::LineNumbers;
+
+// Options changed at runtime: !set changes config for every tab, !setl only for
+// the current one. Tab values win over config ones till they're reset with `name<`.
+
+impl Tab {
+    // None value drops tab's own one
+    pub fn set_option(&mut self, name: &str, val: Option<&str>) -> Result<(), String> {
+        match name {
+            "tabstop" => self.tabstop = val.map(|v| dotfiles::parse_num(v, 1, 32)).transpose()?,
+            "expandtab" => self.expandtab = val.map(dotfiles::parse_bool).transpose()?,
+            "numbers" => self.numbers = val.map(dotfiles::parse_numbers).transpose()?,
+            "wrap" => self.wrap = val.map(dotfiles::parse_bool).transpose()?,
+            "autoreload" => self.autoreload = val.map(dotfiles::parse_bool).transpose()?,
+            "syntax" => self.syntax = val.map(dotfiles::parse_bool).transpose()?,
+            _ => return Err(format!("{} can't be set for a tab, use !set", name)),
+        }
+        Ok(())
+    }
+
+    // Own value of the tab, None if it follows config
+    pub fn option(&self, name: &str) -> Option<String> {
+        match name {
+            "tabstop" => self.tabstop.map(|v| v.to_string()),
+            "expandtab" => self.expandtab.map(on_off),
+            "numbers" => self.numbers.map(|v| v.name().to_string()),
+            "wrap" => self.wrap.map(on_off),
+            "autoreload" => self.autoreload.map(on_off),
+            "syntax" => self.syntax.map(on_off),
+            _ => None,
+        }
+    }
+
+    // Overridden options as (name, value), kept in sessions
+    pub fn own_options(&self) -> Vec<(String, String)> {
+        TAB_OPTIONS
+            .iter()
+            .filter_map(|name| self.option(name).map(|v| (name.to_string(), v)))
+            .collect()
+    }
+}
+
+impl App {
+    pub fn tab_tabstop(&self, tab: &Tab) -> usize {
+        tab.tabstop.unwrap_or(self.config.tabstop)
+    }
+
+    pub fn tab_expandtab(&self, tab: &Tab) -> bool {
+        tab.expandtab.unwrap_or(self.config.expandtab)
+    }
+
+    pub fn tab_numbers(&self, tab: &Tab) -> LineNumbers {
+        tab.numbers.unwrap_or(self.config.numbers)
+    }
+
+    // One arg of !set / !setl: name=value sets, name shows, name< (!setl) follows config again
+    pub fn set_option_arg(&mut self, arg: &str, local: bool) -> Result<String, String> {
+        let cur_tab = self.cur_tab;
+        if let Some((name, val)) = arg.split_once('=') {
+            match local {
+                true => self.tabs[cur_tab].set_option(name, Some(val))?,
+                false => self.config.set(name, val)?,
+            }
+            self.option_changed(name)?;
+            return self.option_value(name, local);
+        }
+        if let Some(name) = arg.strip_suffix('<') {
+            if !local {
+                return Err(format!("{}< is for !setl", name));
+            }
+            self.tabs[cur_tab].set_option(name, None)?;
+            self.option_changed(name)?;
+            return self.option_value(name, local);
+        }
+        self.option_value(arg, local)
+    }
+
+    fn option_value(&self, name: &str, local: bool) -> Result<String, String> {
+        let global = self
+            .config
+            .get(name)
+            .ok_or(format!("Unknown option {}", name))?;
+        let own = self.tabs[self.cur_tab].option(name);
+        Ok(match (own, local) {
+            (Some(v), _) => format!("{}={} (tab)", name, v),
+            (None, true) if TAB_OPTIONS.contains(&name) => format!("{}={} (config)", name, global),
+            (None, _) => format!("{}={}", name, global),
+        })
+    }
+
+    // Things depending on an option are brought up to date
+    fn option_changed(&mut self, name: &str) -> Result<(), String> {
+        match name {
+            "log_level" => self.logger.level = self.config.log_level,
+            "tabstop" | "numbers" | "wrap" | "scrolloff" => self.scroll_to_cursor(),
+            "theme" | "truecolor" => {
+                let theme = self.config.theme.clone();
+                if let Err(e) = self.set_theme(&theme) {
+                    // theme that failed to load isn't kept in config
+                    self.config.theme = self.theme.name.clone();
+                    return Err(e);
+                }
+            }
+            "clipboard.copy" | "clipboard.paste" => {
+                let (copy, paste) = (
+                    self.config.clipboard_copy.clone(),
+                    self.config.clipboard_paste.clone(),
+                );
+                self.registers.set_clipboard_cmds(copy, paste);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // !set without args: every option with its value, in new tab
+    pub fn list_options(&mut self) {
+        let lines: Vec<String> = OPTIONS
+            .iter()
+            .filter_map(|name| self.option_value(name, false).ok())
+            .collect();
+        let mut tab = Tab::new(Some("options".to_string()));
+        tab.str_into_buf(lines.join("\n"));
+        self.tabs.push(tab);
+        self.cur_tab = self.tabs.len() - 1;
+    }
+}
@@ -43,7 +43,7 @@ impl LuaLoader {
     }
     pub fn load_plug(&mut self, path: String, tx: Sender<PluginMessage>) -> Result<(), String> {
         let mut plug = LuaPlugin::new();
-        let plug_buf: String = match fs::read_to_string(path) {
+        let plug_buf: String = match fs::read_to_string(&path) {
             Ok(f) => f,
             Err(e) => {
                 let err_msg = "FS ERR: ".to_string() + &e.to_string();
@@ -70,6 +70,7 @@ impl LuaLoader {
         plug.version = globals
             .get("PLUGIN_VERSION")
             .unwrap_or("v1.0.0".to_string());
+        plug.path = path;
 
         plug.load_defaults(tx.clone(), self.plugins.len());
 
@@ -153,9 +154,18 @@ pub enum PluginMessage {
     Command(PlugCom),
     Event(AppEvent),
     RegisterCommand(String, Function, usize), // lua func!; usize for plug id
+    Map(String, String, MapRhs, usize),       // mode, keys, target, plug id
+    Unmap(String, String),                    // mode, keys
     Error(String),
 }
 
+// frevi_map target: text like in !map or lua func
+#[derive(Debug, Clone)]
+pub enum MapRhs {
+    Text(String),
+    Lua(Function),
+}
+
 #[derive(Debug, Clone)]
 pub enum AppEvent {
     KeyChar(char),
@@ -171,6 +181,7 @@ pub struct LuaPlugin {
     pub author: String,
     pub version: String,
     pub desc: String,
+    pub path: String, // file it was loaded from, kept in sessions
 }
 
 impl LuaPlugin {
@@ -181,6 +192,7 @@ impl LuaPlugin {
             author: String::new(),
             version: String::new(),
             desc: String::new(),
+            path: String::new(),
         }
     }
     fn load_defaults(&mut self, tx: Sender<PluginMessage>, plugID: usize) {
@@ -223,5 +235,50 @@ impl LuaPlugin {
                 }
             };
         globals.set("frevi_reg_com", reg_com_func);
+        let tx_map = tx.clone();
+        let map_func = match self.lua.create_function(
+            move |_, (mode, keys, target): (String, String, Value)| {
+                let rhs = match target {
+                    Value::String(s) => MapRhs::Text(s.to_str()?.to_string()),
+                    Value::Function(f) => MapRhs::Lua(f),
+                    _ => {
+                        return Err(mlua::Error::RuntimeError(
+                            "frevi_map target must be a string or a function".to_string(),
+                        ));
+                    }
+                };
+                tx_map
+                    .send(PluginMessage::Map(mode, keys, rhs, plugID))
+                    .map_err(|e| {
+                        mlua::Error::RuntimeError(format!("Failed to send message: {}", e))
+                    })?;
+                Ok(())
+            },
+        ) {
+            Ok(lf) => lf,
+            Err(e) => {
+                let _ = tx.send(PluginMessage::Error(e.to_string()));
+                return;
+            }
+        };
+        let _ = globals.set("frevi_map", map_func);
+        let tx_unmap = tx.clone();
+        let unmap_func = match self
+            .lua
+            .create_function(move |_, (mode, keys): (String, String)| {
+                tx_unmap
+                    .send(PluginMessage::Unmap(mode, keys))
+                    .map_err(|e| {
+                        mlua::Error::RuntimeError(format!("Failed to send message: {}", e))
+                    })?;
+                Ok(())
+            }) {
+            Ok(lf) => lf,
+            Err(e) => {
+                let _ = tx.send(PluginMessage::Error(e.to_string()));
+                return;
+            }
+        };
+        let _ = globals.set("frevi_unmap", unmap_func);
     }
 }
@@ -0,0 +1,59 @@
+use crossterm::event::{KeyCode, KeyEvent};
+
+use crate::app::{App, Mode};
+
+// Called with id of the tab the question was about
+pub type PromptAction = fn(&mut App, usize);
+
+// Question in status line answered with one key, like "[r]ecover, [d]iff, [x] discard".
+// Esc leaves it for later. Several questions wait in a queue and are asked one by one.
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub text: String,
+    pub choices: Vec<(char, PromptAction)>,
+    pub on_esc: Option<PromptAction>,
+    pub tab_id: usize,
+}
+
+impl App {
+    pub fn ask(&mut self, prompt: Prompt) {
+        match self.mode {
+            Mode::Insert => {
+                self.seal_edit();
+                self.mode = Mode::Normal;
+                self.clamp_normal_cursor();
+            }
+            Mode::Visual(_) => self.exit_visual(),
+            _ => {}
+        }
+        self.prompts.push(prompt);
+        self.mode = Mode::Prompt;
+    }
+
+    // Tab a prompt is about, if it wasn't closed meanwhile
+    pub fn tab_by_id(&self, id: usize) -> Option<usize> {
+        self.tabs.iter().position(|t| t.id == id)
+    }
+
+    pub fn handle_prompt_key(&mut self, key: KeyEvent) {
+        let Some(prompt) = self.prompts.first() else {
+            self.mode = Mode::Normal;
+            return;
+        };
+        let action = match key.code {
+            KeyCode::Esc => prompt.on_esc,
+            KeyCode::Char(ch) => match prompt.choices.iter().find(|(c, _)| *c == ch) {
+                Some((_, action)) => Some(*action),
+                None => return,
+            },
+            _ => return,
+        };
+        let prompt = self.prompts.remove(0);
+        if self.prompts.is_empty() {
+            self.mode = Mode::Normal;
+        }
+        if let Some(action) = action {
+            action(self, prompt.tab_id);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use regex::Regex;
+
+use crate::search;
+use crate::tabs::Tab;
+
+// Ex-style line range typed before command name: !10,20s/a/b/, !%s/a/b/g, !'<,'>s/a/b/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineRange {
+    pub start: usize, // lines, 0-based, inclusive
+    pub end: usize,
+}
+
+impl LineRange {
+    pub fn single(line: usize) -> LineRange {
+        LineRange {
+            start: line,
+            end: line,
+        }
+    }
+}
+
+// Pattern between delimiters, \/ inside gives /. Returns pattern and text after closing one
+// (closing delimiter may be omitted at the end, like in vim).
+fn split_pattern(text: &str, delim: char) -> (String, &str) {
+    let mut pattern = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        if ch == delim {
+            return (pattern, &text[i + ch.len_utf8()..]);
+        }
+        if ch == '\\' {
+            match chars.next() {
+                Some((_, c)) if c == delim => pattern.push(c),
+                Some((_, c)) => {
+                    pattern.push('\\');
+                    pattern.push(c);
+                }
+                None => pattern.push('\\'),
+            }
+        } else {
+            pattern.push(ch);
+        }
+    }
+    (pattern, "")
+}
+
+// Line of next match after (or before) current line, wraps around buffer end
+fn search_line(tab: &Tab, re: &Regex, backward: bool) -> Option<usize> {
+    let total = tab.buf.len_lines();
+    let cur = tab.cursor_xy.1;
+    (1..=total)
+        .map(|i| match backward {
+            true => (cur + total - i) % total,
+            false => (cur + i) % total,
+        })
+        .find(|&line| re.is_match(&tab.buf.line(line)))
+}
+
+// One address: 12 . $ 'a /pat/ ?pat?
+// Empty pattern (//) means last searched one.
+fn parse_address<'a>(
+    text: &'a str,
+    tab: &Tab,
+    last_search: Option<&Regex>,
+) -> Result<(Option<usize>, &'a str), String> {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some('.') => Ok((Some(tab.cursor_xy.1), chars.as_str())),
+        Some('$') => Ok((Some(tab.buf.last_line()), chars.as_str())),
+        Some('\'') => {
+            let mark = match chars.next() {
+                Some(m) => m,
+                None => return Err("Mark name expected after '".to_string()),
+            };
+            match tab.marks.get(&mark) {
+                Some(&(_, line)) => Ok((Some(line), chars.as_str())),
+                None => Err(format!("Mark '{} is not set", mark)),
+            }
+        }
+        Some(d) if d == '/' || d == '?' => {
+            let (pattern, rest) = split_pattern(chars.as_str(), d);
+            let compiled;
+            let re = match pattern.is_empty() {
+                true => match last_search {
+                    Some(re) => re,
+                    None => return Err("No previous search pattern".to_string()),
+                },
+                false => {
+                    compiled =
+                        search::compile(&pattern).map_err(|e| format!("Invalid pattern: {}", e))?;
+                    &compiled
+                }
+            };
+            match search_line(tab, re, d == '?') {
+                Some(line) => Ok((Some(line), rest)),
+                None => Err(format!("Pattern not found: {}", pattern)),
+            }
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let digits = text.chars().take_while(|c| c.is_ascii_digit()).count();
+            let num: usize = match text[..digits].parse() {
+                Ok(n) => n,
+                Err(e) => return Err(format!("Invalid range: {}", e)),
+            };
+            // line numbers are 1-based for user, 0 means the first line too
+            Ok((Some(num.saturating_sub(1)), &text[digits..]))
+        }
+        _ => Ok((None, text)),
+    }
+}
+
+// Address followed by offsets: $-3, 'a+2, /fn/+1, or just +5 (from current line).
+// Bare + and - mean one line.
+fn parse_line<'a>(
+    text: &'a str,
+    tab: &Tab,
+    last_search: Option<&Regex>,
+) -> Result<(Option<usize>, &'a str), String> {
+    let (base, mut rest) = parse_address(text, tab, last_search)?;
+    let mut line = base.map(|l| l as isize);
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let after = &rest[1..];
+        let digits = after.chars().take_while(|c| c.is_ascii_digit()).count();
+        let num: isize = match digits {
+            0 => 1,
+            _ => after[..digits]
+                .parse()
+                .map_err(|e| format!("Invalid range: {}", e))?,
+        };
+        let cur = line.unwrap_or(tab.cursor_xy.1 as isize);
+        let moved = match sign {
+            '+' => cur.checked_add(num),
+            _ => cur.checked_sub(num),
+        };
+        line = Some(moved.ok_or("Invalid range: offset too large")?);
+        rest = &after[digits..];
+    }
+    match line {
+        Some(l) if l < 0 => Err("Invalid range: line before the first one".to_string()),
+        l => Ok((l.map(|l| l as usize), rest)),
+    }
+}
+
+// Parses range at the start of text, returns it with the rest of text.
+// No range gives None, so commands can pick their own default.
+pub fn parse_range<'a>(
+    text: &'a str,
+    tab: &Tab,
+    last_search: Option<&Regex>,
+) -> Result<(Option<LineRange>, &'a str), String> {
+    let last_line = tab.buf.last_line();
+    if let Some(rest) = text.strip_prefix('%') {
+        return Ok((
+            Some(LineRange {
+                start: 0,
+                end: last_line,
+            }),
+            rest,
+        ));
+    }
+    let (first, rest) = parse_line(text, tab, last_search)?;
+    let (start, end, rest) = match rest.strip_prefix(',') {
+        Some(after) => {
+            // missing address means current line: ",5" or "5,"
+            let (second, rest) = parse_line(after, tab, last_search)?;
+            let cur = tab.cursor_xy.1;
+            (first.unwrap_or(cur), second.unwrap_or(cur), rest)
+        }
+        None => match first {
+            Some(line) => (line, line, rest),
+            None => return Ok((None, text)),
+        },
+    };
+    if start > last_line || end > last_line {
+        return Err(format!("Invalid range: buffer has {} lines", last_line + 1));
+    }
+    Ok((
+        Some(LineRange {
+            start: start.min(end),
+            end: start.max(end),
+        }),
+        rest,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // five lines, cursor on the third, mark a on the second
+    fn tab() -> Tab {
+        let mut tab = Tab::new(None);
+        tab.str_into_buf("fn a\nlet b\nfn c\nlet d\nend".to_string());
+        tab.cursor_xy = (0, 2);
+        tab.marks.insert('a', (0, 1));
+        tab
+    }
+
+    fn range(text: &str) -> Result<(Option<LineRange>, &str), String> {
+        parse_range(text, &tab(), None)
+    }
+
+    fn lines(start: usize, end: usize) -> Option<LineRange> {
+        Some(LineRange { start, end })
+    }
+
+    #[test]
+    fn whole_and_single() {
+        assert_eq!(range("%s/a/b/"), Ok((lines(0, 4), "s/a/b/")));
+        assert_eq!(range(".d"), Ok((lines(2, 2), "d")));
+        assert_eq!(range("$"), Ok((lines(4, 4), "")));
+        assert_eq!(range("2"), Ok((lines(1, 1), "")));
+        assert_eq!(range("0"), Ok((lines(0, 0), "")));
+        assert_eq!(range("s/a/b/"), Ok((None, "s/a/b/")));
+    }
+
+    #[test]
+    fn pairs() {
+        assert_eq!(range("1,$"), Ok((lines(0, 4), "")));
+        assert_eq!(range(",4"), Ok((lines(2, 3), "")));
+        assert_eq!(range("2,"), Ok((lines(1, 2), "")));
+        // reversed range is swapped
+        assert_eq!(range("4,2"), Ok((lines(1, 3), "")));
+    }
+
+    #[test]
+    fn marks() {
+        assert_eq!(range("'a,."), Ok((lines(1, 2), "")));
+        assert_eq!(range("'b"), Err("Mark 'b is not set".to_string()));
+        assert!(range("'").is_err());
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(range("/let/"), Ok((lines(3, 3), "")));
+        assert_eq!(range("?let?"), Ok((lines(1, 1), "")));
+        // search wraps around the end
+        assert_eq!(range("/^fn a/"), Ok((lines(0, 0), "")));
+        assert_eq!(range(r"/a\/b/d"), Err("Pattern not found: a/b".to_string()));
+        assert_eq!(range(".,/end"), Ok((lines(2, 4), "")));
+        assert!(range("//").is_err());
+        let last = Regex::new("let").unwrap();
+        let (r, _) = parse_range("//", &tab(), Some(&last)).unwrap();
+        assert_eq!(r, Some(LineRange::single(3)));
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(range("$-3"), Ok((lines(1, 1), "")));
+        assert_eq!(range("'a+2"), Ok((lines(3, 3), "")));
+        assert_eq!(range("/let/+1"), Ok((lines(4, 4), "")));
+        assert_eq!(range("+,++"), Ok((lines(3, 4), "")));
+        assert_eq!(range("-2"), Ok((lines(0, 0), "")));
+        assert_eq!(range("1+1-1"), Ok((lines(0, 0), "")));
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(range("6").is_err());
+        assert!(range("1,9").is_err());
+        assert!(range("$+1").is_err());
+        assert!(range("-3").is_err());
+        assert!(range(".+9223372036854775807").is_err());
+        assert!(range(".+9223372036854775806+5").is_err());
+        assert!(range("99999999999999999999999").is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegContent {
+    pub text: String,
+    pub linewise: bool,
+}
+
+// Vi-like registers:
+// "  unnamed, points to last written one
+// 0  last yank, 1-9 ring of deletes (linewise or multiline), - small deletes
+// a-z named (A-Z appends), _ black hole, % current filename (read only)
+// + * system clipboard
+#[derive(Debug)]
+pub struct Registers {
+    unnamed: Option<RegContent>,
+    regs: HashMap<char, RegContent>,
+    copy_cmd: Option<String>,
+    paste_cmd: Option<String>,
+}
+
+impl Registers {
+    pub fn new(copy_cmd: Option<String>, paste_cmd: Option<String>) -> Registers {
+        Registers {
+            unnamed: None,
+            regs: HashMap::new(),
+            copy_cmd,
+            paste_cmd,
+        }
+    }
+
+    // + and * commands, after !set clipboard.copy / clipboard.paste
+    pub fn set_clipboard_cmds(&mut self, copy_cmd: Option<String>, paste_cmd: Option<String>) {
+        self.copy_cmd = copy_cmd;
+        self.paste_cmd = paste_cmd;
+    }
+
+    pub fn is_valid_name(reg: char) -> bool {
+        reg.is_ascii_alphanumeric() || "\"-_%+*".contains(reg)
+    }
+
+    // Stores yanked text. Returns error message if something went wrong
+    pub fn yank(&mut self, reg: Option<char>, content: RegContent) -> Result<(), String> {
+        match reg {
+            None | Some('"') => {
+                self.regs.insert('0', content.clone());
+                self.unnamed = Some(content);
+                Ok(())
+            }
+            Some(r) => self.write(r, content),
+        }
+    }
+
+    // Stores deleted text, shifting the numbered ring
+    pub fn delete(&mut self, reg: Option<char>, content: RegContent) -> Result<(), String> {
+        match reg {
+            None | Some('"') => {
+                if content.linewise || content.text.contains('\n') {
+                    for i in (1..9).rev() {
+                        let from = char::from_digit(i, 10).unwrap_or('1');
+                        let to = char::from_digit(i + 1, 10).unwrap_or('9');
+                        if let Some(c) = self.regs.remove(&from) {
+                            self.regs.insert(to, c);
+                        }
+                    }
+                    self.regs.insert('1', content.clone());
+                } else {
+                    self.regs.insert('-', content.clone());
+                }
+                self.unnamed = Some(content);
+                Ok(())
+            }
+            Some(r) => self.write(r, content),
+        }
+    }
+
+    // Writes into explicitly named register
+    pub fn write(&mut self, reg: char, content: RegContent) -> Result<(), String> {
+        match reg {
+            '_' => Ok(()),
+            '%' => Err("Register % is read only".to_string()),
+            '+' | '*' => {
+                let res = self.copy_to_clipboard(reg, &content.text);
+                self.regs.insert(reg, content.clone());
+                self.unnamed = Some(content);
+                res
+            }
+            'A'..='Z' => {
+                let lower = reg.to_ascii_lowercase();
+                let res = match self.regs.remove(&lower) {
+                    Some(old) => {
+                        let mut text = old.text;
+                        let linewise = old.linewise || content.linewise;
+                        if linewise && !text.ends_with('\n') {
+                            text.push('\n');
+                        }
+                        text.push_str(&content.text);
+                        if linewise && !text.ends_with('\n') {
+                            text.push('\n');
+                        }
+                        RegContent { text, linewise }
+                    }
+                    None => content,
+                };
+                self.regs.insert(lower, res.clone());
+                self.unnamed = Some(res);
+                Ok(())
+            }
+            r if Registers::is_valid_name(r) => {
+                self.regs.insert(r, content.clone());
+                self.unnamed = Some(content);
+                Ok(())
+            }
+            r => Err(format!("Invalid register {}", r)),
+        }
+    }
+
+    // Stores recorded macro; unlike yanks it leaves unnamed register alone
+    pub fn record(&mut self, reg: char, text: String) -> Result<(), String> {
+        let unnamed = self.unnamed.clone();
+        let res = self.write(
+            reg,
+            RegContent {
+                text,
+                linewise: false,
+            },
+        );
+        self.unnamed = unnamed;
+        res
+    }
+
+    // filename is the current tab one, for %
+    pub fn get(&self, reg: Option<char>, filename: &str) -> Option<RegContent> {
+        match reg {
+            None | Some('"') => self.unnamed.clone(),
+            Some('_') => None,
+            Some('%') => Some(RegContent {
+                text: filename.to_string(),
+                linewise: false,
+            }),
+            Some(r) if r == '+' || r == '*' => match self.paste_from_clipboard() {
+                Some(text) => Some(RegContent {
+                    linewise: text.ends_with('\n'),
+                    text,
+                }),
+                // no paste command; what we copied ourselves is the best guess
+                None => self.regs.get(&r).cloned(),
+            },
+            Some(r) => self.regs.get(&r.to_ascii_lowercase()).cloned(),
+        }
+    }
+
+    // All non-empty registers in display order
+    pub fn list(&self, filename: &str) -> Vec<(char, RegContent)> {
+        let mut res: Vec<(char, RegContent)> = Vec::new();
+        if let Some(c) = &self.unnamed {
+            res.push(('"', c.clone()));
+        }
+        let order = "0123456789-abcdefghijklmnopqrstuvwxyz+*";
+        for r in order.chars() {
+            if let Some(c) = self.regs.get(&r) {
+                res.push((r, c.clone()));
+            }
+        }
+        if !filename.is_empty() {
+            res.push((
+                '%',
+                RegContent {
+                    text: filename.to_string(),
+                    linewise: false,
+                },
+            ));
+        }
+        res
+    }
+
+    // External command is used if configured, OSC 52 otherwise or if it failed
+    fn copy_to_clipboard(&self, reg: char, text: &str) -> Result<(), String> {
+        if let Some(cmd) = &self.copy_cmd {
+            match run_with_stdin(cmd, text) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    write_osc52(reg, text)?;
+                    return Err(format!("Clipboard command failed ({}), used OSC 52", e));
+                }
+            }
+        }
+        write_osc52(reg, text)
+    }
+
+    fn paste_from_clipboard(&self) -> Option<String> {
+        let cmd = self.paste_cmd.as_ref()?;
+        let out = shell_command(cmd).output().ok()?;
+        if !out.status.success() {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&out.stdout).to_string())
+    }
+}
+
+fn shell_command(cmd: &str) -> Command {
+    if cfg!(target_os = "windows") {
+        let mut c = Command::new("cmd");
+        c.args(["/C", cmd]);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.args(["-c", cmd]);
+        c
+    }
+}
+
+fn run_with_stdin(cmd: &str, text: &str) -> Result<(), String> {
+    let mut child = shell_command(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(text.as_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("exited with {}", status));
+    }
+    Ok(())
+}
+
+// Asks the terminal to set clipboard: ESC ] 52 ; c ; base64 BEL
+fn write_osc52(reg: char, text: &str) -> Result<(), String> {
+    let selection = if reg == '*' { 'p' } else { 'c' };
+    let seq = format!("\x1b]52;{};{}\x07", selection, base64(text.as_bytes()));
+    let mut out = std::io::stdout();
+    out.write_all(seq.as_bytes())
+        .and_then(|_| out.flush())
+        .map_err(|e| e.to_string())
+}
+
+fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut res = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        res.push(TABLE[(n >> 18) as usize & 63] as char);
+        res.push(TABLE[(n >> 12) as usize & 63] as char);
+        res.push(match chunk.len() {
+            1 => '=',
+            _ => TABLE[(n >> 6) as usize & 63] as char,
+        });
+        res.push(match chunk.len() {
+            3 => TABLE[n as usize & 63] as char,
+            _ => '=',
+        });
+    }
+    res
+}
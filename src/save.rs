@@ -0,0 +1,214 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::app::{App, Mode};
+use crate::disk::{DiskInfo, DiskStamp};
+use crate::fileformat;
+
+// Where the previous version of a file is copied before it gets overwritten
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backup {
+    Off,
+    Beside,       // file~ next to the file
+    Dir(PathBuf), // %home%me%file~ in that dir, so same names from different dirs don't clash
+}
+
+impl Backup {
+    // backup option of config.toml (or old ~/.frevi/backup): off, beside or a dir path (~/ is home)
+    pub fn from_cfg(line: &str) -> Backup {
+        match line.trim() {
+            "" | "off" => Backup::Off,
+            "beside" => Backup::Beside,
+            dir => {
+                let dir = match (dir.strip_prefix("~/"), dirs::home_dir()) {
+                    (Some(rest), Some(home)) => home.join(rest),
+                    _ => PathBuf::from(dir),
+                };
+                Backup::Dir(dir)
+            }
+        }
+    }
+
+    pub fn to_cfg(&self) -> String {
+        match self {
+            Backup::Off => "off".to_string(),
+            Backup::Beside => "beside".to_string(),
+            Backup::Dir(dir) => dir.to_string_lossy().to_string(),
+        }
+    }
+
+    fn path_for(&self, file: &Path) -> Option<PathBuf> {
+        let name = file.file_name()?.to_string_lossy().to_string();
+        match self {
+            Backup::Off => None,
+            Backup::Beside => Some(file.with_file_name(name + "~")),
+            Backup::Dir(dir) => {
+                let full = fs::canonicalize(file).unwrap_or(file.to_path_buf());
+                let flat = full.to_string_lossy().replace(['/', '\\'], "%");
+                Some(dir.join(flat + "~"))
+            }
+        }
+    }
+}
+
+// File a path points to, through any chain of symlinks. Works for dangling
+// links too, the file is created where the link points.
+fn resolve_links(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let dest = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(dir) => dir.join(dest),
+                    None => dest,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+    Err(io::Error::other("Too many levels of symlinks"))
+}
+
+// Hidden temp file next to the target, so rename stays on one filesystem
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{}.{}.frevi-tmp", name, std::process::id()))
+}
+
+fn dir_of(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+// Whether the file can be replaced by a new one without anybody noticing:
+// same owner can be set and no other hard links would keep the old contents
+#[cfg(unix)]
+fn replaceable(meta: &fs::Metadata, tmp: &File) -> bool {
+    use std::os::unix::fs::{MetadataExt, fchown};
+    meta.nlink() <= 1 && fchown(tmp, Some(meta.uid()), Some(meta.gid())).is_ok()
+}
+
+#[cfg(not(unix))]
+fn replaceable(_meta: &fs::Metadata, _tmp: &File) -> bool {
+    true
+}
+
+// Writes over the file itself: keeps its inode, owner and links, but a crash
+// halfway leaves it cut
+fn write_in_place(target: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(target)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+// Temp file gets the bytes, is synced and renamed over the target, so on a crash
+// or a full disk the old file stays whole. Mode and owner of the old file are kept.
+// Falls back to writing in place when the dir isn't writable or the owner can't be kept.
+fn write_atomic(target: &Path, bytes: &[u8], meta: Option<&fs::Metadata>) -> io::Result<()> {
+    let tmp_path = temp_path(target);
+    let mut tmp = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+    {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied && meta.is_some() => {
+            return write_in_place(target, bytes);
+        }
+        Err(e) => return Err(e),
+    };
+    if let Some(meta) = meta
+        && !replaceable(meta, &tmp)
+    {
+        drop(tmp);
+        let _ = fs::remove_file(&tmp_path);
+        return write_in_place(target, bytes);
+    }
+    let res = (|| {
+        tmp.write_all(bytes)?;
+        if let Some(meta) = meta {
+            tmp.set_permissions(meta.permissions())?;
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, target)
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return res;
+    }
+    // rename itself has to hit the disk too
+    if let Ok(dir) = File::open(dir_of(target)) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+// Saves bytes into file at path (following symlinks), backing up the old one first
+pub fn write_file(path: &str, bytes: &[u8], backup: &Backup) -> io::Result<()> {
+    let target = resolve_links(Path::new(path))?;
+    let meta = fs::metadata(&target).ok();
+    if meta.as_ref().is_some_and(|m| m.is_dir()) {
+        return Err(io::Error::other(format!("{} is a directory", path)));
+    }
+    if meta.is_some()
+        && let Some(backup_path) = backup.path_for(&target)
+    {
+        if let Some(dir) = backup_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::copy(&target, &backup_path)
+            .map_err(|e| io::Error::other(format!("Backup to {:?} failed: {}", backup_path, e)))?;
+    }
+    write_atomic(&target, bytes, meta.as_ref())
+}
+
+impl App {
+    // Main loop: with autosave on, changed tabs are written into their files once
+    // keys stop for that long. Tabs whose file changed on disk are left to the prompt.
+    pub fn update_autosave(&mut self) {
+        let wait = Duration::from_secs(self.config.autosave);
+        if wait.is_zero()
+            || self.last_key.elapsed() < wait
+            || self.last_autosave > self.last_key
+            || matches!(self.mode, Mode::Insert | Mode::Prompt)
+        {
+            return;
+        }
+        self.last_autosave = Instant::now();
+        for idx in 0..self.tabs.len() {
+            let tab = &self.tabs[idx];
+            if tab.filename.is_empty() || !tab.changed || !self.disk_unchanged(idx) {
+                continue;
+            }
+            if let Err(e) = self.write_tab(idx) {
+                let msg = format!("Autosave of {} failed: {}", self.tabs[idx].filename, e);
+                self.throw_status_error(msg);
+            }
+        }
+    }
+
+    // Writes tab into its own file the way !w does
+    fn write_tab(&mut self, idx: usize) -> Result<(), String> {
+        let tab = &self.tabs[idx];
+        let bytes = fileformat::to_bytes(&tab.buf, &tab.format).map_err(|e| e.to_string())?;
+        write_file(&tab.filename, &bytes, &self.config.backup).map_err(|e| e.to_string())?;
+        let tab = &mut self.tabs[idx];
+        tab.disk = DiskInfo::new(DiskStamp::written(&tab.filename, &bytes));
+        tab.mark_saved();
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use regex::{Regex, RegexBuilder};
+
+use crate::app::{App, Mode};
+use crate::buffer::TextBuffer;
+use crate::tabs::Tab;
+
+// State of / ? n N
+#[derive(Debug)]
+pub struct Search {
+    pub pattern: String,
+    pub regex: Option<Regex>,
+    pub backward: bool,  // direction of last search, ? is backward
+    pub highlight: bool, // matches stay highlighted until !nohl
+    pub history: Vec<String>,
+    pub hist_ctr: usize,
+    origin: ((usize, usize), usize), // cursor and scroll before prompt, restored on Esc
+}
+
+impl Search {
+    pub fn new() -> Search {
+        Search {
+            pattern: String::new(),
+            regex: None,
+            backward: false,
+            highlight: false,
+            history: Vec::new(),
+            hist_ctr: 0,
+            origin: ((0, 0), 0),
+        }
+    }
+}
+
+// Smart-case: pattern is case sensitive only if it has uppercase letters
+// (escapes like \S or \W don't count)
+pub fn compile(pattern: &str) -> Result<Regex, String> {
+    let mut has_upper = false;
+    let mut escaped = false;
+    for ch in pattern.chars() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        if ch == '\\' {
+            escaped = true;
+        } else if ch.is_uppercase() {
+            has_upper = true;
+        }
+    }
+    RegexBuilder::new(pattern)
+        .case_insensitive(!has_upper)
+        .build()
+        .map_err(|e| match e {
+            regex::Error::Syntax(s) => {
+                let last = s.lines().last().unwrap_or("").trim();
+                last.trim_start_matches("error: ").to_string()
+            }
+            e => e.to_string(),
+        })
+}
+
+// Matches in one line as [from, to) char ranges
+pub fn line_matches(re: &Regex, text: &str) -> Vec<(usize, usize)> {
+    let mut res: Vec<(usize, usize)> = Vec::new();
+    let mut chars_before = 0;
+    let mut last_byte = 0;
+    for m in re.find_iter(text) {
+        chars_before += text[last_byte..m.start()].chars().count();
+        let len = m.as_str().chars().count();
+        res.push((chars_before, chars_before + len));
+        chars_before += len;
+        last_byte = m.end();
+    }
+    res
+}
+
+// All matches in buffer as (line, from, to)
+pub fn all_matches(buf: &TextBuffer, re: &Regex) -> Vec<(usize, usize, usize)> {
+    let mut res: Vec<(usize, usize, usize)> = Vec::new();
+    for line in 0..buf.len_lines() {
+        for (from, to) in line_matches(re, &buf.line(line)) {
+            res.push((line, from, to));
+        }
+    }
+    res
+}
+
+// Index of next match after (x, line) in given direction and whether search wrapped
+fn next_match(
+    matches: &[(usize, usize, usize)],
+    pos: (usize, usize),
+    backward: bool,
+) -> Option<(usize, bool)> {
+    if matches.is_empty() {
+        return None;
+    }
+    let (x, line) = pos;
+    if backward {
+        match matches
+            .iter()
+            .rposition(|&(l, from, _)| (l, from) < (line, x))
+        {
+            Some(i) => Some((i, false)),
+            None => Some((matches.len() - 1, true)),
+        }
+    } else {
+        match matches
+            .iter()
+            .position(|&(l, from, _)| (l, from) > (line, x))
+        {
+            Some(i) => Some((i, false)),
+            None => Some((0, true)),
+        }
+    }
+}
+
+impl App {
+    pub fn start_search(&mut self, backward: bool) {
+        self.seal_edit();
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        self.search.origin = (cur_tab.cursor_xy, cur_tab.scroll_offset);
+        self.search.hist_ctr = self.search.history.len();
+        self.mode = Mode::Search { backward };
+        self.status_message = false;
+        self.command_buf.clear();
+        self.cursor_pos_xy.0 = 0;
+    }
+
+    pub fn handle_search_key(&mut self, key: KeyEvent) {
+        let backward = match self.mode {
+            Mode::Search { backward } => backward,
+            _ => return,
+        };
+        match key.code {
+            KeyCode::Esc => self.cancel_search(),
+            KeyCode::Backspace if self.command_buf.is_empty() => self.cancel_search(),
+            KeyCode::Enter => {
+                let pattern = self.command_buf.clone();
+                self.command_buf.clear();
+                self.mode = Mode::Normal;
+                self.restore_search_origin();
+                // empty pattern repeats the last one
+                if !pattern.is_empty() {
+                    if self.search.history.last() != Some(&pattern) {
+                        self.search.history.push(pattern.clone());
+                    }
+                    self.search.pattern = pattern;
+                }
+                self.search.backward = backward;
+                match compile(&self.search.pattern) {
+                    Ok(re) => self.search.regex = Some(re),
+                    Err(e) => {
+                        self.search.regex = None;
+                        self.throw_status_error(format!("Invalid pattern: {}", e));
+                        return;
+                    }
+                }
+                self.search_next(false, None);
+            }
+            KeyCode::Up => {
+                if self.search.hist_ctr > 0 {
+                    self.search.hist_ctr -= 1;
+                    self.command_buf = self.search.history[self.search.hist_ctr].clone();
+                    self.cursor_pos_xy.0 = self.command_buf.chars().count() as u16;
+                    self.update_incsearch(backward);
+                }
+            }
+            KeyCode::Down => {
+                if self.search.hist_ctr < self.search.history.len() {
+                    self.search.hist_ctr += 1;
+                    self.command_buf = self
+                        .search
+                        .history
+                        .get(self.search.hist_ctr)
+                        .cloned()
+                        .unwrap_or_default();
+                    self.cursor_pos_xy.0 = self.command_buf.chars().count() as u16;
+                    self.update_incsearch(backward);
+                }
+            }
+            _ => {
+                // line editing is the same as in command line
+                self.handle_command_key(key);
+                self.update_incsearch(backward);
+            }
+        }
+    }
+
+    fn cancel_search(&mut self) {
+        self.command_buf.clear();
+        self.cursor_pos_xy.0 = 0;
+        self.mode = Mode::Normal;
+        self.restore_search_origin();
+        self.search.regex = compile(&self.search.pattern).ok();
+    }
+
+    fn restore_search_origin(&mut self) {
+        let (cursor, scroll) = self.search.origin;
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        cur_tab.cursor_xy = cursor;
+        cur_tab.scroll_offset = scroll;
+    }
+
+    // Moves to the first match of what is typed so far
+    fn update_incsearch(&mut self, backward: bool) {
+        self.restore_search_origin();
+        self.search.regex = match self.command_buf.is_empty() {
+            true => None,
+            false => compile(&self.command_buf).ok(),
+        };
+        let re = match &self.search.regex {
+            Some(re) => re,
+            None => return,
+        };
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let matches = all_matches(&cur_tab.buf, re);
+        if let Some((i, _)) = next_match(&matches, cur_tab.cursor_xy, backward) {
+            let (line, from, _) = matches[i];
+            self.tabs[self.cur_tab].cursor_xy = (from, line);
+            self.scroll_to_cursor();
+        }
+    }
+
+    // n (reverse = false) and N; count jumps over more matches
+    pub fn search_next(&mut self, reverse: bool, count: Option<usize>) {
+        self.seal_edit();
+        let re = match &self.search.regex {
+            Some(re) => re,
+            None => {
+                self.throw_status_message("No previous search pattern".to_string());
+                return;
+            }
+        };
+        let backward = self.search.backward != reverse;
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let matches = all_matches(&cur_tab.buf, re);
+        let mut pos = cur_tab.cursor_xy;
+        let mut found: Option<usize> = None;
+        let mut wrapped = false;
+        for _ in 0..count.unwrap_or(1).max(1) {
+            match next_match(&matches, pos, backward) {
+                Some((i, w)) => {
+                    wrapped |= w;
+                    found = Some(i);
+                    pos = (matches[i].1, matches[i].0);
+                }
+                None => break,
+            }
+        }
+        self.search.highlight = true;
+        let i = match found {
+            Some(i) => i,
+            None => {
+                let msg = format!("Pattern not found: {}", self.search.pattern);
+                self.throw_status_message(msg);
+                return;
+            }
+        };
+        self.push_jump();
+        self.tabs[self.cur_tab].cursor_xy = pos;
+        self.scroll_to_cursor();
+        let mut msg = format!("match {} of {}", i + 1, matches.len());
+        if wrapped {
+            msg.push_str(match backward {
+                true => " (hit TOP, continuing at BOTTOM)",
+                false => " (hit BOTTOM, continuing at TOP)",
+            });
+        }
+        self.throw_status_message(msg);
+    }
+
+    // * and #: searches word under cursor
+    pub fn search_word(&mut self, backward: bool, count: Option<usize>) {
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let chars: Vec<char> = cur_tab.buf.line(cur_tab.cursor_xy.1).chars().collect();
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let x = match (cur_tab.cursor_xy.0..chars.len()).find(|&i| is_word(chars[i])) {
+            Some(x) => x,
+            None => {
+                self.throw_status_message("No word under cursor".to_string());
+                return;
+            }
+        };
+        let mut start = x;
+        while start > 0 && is_word(chars[start - 1]) {
+            start -= 1;
+        }
+        let mut end = x;
+        while end < chars.len() && is_word(chars[end]) {
+            end += 1;
+        }
+        let word: String = chars[start..end].iter().collect();
+        self.search.pattern = format!(r"\b{}\b", regex::escape(&word));
+        if self.search.history.last() != Some(&self.search.pattern) {
+            self.search.history.push(self.search.pattern.clone());
+        }
+        self.search.backward = backward;
+        // exact case for the word, not smart-case
+        self.search.regex = Regex::new(&self.search.pattern).ok();
+        self.tabs[self.cur_tab].cursor_xy.0 = start;
+        self.search_next(false, count);
+    }
+
+    // Matches to highlight in a shown line, while typing a pattern or after a search
+    pub fn search_matches_in(&self, text: &str) -> Vec<(usize, usize)> {
+        let active = matches!(self.mode, Mode::Search { .. }) || self.search.highlight;
+        match (&self.search.regex, active) {
+            (Some(re), true) => line_matches(re, text),
+            _ => Vec::new(),
+        }
+    }
+}
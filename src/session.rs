@@ -0,0 +1,396 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::app::App;
+use crate::dotfiles::TAB_OPTIONS;
+use crate::logger::LogLevel;
+use crate::plugin::PlugLoaders;
+use crate::save::{self, Backup};
+use crate::swap::abs_path;
+use crate::tabs::Tab;
+use crate::window::{Window, Windows};
+
+// Session keeps which files are open and where in them the cursor was, windows,
+// aliases and plugins, in ~/.frevi/sessions/<name>. Texts stay in their files,
+// tabs without a file come back empty.
+//
+// It's a text file of "key value" lines; every tab starts with a "tab" line:
+//   frevi session
+//   cur_tab 1
+//   layout v0.500 w w            (see window::Layout::to_text)
+//   window 0 0 0 0 0             (tab, cursor x and line, scroll, left col)
+//   window 1 4 120 100 0
+//   focus 1
+//   plugin /home/me/plugs/count.lua
+//   alias wq !w
+//   tab
+//   file /home/me/src/main.rs
+//   name main.rs
+//   cursor 4 120
+//   scroll 100 0
+//   wrap off
+
+const HEADER: &str = "frevi session";
+pub const DEFAULT_SESSION: &str = "default";
+
+#[derive(Debug, Default)]
+struct TabEntry {
+    file: String,
+    name: String,
+    cursor: (usize, usize),      // (x, line)
+    scroll: (usize, usize),      // scroll_offset, left_col
+    opts: Vec<(String, String)>, // options set by !setl or !tab wrap
+}
+
+#[derive(Debug, Default)]
+struct WinEntry {
+    tab: usize,             // index in tabs
+    cursor: (usize, usize), // (x, line)
+    scroll: (usize, usize), // scroll_offset, left_col
+}
+
+#[derive(Debug, Default)]
+struct Session {
+    cur_tab: usize,
+    layout: String,
+    windows: Vec<WinEntry>,
+    focus: usize,
+    plugins: Vec<String>,
+    aliases: Vec<(String, Vec<String>)>,
+    tabs: Vec<TabEntry>,
+}
+
+fn two_nums(val: &str) -> (usize, usize) {
+    let mut nums = val.split_whitespace().map(|n| n.parse().unwrap_or(0));
+    (nums.next().unwrap_or(0), nums.next().unwrap_or(0))
+}
+
+impl Session {
+    fn to_text(&self) -> String {
+        let mut res = format!("{}\ncur_tab {}\n", HEADER, self.cur_tab);
+        res.push_str(&format!("layout {}\n", self.layout));
+        for win in self.windows.iter() {
+            res.push_str(&format!(
+                "window {} {} {} {} {}\n",
+                win.tab, win.cursor.0, win.cursor.1, win.scroll.0, win.scroll.1
+            ));
+        }
+        res.push_str(&format!("focus {}\n", self.focus));
+        for plug in self.plugins.iter() {
+            res.push_str(&format!("plugin {}\n", plug));
+        }
+        for (name, com) in self.aliases.iter() {
+            res.push_str(&format!("alias {} {}\n", name, com.join(" ")));
+        }
+        for tab in self.tabs.iter() {
+            res.push_str("tab\n");
+            if !tab.file.is_empty() {
+                res.push_str(&format!("file {}\n", tab.file));
+            }
+            res.push_str(&format!("name {}\n", tab.name));
+            res.push_str(&format!("cursor {} {}\n", tab.cursor.0, tab.cursor.1));
+            res.push_str(&format!("scroll {} {}\n", tab.scroll.0, tab.scroll.1));
+            for (name, val) in tab.opts.iter() {
+                res.push_str(&format!("{} {}\n", name, val));
+            }
+        }
+        res
+    }
+
+    // Unknown keys are skipped, so older frevi can read newer sessions
+    fn parse(text: &str) -> Result<Session, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("Not a frevi session file".to_string());
+        }
+        let mut ses = Session::default();
+        for line in lines {
+            let (key, val) = line.split_once(' ').unwrap_or((line, ""));
+            if key == "tab" {
+                ses.tabs.push(TabEntry::default());
+                continue;
+            }
+            match (key, ses.tabs.last_mut()) {
+                ("cur_tab", _) => ses.cur_tab = val.trim().parse().unwrap_or(0),
+                ("layout", _) => ses.layout = val.to_string(),
+                ("window", _) => {
+                    let nums: Vec<usize> = val
+                        .split_whitespace()
+                        .map(|n| n.parse().unwrap_or(0))
+                        .collect();
+                    if let [tab, x, line, scroll, left_col] = nums[..] {
+                        ses.windows.push(WinEntry {
+                            tab,
+                            cursor: (x, line),
+                            scroll: (scroll, left_col),
+                        });
+                    }
+                }
+                ("focus", _) => ses.focus = val.trim().parse().unwrap_or(0),
+                ("plugin", _) => ses.plugins.push(val.to_string()),
+                ("alias", _) => {
+                    let mut words = val.split_whitespace().map(String::from);
+                    if let Some(name) = words.next() {
+                        ses.aliases.push((name, words.collect()));
+                    }
+                }
+                ("file", Some(tab)) => tab.file = val.to_string(),
+                ("name", Some(tab)) => tab.name = val.to_string(),
+                ("cursor", Some(tab)) => tab.cursor = two_nums(val),
+                ("scroll", Some(tab)) => tab.scroll = two_nums(val),
+                (opt, Some(tab)) if TAB_OPTIONS.contains(&opt) => {
+                    tab.opts.push((opt.to_string(), val.trim().to_string()))
+                }
+                _ => {}
+            }
+        }
+        Ok(ses)
+    }
+}
+
+impl App {
+    fn session_path(&self, name: &str) -> Result<PathBuf, String> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(format!("Bad session name {}", name));
+        }
+        if self.config.cfg_path.as_os_str().is_empty() {
+            return Err("No config dir to keep sessions in".to_string());
+        }
+        Ok(self.config.cfg_path.join("sessions").join(name))
+    }
+
+    fn plugin_paths(&self) -> Vec<String> {
+        match &self.plugin_subsys {
+            PlugLoaders::LuaL(lualoader) => lualoader
+                .plugins
+                .iter()
+                .map(|p| p.path.clone())
+                .filter(|p| !p.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn save_session(&mut self, name: &str) -> Result<(), String> {
+        let path = self.session_path(name)?;
+        let mut aliases: Vec<(String, Vec<String>)> = self
+            .aliases
+            .iter()
+            .map(|(n, c)| (n.clone(), c.clone()))
+            .collect();
+        aliases.sort();
+        self.update_windows();
+        let ids = self.windows.layout.ids();
+        let windows = ids
+            .iter()
+            .filter_map(|id| {
+                let win = self.windows.get(*id)?;
+                let tab = self.tabs.iter().position(|t| t.id == win.tab_id)?;
+                let t = &self.tabs[tab];
+                Some(match *id == self.windows.focus {
+                    true => WinEntry {
+                        tab,
+                        cursor: t.cursor_xy,
+                        scroll: (t.scroll_offset, t.left_col),
+                    },
+                    false => WinEntry {
+                        tab,
+                        cursor: t.win_cursors.get(id).copied().unwrap_or(t.cursor_xy),
+                        scroll: (win.scroll_offset, win.left_col),
+                    },
+                })
+            })
+            .collect();
+        let ses = Session {
+            cur_tab: self.cur_tab,
+            layout: self.windows.layout.to_text(),
+            windows,
+            focus: ids
+                .iter()
+                .position(|id| *id == self.windows.focus)
+                .unwrap_or(0),
+            plugins: self.plugin_paths(),
+            aliases,
+            tabs: self
+                .tabs
+                .iter()
+                .map(|t| TabEntry {
+                    file: abs_path(&t.filename),
+                    name: t.displayed_name.clone(),
+                    cursor: t.cursor_xy,
+                    scroll: (t.scroll_offset, t.left_col),
+                    opts: t.own_options(),
+                })
+                .collect(),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let path = path.to_string_lossy().to_string();
+        save::write_file(&path, ses.to_text().as_bytes(), &Backup::Off).map_err(|e| e.to_string())
+    }
+
+    // Replaces open tabs with the ones of the session. Files that are gone get
+    // empty tabs with their names and are listed in the status line.
+    pub fn load_session(&mut self, name: &str) -> Result<(), String> {
+        let path = self.session_path(name)?;
+        let text = match fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(format!("No session named {}", name));
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let ses = Session::parse(&text)?;
+
+        let mut tabs: Vec<Tab> = Vec::new();
+        let mut missing: Vec<String> = Vec::new();
+        for entry in ses.tabs.iter() {
+            let mut tab = Tab::new(Some(entry.name.clone()));
+            if !entry.file.is_empty() {
+                match tab.readf(entry.file.clone()) {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        missing.push(entry.file.clone());
+                    }
+                    Err(e) => missing.push(format!("{} ({})", entry.file, e)),
+                }
+            }
+            // file could change since, cursor has to stay inside it
+            let line = entry.cursor.1.min(tab.buf.last_line());
+            let x = entry.cursor.0.min(tab.buf.line_len(line));
+            tab.cursor_xy = (tab.buf.grapheme_start(line, x), line);
+            tab.scroll_offset = entry.scroll.0.min(line);
+            tab.left_col = entry.scroll.1;
+            for (name, val) in entry.opts.iter() {
+                let _ = tab.set_option(name, Some(val));
+            }
+            tabs.push(tab);
+        }
+        if tabs.is_empty() {
+            tabs.push(Tab::new(None));
+        }
+
+        // A-Z marks of tabs going away stay, like with !tab rm
+        for idx in 0..self.tabs.len() {
+            self.keep_global_marks(idx);
+        }
+        self.drop_swaps();
+        self.tabs = tabs;
+        self.cur_tab = ses.cur_tab.min(self.tabs.len() - 1);
+        self.restore_windows(&ses);
+        for (alias, com) in ses.aliases {
+            self.aliases.insert(alias, com);
+        }
+        let loaded = self.plugin_paths();
+        let tx = self.plugin_tx.clone();
+        let mut plug_errors: Vec<String> = Vec::new();
+        match &mut self.plugin_subsys {
+            PlugLoaders::LuaL(lualoader) => {
+                for plug in ses.plugins.iter().filter(|p| !loaded.contains(p)) {
+                    if let Err(e) = lualoader.load_plug(plug.clone(), tx.clone()) {
+                        plug_errors.push(e);
+                    }
+                }
+            }
+        }
+        for e in plug_errors.iter() {
+            let _ = self.logger.log_msg(LogLevel::PluginFault, e.clone());
+        }
+        self.clamp_normal_cursor();
+        self.scroll_to_cursor();
+        for idx in 0..self.tabs.len() {
+            self.check_swap(idx);
+        }
+
+        let mut msg = format!("Session {} loaded, {} tab(s)", name, self.tabs.len());
+        if !missing.is_empty() {
+            msg.push_str(&format!("; could not open: {}", missing.join(", ")));
+        }
+        if !plug_errors.is_empty() {
+            msg.push_str("; some plugins failed to load, check logs");
+        }
+        self.throw_status_message(msg);
+        Ok(())
+    }
+
+    // Windows of the session; one window on current tab if they don't fit the tabs
+    fn restore_windows(&mut self, ses: &Session) {
+        let cur_id = self.tabs[self.cur_tab].id;
+        let wins: Vec<Window> = ses
+            .windows
+            .iter()
+            .map(|win| Window {
+                id: 0,
+                tab_id: self.tabs.get(win.tab).map(|t| t.id).unwrap_or(cur_id),
+                scroll_offset: win.scroll.0,
+                left_col: win.scroll.1,
+            })
+            .collect();
+        let Some(windows) = Windows::from_parts(&ses.layout, wins, ses.focus) else {
+            self.windows = Windows::new(cur_id);
+            return;
+        };
+        self.windows = windows;
+        for (id, win) in ses.windows.iter().enumerate() {
+            let Some(tab) = self.tabs.get_mut(win.tab) else {
+                continue;
+            };
+            let line = win.cursor.1.min(tab.buf.last_line());
+            let x = win.cursor.0.min(tab.buf.line_len(line));
+            tab.win_cursors
+                .insert(id, (tab.buf.grapheme_start(line, x), line));
+        }
+        // focused one takes its view out of the tab
+        let focus = self.windows.focus;
+        if let Some(win) = self.windows.get(focus).cloned() {
+            self.cur_tab = self
+                .tabs
+                .iter()
+                .position(|t| t.id == win.tab_id)
+                .unwrap_or(self.cur_tab);
+            let tab = &mut self.tabs[self.cur_tab];
+            if let Some(cursor) = tab.win_cursors.remove(&focus) {
+                tab.cursor_xy = cursor;
+                tab.scroll_offset = win.scroll_offset;
+                tab.left_col = win.left_col;
+            }
+        }
+    }
+
+    pub fn session_names(&self) -> Vec<String> {
+        let Ok(path) = self.session_path(DEFAULT_SESSION) else {
+            return Vec::new();
+        };
+        let Some(Ok(entries)) = path.parent().map(fs::read_dir) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|n| !n.starts_with('.'))
+            .collect();
+        names.sort();
+        names
+    }
+
+    // At start without a file: brings back the session named by autosession option
+    pub fn restore_autosession(&mut self) {
+        let Some(name) = self.config.autosession.clone() else {
+            return;
+        };
+        match self.session_path(&name) {
+            Ok(path) if path.exists() => {
+                if let Err(e) = self.load_session(&name) {
+                    self.throw_status_error(e);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // On quit: keeps open tabs for the next start
+    pub fn store_autosession(&mut self) {
+        if let Some(name) = self.config.autosession.clone() {
+            let _ = self.save_session(&name);
+        }
+    }
+}
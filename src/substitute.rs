@@ -0,0 +1,441 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use regex::{Regex, RegexBuilder};
+
+use crate::app::{App, Mode};
+use crate::motions;
+use crate::range::LineRange;
+use crate::search;
+use crate::tabs::Tab;
+
+// Parsed "/pattern/replacement/flags"
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubstSpec {
+    pub pattern: String,
+    pub replacement: String, // in regex crate syntax: ${1}, $$
+    pub global: bool,
+    pub confirm: bool,
+    pub ignore_case: Option<bool>, // i / I flags, None for smart-case
+}
+
+// State of !s with c flag, waiting for y/n/a/q/l
+#[derive(Debug)]
+pub struct SubstConfirm {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+    line: usize,
+    last: usize,
+    byte: usize,             // where to look for next match in the line
+    prev_end: Option<usize>, // end of previous match in the line, no empty match allowed there
+    count: usize,
+}
+
+impl SubstConfirm {
+    fn next_line(&mut self) {
+        self.line += 1;
+        self.byte = 0;
+        self.prev_end = None;
+    }
+}
+
+// Splits at unescaped delimiters. Escaped delimiter becomes literal in both parts.
+pub fn parse_subst(text: &str) -> Result<SubstSpec, String> {
+    let mut chars = text.chars();
+    let delim = match chars.next() {
+        Some(d) if !d.is_alphanumeric() && !d.is_whitespace() && d != '\\' => d,
+        _ => return Err("Usage: !s/pattern/replacement/flags".to_string()),
+    };
+    let mut parts: Vec<String> = vec![String::new()];
+    while let Some(ch) = chars.next() {
+        let field = parts.len() - 1;
+        if ch == '\\' && field < 2 {
+            match chars.next() {
+                Some(c) if c == delim && field == 0 => {
+                    parts[field].push_str(&regex::escape(&c.to_string()))
+                }
+                Some(c) => {
+                    parts[field].push('\\');
+                    parts[field].push(c);
+                }
+                None => parts[field].push('\\'),
+            }
+        } else if ch == delim && field < 2 {
+            parts.push(String::new());
+        } else {
+            parts[field].push(ch);
+        }
+    }
+    parts.resize(3, String::new());
+    let mut spec = SubstSpec {
+        pattern: parts[0].clone(),
+        replacement: convert_replacement(&parts[1]),
+        global: false,
+        confirm: false,
+        ignore_case: None,
+    };
+    for flag in parts[2].trim().chars() {
+        match flag {
+            'g' => spec.global = true,
+            'c' => spec.confirm = true,
+            'i' => spec.ignore_case = Some(true),
+            'I' => spec.ignore_case = Some(false),
+            f => return Err(format!("Unknown flag {}", f)),
+        }
+    }
+    Ok(spec)
+}
+
+// Vi-style replacement into regex crate one:
+// \1..\9 and & are groups, \n \t newline and tab, \& \$ \\ literal chars.
+// $1 and ${name} of regex crate work as well.
+fn convert_replacement(rep: &str) -> String {
+    let mut res = String::new();
+    let mut chars = rep.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => res.push_str(&format!("${{{}}}", d)),
+                Some('n') => res.push('\n'),
+                Some('t') => res.push('\t'),
+                Some('$') => res.push_str("$$"),
+                Some(c) => res.push(c),
+                None => res.push('\\'),
+            },
+            '&' => res.push_str("${0}"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+fn compile(spec: &SubstSpec) -> Result<Regex, String> {
+    match spec.ignore_case {
+        Some(ic) => RegexBuilder::new(&spec.pattern)
+            .case_insensitive(ic)
+            .build()
+            .map_err(|e| e.to_string()),
+        None => search::compile(&spec.pattern),
+    }
+}
+
+impl App {
+    pub fn substitute(&mut self, range: Option<LineRange>, spec_text: &str) {
+        let mut spec = match parse_subst(spec_text) {
+            Ok(s) => s,
+            Err(e) => {
+                self.throw_status_error(e);
+                return;
+            }
+        };
+        // empty pattern is the last searched one
+        if spec.pattern.is_empty() {
+            if self.search.pattern.is_empty() {
+                self.throw_status_message("No previous search pattern".to_string());
+                return;
+            }
+            spec.pattern = self.search.pattern.clone();
+        }
+        let re = match compile(&spec) {
+            Ok(re) => re,
+            Err(e) => {
+                self.throw_status_error(format!("Invalid pattern: {}", e));
+                return;
+            }
+        };
+        // n and N continue with this pattern
+        self.search.pattern = spec.pattern.clone();
+        self.search.regex = Some(re.clone());
+
+        let range = range.unwrap_or(LineRange::single(self.tabs[self.cur_tab].cursor_xy.1));
+        self.seal_edit();
+        if spec.confirm {
+            self.search.highlight = true;
+            self.subst_confirm = Some(SubstConfirm {
+                regex: re,
+                replacement: spec.replacement,
+                global: spec.global,
+                line: range.start,
+                last: range.end,
+                byte: 0,
+                prev_end: None,
+                count: 0,
+            });
+            self.mode = Mode::Confirm;
+            self.confirm_next(false);
+            return;
+        }
+
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let mut subs: usize = 0;
+        let mut lines: usize = 0;
+        let mut last_changed: Option<usize> = None;
+        let mut added_lines: usize = 0;
+        // bottom up, so new lines from replacement don't move lines still to do
+        for line in (range.start..=range.end).rev() {
+            let text = cur_tab.buf.line(line);
+            let n = match spec.global {
+                true => re.find_iter(&text).count(),
+                false => re.is_match(&text) as usize,
+            };
+            if n == 0 {
+                continue;
+            }
+            let new_text = match spec.global {
+                true => re.replace_all(&text, spec.replacement.as_str()),
+                false => re.replace(&text, spec.replacement.as_str()),
+            };
+            subs += n;
+            lines += 1;
+            if new_text != text {
+                let len = cur_tab.buf.line_len(line);
+                cur_tab.remove_text(line, 0, line, len, true);
+                cur_tab.insert_text(line, 0, &new_text, true);
+            }
+            match last_changed {
+                None => last_changed = Some(line + new_text.matches('\n').count()),
+                Some(_) => added_lines += new_text.matches('\n').count(),
+            }
+        }
+        self.seal_edit();
+        let last = match last_changed {
+            Some(l) => l + added_lines,
+            None => {
+                self.throw_status_message(format!("Pattern not found: {}", spec.pattern));
+                return;
+            }
+        };
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        cur_tab.cursor_xy = (motions::first_non_blank(&cur_tab.buf, last), last);
+        self.scroll_to_cursor();
+        self.throw_status_message(format!("{} substitution(s) on {} line(s)", subs, lines));
+    }
+
+    // Goes to the next match and asks about it.
+    // With `all` replaces every remaining match without asking.
+    fn confirm_next(&mut self, all: bool) {
+        loop {
+            let state = match &mut self.subst_confirm {
+                Some(s) => s,
+                None => return,
+            };
+            let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+            if state.line > state.last.min(cur_tab.buf.last_line()) {
+                self.finish_confirm();
+                return;
+            }
+            let text = cur_tab.buf.line(state.line);
+            let found = match state.byte <= text.len() {
+                true => state.regex.find_at(&text, state.byte),
+                false => None,
+            };
+            let m = match found {
+                Some(m) => m,
+                None => {
+                    state.next_line();
+                    continue;
+                }
+            };
+            if m.start() == m.end() && state.prev_end == Some(m.start()) {
+                state.byte = m.end() + text[m.end()..].chars().next().map_or(1, |c| c.len_utf8());
+                continue;
+            }
+            let x = text[..m.start()].chars().count();
+            cur_tab.cursor_xy = (x, state.line);
+            if !all {
+                let msg = format!(
+                    "replace with {} (y/n/a/q/l)?",
+                    state.replacement.replace('\n', "^J")
+                );
+                self.scroll_to_cursor();
+                self.throw_status_message(msg);
+                return;
+            }
+            self.confirm_replace();
+        }
+    }
+
+    // Replaces the match under cursor and moves past it
+    fn confirm_replace(&mut self) {
+        let state = match &mut self.subst_confirm {
+            Some(s) => s,
+            None => return,
+        };
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let text = cur_tab.buf.line(state.line);
+        let caps = match state.regex.captures_at(&text, state.byte) {
+            Some(c) => c,
+            None => return,
+        };
+        let m = caps.get(0).expect("group 0 is always there");
+        let mut new_text = String::new();
+        caps.expand(&state.replacement, &mut new_text);
+        let from = text[..m.start()].chars().count();
+        let to = from + m.as_str().chars().count();
+        cur_tab.remove_text(state.line, from, state.line, to, true);
+        let (end_line, end_x) = cur_tab.insert_text(state.line, from, &new_text, true);
+        state.count += 1;
+        state.last += end_line - state.line;
+        state.line = end_line;
+        if !state.global {
+            state.next_line();
+            return;
+        }
+        let line_text = cur_tab.buf.line(end_line);
+        state.byte = line_text
+            .char_indices()
+            .nth(end_x)
+            .map(|(i, _)| i)
+            .unwrap_or(line_text.len());
+        state.prev_end = Some(state.byte);
+        if m.start() == m.end() {
+            // empty match, step over one char so it's not found again
+            state.byte += line_text[state.byte..]
+                .chars()
+                .next()
+                .map_or(1, |c| c.len_utf8());
+        }
+    }
+
+    // Skips the match under cursor
+    fn confirm_skip(&mut self) {
+        let state = match &mut self.subst_confirm {
+            Some(s) => s,
+            None => return,
+        };
+        let text = self.tabs[self.cur_tab].buf.line(state.line);
+        let m = match state.regex.find_at(&text, state.byte) {
+            Some(m) => m,
+            None => return,
+        };
+        if !state.global {
+            state.next_line();
+            return;
+        }
+        state.byte = m.end();
+        state.prev_end = Some(m.end());
+        if m.start() == m.end() {
+            state.byte += text[m.end()..].chars().next().map_or(1, |c| c.len_utf8());
+        }
+    }
+
+    fn finish_confirm(&mut self) {
+        self.mode = Mode::Normal;
+        self.seal_edit();
+        let count = match self.subst_confirm.take() {
+            Some(s) => s.count,
+            None => 0,
+        };
+        self.clamp_normal_cursor();
+        self.throw_status_message(format!("{} substitution(s)", count));
+    }
+
+    pub fn handle_confirm_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char('y') => {
+                self.confirm_replace();
+                self.confirm_next(false);
+            }
+            KeyCode::Char('n') => {
+                self.confirm_skip();
+                self.confirm_next(false);
+            }
+            KeyCode::Char('a') => self.confirm_next(true),
+            KeyCode::Char('l') => {
+                self.confirm_replace();
+                self.finish_confirm();
+            }
+            KeyCode::Char('q') | KeyCode::Esc => self.finish_confirm(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn spec(text: &str) -> SubstSpec {
+        parse_subst(text).unwrap()
+    }
+
+    // runs !s over every line of text, with confirm flag answers all with `a`
+    fn subst(text: &str, spec_text: &str) -> String {
+        let mut app = App::new_test();
+        app.tabs[0].str_into_buf(text.to_string());
+        let last = app.tabs[0].buf.last_line();
+        app.substitute(
+            Some(LineRange {
+                start: 0,
+                end: last,
+            }),
+            spec_text,
+        );
+        if app.subst_confirm.is_some() {
+            app.handle_confirm_key(KeyEvent::new(KeyCode::Char('a'), KeyModifiers::NONE));
+        }
+        app.tabs[0].buf.rope().to_string()
+    }
+
+    #[test]
+    fn escaped_delimiter() {
+        let s = spec(r"/a\/b/c\/d/");
+        assert_eq!(s.pattern, r"a/b");
+        assert_eq!(s.replacement, "c/d");
+        let s = spec(r"#a\#b#c#");
+        assert_eq!(s.pattern, r"a\#b");
+        assert_eq!(s.replacement, "c");
+        // other escapes are kept for the regex
+        assert_eq!(spec(r"/\d\./x/").pattern, r"\d\.");
+    }
+
+    #[test]
+    fn missing_parts() {
+        let s = spec("/a");
+        assert_eq!((s.pattern.as_str(), s.replacement.as_str()), ("a", ""));
+        assert!(!s.global);
+        assert_eq!(spec("/a/b").replacement, "b");
+        assert!(parse_subst("a/b/").is_err());
+        assert!(parse_subst("").is_err());
+    }
+
+    #[test]
+    fn flags() {
+        let s = spec("/a/b/gc");
+        assert!(s.global && s.confirm);
+        assert_eq!(s.ignore_case, None);
+        assert_eq!(spec("/a/b/i").ignore_case, Some(true));
+        assert_eq!(spec("/a/b/I").ignore_case, Some(false));
+        assert_eq!(parse_subst("/a/b/x"), Err("Unknown flag x".to_string()));
+    }
+
+    #[test]
+    fn replacement() {
+        assert_eq!(convert_replacement("[&]"), "[${0}]");
+        assert_eq!(convert_replacement(r"\&"), "&");
+        assert_eq!(convert_replacement(r"\2\1"), "${2}${1}");
+        assert_eq!(convert_replacement(r"a\nb\tc"), "a\nb\tc");
+        assert_eq!(convert_replacement(r"\$1"), "$$1");
+        assert_eq!(convert_replacement("$1"), "$1");
+        assert_eq!(convert_replacement(r"\\"), r"\");
+        assert_eq!(convert_replacement("a\\"), "a\\");
+    }
+
+    #[test]
+    fn substitutes() {
+        assert_eq!(subst("foo bar", r"/(\w+) (\w+)/\2 \1/"), "bar foo");
+        assert_eq!(subst("a.a", "/a/<&>/"), "<a>.a");
+        assert_eq!(subst("a.a", "/a/<&>/g"), "<a>.<a>");
+        assert_eq!(subst("a,b", r"/,/\n/"), "a\nb");
+        assert_eq!(subst("a", r"/a/\$1/"), "$1");
+        assert_eq!(subst("Ab ab", "/ab/x/gI"), "Ab x");
+    }
+
+    #[test]
+    fn empty_matches_step_forward() {
+        assert_eq!(subst("abc", "/x*/-/g"), "-a-b-c-");
+        assert_eq!(subst("abc", "/x*/-/gc"), "-a-b-c-");
+        assert_eq!(subst("äb", "/b*/-/gc"), "-ä-");
+        assert_eq!(subst("ab\ncd", "/$/;/gc"), "ab;\ncd;");
+    }
+}
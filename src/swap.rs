@@ -0,0 +1,692 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+
+use crate::app::App;
+use crate::buffer::TextBuffer;
+use crate::diff;
+use crate::fileformat::{self, FileFormat, LineEnding};
+use crate::logger::LogLevel;
+use crate::prompt::Prompt;
+use crate::save::{self, Backup};
+use crate::tabs::Tab;
+
+// Every tab with a file has a swap file in ~/.frevi/swap while it's open: it tells
+// other frevi instances the file is taken and, once the tab is changed, holds its
+// text. Swaps are rewritten after a second without keys, or every 10 s of typing,
+// in a thread of their own so big files don't stop the editor.
+// Swap left by a dead process is offered for recovery when its file is opened.
+const IDLE: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default)]
+pub struct SwapState {
+    pub path: Option<PathBuf>, // our swap file, once written
+    file: String,              // absolute name of the file it was written for
+    version: u64,              // edit version and changed flag it holds
+    modified: bool,
+    written: Option<Instant>,
+    writing: Option<Arc<OnceLock<Result<(), String>>>>, // thread writing it, set when done
+    pub found: Option<PathBuf>, // swap of a crashed frevi waiting for !recover
+}
+
+// Swap file header: "key value" lines, empty line, then text of the tab
+#[derive(Debug, Clone)]
+pub struct SwapInfo {
+    pub path: PathBuf,
+    pub pid: u32,
+    pub file: String, // empty for tab without file
+    pub time: String,
+    pub modified: bool,
+    pub format: FileFormat,
+    pub cursor: (usize, usize),
+}
+
+impl SwapInfo {
+    // Reads only the header
+    fn read(path: &Path) -> Option<SwapInfo> {
+        let file = fs::File::open(path).ok()?;
+        let mut lines = BufReader::new(file)
+            .lines()
+            .map_while(|l| l.ok())
+            .take_while(|l| !l.is_empty());
+        if lines.next().as_deref() != Some("frevi swap") {
+            return None;
+        }
+        let mut info = SwapInfo {
+            path: path.to_path_buf(),
+            pid: 0,
+            file: String::new(),
+            time: String::new(),
+            modified: false,
+            format: FileFormat::default(),
+            cursor: (0, 0),
+        };
+        for line in lines {
+            let (key, val) = line.split_once(' ').unwrap_or((&line, ""));
+            match key {
+                "pid" => info.pid = val.parse().ok()?,
+                "file" => info.file = val.to_string(),
+                "time" => info.time = val.to_string(),
+                "modified" => info.modified = val == "yes",
+                "ending" => info.format.ending = LineEnding::from_name(val)?,
+                "encoding" => {
+                    (info.format.encoding, info.format.bom) = fileformat::encoding_from_name(val)?
+                }
+                "eol" => info.format.final_newline = val == "on",
+                "cursor" => {
+                    let (x, line) = val.split_once(' ')?;
+                    info.cursor = (x.parse().ok()?, line.parse().ok()?);
+                }
+                _ => {}
+            }
+        }
+        Some(info)
+    }
+
+    // Text saved in swap
+    fn text(&self) -> Option<String> {
+        let data = fs::read(&self.path).ok()?;
+        let start = data.windows(2).position(|w| w == b"\n\n")? + 2;
+        Some(String::from_utf8_lossy(&data[start..]).to_string())
+    }
+
+    fn name(&self) -> String {
+        match self.file.is_empty() {
+            true => "[tab without file]".to_string(),
+            false => self.file.clone(),
+        }
+    }
+
+    // Left by a frevi which isn't running anymore, with changes in it
+    fn recoverable(&self) -> bool {
+        self.modified && !pid_alive(self.pid)
+    }
+}
+
+fn swap_header(tab: &Tab, file: &str) -> String {
+    let format = &tab.format;
+    format!(
+        "frevi swap\npid {}\nfile {}\ntime {}\nmodified {}\nending {}\nencoding {}\neol {}\ncursor {} {}\n\n",
+        std::process::id(),
+        file,
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        if tab.changed { "yes" } else { "no" },
+        format.ending,
+        format.encoding_name(),
+        if format.final_newline { "on" } else { "off" },
+        tab.cursor_xy.0,
+        tab.cursor_xy.1
+    )
+}
+
+// Name the file is known by in swaps, same for relative and absolute ones
+pub fn abs_path(filename: &str) -> String {
+    if filename.is_empty() {
+        return String::new();
+    }
+    let path = Path::new(filename);
+    match fs::canonicalize(path) {
+        Ok(p) => p.to_string_lossy().to_string(),
+        Err(_) => std::env::current_dir()
+            .map(|d| d.join(path))
+            .unwrap_or(path.to_path_buf())
+            .to_string_lossy()
+            .to_string(),
+    }
+}
+
+// /home/me/a.rs has swap %home%me%a.rs.swp, or %home%me%a.rs.1.swp when
+// another frevi holds the first one
+fn swap_stem(file: &str) -> String {
+    file.replace(['/', '\\', ':'], "%")
+}
+
+fn is_swap_of(name: &str, stem: &str) -> bool {
+    let Some(rest) = name.strip_prefix(stem) else {
+        return false;
+    };
+    match rest.strip_suffix(".swp") {
+        Some("") => true,
+        Some(n) => n
+            .strip_prefix('.')
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())),
+        None => false,
+    }
+}
+
+#[cfg(unix)]
+fn pid_alive(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    if Path::new("/proc/self").exists() {
+        return Path::new("/proc").join(pid.to_string()).exists();
+    }
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+#[cfg(not(unix))]
+fn pid_alive(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).contains(&pid.to_string()))
+}
+
+impl App {
+    fn swap_dir(&self) -> Option<PathBuf> {
+        if self.config.cfg_path.as_os_str().is_empty() {
+            return None;
+        }
+        let dir = self.config.cfg_path.join("swap");
+        fs::create_dir_all(&dir).ok()?;
+        Some(dir)
+    }
+
+    fn all_swaps(&self) -> Vec<SwapInfo> {
+        let Some(dir) = self.swap_dir() else {
+            return Vec::new();
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut res: Vec<SwapInfo> = entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".swp"))
+            .filter_map(|e| SwapInfo::read(&e.path()))
+            .collect();
+        res.sort_by(|a, b| b.time.cmp(&a.time));
+        res
+    }
+
+    // Swap files of a file, newest first
+    fn swaps_of(&self, file: &str) -> Vec<SwapInfo> {
+        let stem = swap_stem(file);
+        self.all_swaps()
+            .into_iter()
+            .filter(|s| {
+                s.path
+                    .file_name()
+                    .is_some_and(|n| is_swap_of(&n.to_string_lossy(), &stem))
+            })
+            .collect()
+    }
+
+    // Whether one of our tabs writes this swap
+    fn own_swap(&self, path: &Path) -> bool {
+        self.tabs
+            .iter()
+            .any(|t| t.swap.path.as_deref() == Some(path))
+    }
+
+    fn free_swap_path(&self, dir: &Path, tab: &Tab, file: &str) -> PathBuf {
+        if file.is_empty() {
+            return dir.join(format!("noname.{}.{}.swp", std::process::id(), tab.id));
+        }
+        let stem = swap_stem(file);
+        (0..)
+            .map(|n| match n {
+                0 => dir.join(format!("{}.swp", stem)),
+                n => dir.join(format!("{}.{}.swp", stem, n)),
+            })
+            .find(|p| !p.exists() && !self.own_swap(p))
+            .unwrap_or(dir.join(format!("{}.swp", stem)))
+    }
+
+    // Removes swap of a tab, when it's closed or gets another file
+    pub fn drop_swap(&mut self, idx: usize) {
+        self.swap_write_done(idx, true);
+        if let Some(path) = std::mem::take(&mut self.tabs[idx].swap).path {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    // On normal exit nothing has to be recovered
+    pub fn drop_swaps(&mut self) {
+        for idx in 0..self.tabs.len() {
+            self.drop_swap(idx);
+        }
+    }
+
+    fn write_swap(&mut self, idx: usize, file: String) {
+        let Some(dir) = self.swap_dir() else {
+            return;
+        };
+        self.swap_write_done(idx, true);
+        let path = match &self.tabs[idx].swap.path {
+            Some(p) if self.tabs[idx].swap.file == file => p.clone(),
+            _ => {
+                self.drop_swap(idx);
+                self.free_swap_path(&dir, &self.tabs[idx], &file)
+            }
+        };
+        let tab: &Tab = &self.tabs[idx];
+        let header = swap_header(tab, &file);
+        let text = tab.changed.then(|| tab.buf.rope().clone());
+        let (version, modified) = (tab.edit_hist.version(), tab.changed);
+        let done = Arc::new(OnceLock::new());
+        let res = done.clone();
+        let to = path.clone();
+        thread::spawn(move || {
+            let mut bytes = header.into_bytes();
+            for chunk in text.iter().flat_map(|t| t.chunks()) {
+                bytes.extend_from_slice(chunk.as_bytes());
+            }
+            let written = save::write_file(&to.to_string_lossy(), &bytes, &Backup::Off);
+            let _ = res.set(written.map_err(|e| format!("Can't write swap {:?}: {}", to, e)));
+        });
+        let swap = &mut self.tabs[idx].swap;
+        swap.path = Some(path);
+        swap.file = file;
+        swap.version = version;
+        swap.modified = modified;
+        swap.written = Some(Instant::now());
+        swap.writing = Some(done);
+    }
+
+    // False while swap of the tab is still being written, unless told to wait for it
+    fn swap_write_done(&mut self, idx: usize, wait: bool) -> bool {
+        let Some(writing) = self.tabs[idx].swap.writing.clone() else {
+            return true;
+        };
+        let res = match wait {
+            true => Some(writing.wait()),
+            false => writing.get(),
+        };
+        let Some(res) = res else {
+            return false;
+        };
+        if let Err(e) = res {
+            let _ = self.logger.log_msg(LogLevel::Error, e.clone());
+        }
+        self.tabs[idx].swap.writing = None;
+        true
+    }
+
+    // Called by main loop between keys: writes swaps that are behind their tabs
+    pub fn update_swaps(&mut self) {
+        let idle = self.last_key.elapsed() >= IDLE;
+        for idx in 0..self.tabs.len() {
+            if !self.swap_write_done(idx, false) {
+                continue;
+            }
+            let file = self.tabs[idx].abs_filename();
+            let tab: &Tab = &self.tabs[idx];
+            let swap = &tab.swap;
+            if tab.filename.is_empty() && !tab.changed {
+                if swap.path.is_some() {
+                    self.drop_swap(idx);
+                }
+                continue;
+            }
+            let behind = swap.path.is_none()
+                || swap.file != file
+                || swap.version != tab.edit_hist.version()
+                || swap.modified != tab.changed;
+            let due = idle || swap.written.is_none_or(|t| t.elapsed() >= MAX_DELAY);
+            if behind && due {
+                self.write_swap(idx, file);
+            }
+        }
+    }
+
+    // After a file was opened in tab: warns if other frevi has it open too
+    // and asks about swap left by a crashed one
+    pub fn check_swap(&mut self, idx: usize) {
+        let file = self.tabs[idx].abs_filename();
+        if file.is_empty() {
+            return;
+        }
+        let mut others: Vec<u32> = Vec::new();
+        let mut found: Option<SwapInfo> = None;
+        for info in self.swaps_of(&file) {
+            if self.own_swap(&info.path) {
+                continue;
+            }
+            if info.pid != std::process::id() && pid_alive(info.pid) {
+                others.push(info.pid);
+            } else if !info.modified {
+                let _ = fs::remove_file(&info.path);
+            } else if found.is_none() {
+                found = Some(info);
+            }
+        }
+        self.write_swap(idx, file);
+        if !others.is_empty() {
+            let pids: Vec<String> = others.iter().map(|p| p.to_string()).collect();
+            self.throw_status_warning(format!(
+                "W: {} is also open in another frevi (pid {})",
+                self.tabs[idx].filename,
+                pids.join(", ")
+            ));
+        }
+        if let Some(info) = found {
+            self.tabs[idx].swap.found = Some(info.path.clone());
+            self.ask_recover(idx, &info);
+        }
+    }
+
+    fn ask_recover(&mut self, idx: usize, info: &SwapInfo) {
+        let text = format!(
+            "Swap of {} has unsaved changes from {} (pid {}): [r]ecover, [d]iff, [x] discard, Esc later",
+            info.name(),
+            info.time,
+            info.pid
+        );
+        self.ask(Prompt {
+            text,
+            choices: vec![('r', recover_swap), ('d', diff_swap), ('x', discard_swap)],
+            on_esc: Some(later_swap),
+            tab_id: self.tabs[idx].id,
+        });
+    }
+
+    // !recover: asks again about swap of current tab (or any tab having one)
+    pub fn ask_recover_again(&mut self) -> Result<(), String> {
+        let idx = match self.tabs[self.cur_tab].swap.found {
+            Some(_) => self.cur_tab,
+            None => self
+                .tabs
+                .iter()
+                .position(|t| t.swap.found.is_some())
+                .ok_or("No swap waits for recovery; !recover list shows all".to_string())?,
+        };
+        let path = self.tabs[idx].swap.found.clone().unwrap_or_default();
+        let Some(info) = SwapInfo::read(&path) else {
+            self.tabs[idx].swap.found = None;
+            return Err("Swap file is gone".to_string());
+        };
+        self.cur_tab = idx;
+        self.ask_recover(idx, &info);
+        Ok(())
+    }
+
+    // Swaps of frevis that aren't running anymore
+    pub fn recoverable_swaps(&self) -> Vec<SwapInfo> {
+        self.all_swaps()
+            .into_iter()
+            .filter(|s| s.recoverable() && !self.own_swap(&s.path))
+            .collect()
+    }
+
+    // !recover list
+    pub fn list_swaps(&mut self) {
+        let swaps = self.recoverable_swaps();
+        if swaps.is_empty() {
+            self.throw_status_message("Nothing to recover".to_string());
+            return;
+        }
+        let lines: Vec<String> = swaps
+            .iter()
+            .enumerate()
+            .map(|(i, s)| format!("{}\t{}\t{} (pid {})", i + 1, s.name(), s.time, s.pid))
+            .collect();
+        let mut tab = Tab::new(Some("recover list".to_string()));
+        tab.str_into_buf(lines.join("\n"));
+        self.tabs.push(tab);
+        self.cur_tab = self.tabs.len() - 1;
+        self.throw_status_message("!recover N opens N-th of them".to_string());
+    }
+
+    // !recover N: opens file of N-th swap from the list (or empty tab) and asks about it
+    pub fn open_swap(&mut self, n: usize) -> Result<(), String> {
+        let swaps = self.recoverable_swaps();
+        let info = swaps
+            .get(n.wrapping_sub(1))
+            .ok_or(format!("No swap number {}; see !recover list", n))?
+            .clone();
+        let open = self
+            .tabs
+            .iter()
+            .position(|t| !info.file.is_empty() && abs_path(&t.filename) == info.file);
+        let idx = match open {
+            Some(i) => i,
+            None => {
+                let mut tab = Tab::new(None);
+                if !info.file.is_empty() {
+                    tab.displayed_name = info.file.clone();
+                    match tab.readf(info.file.clone()) {
+                        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                            return Err(e.to_string());
+                        }
+                        _ => {}
+                    }
+                }
+                self.tabs.push(tab);
+                self.tabs.len() - 1
+            }
+        };
+        self.push_jump();
+        self.cur_tab = idx;
+        self.tabs[idx].swap.found = Some(info.path.clone());
+        self.ask_recover(idx, &info);
+        Ok(())
+    }
+
+    // At start: tabs without a file can't be found by opening it, so just tell about them
+    pub fn tell_orphan_swaps(&mut self) {
+        let count = self
+            .recoverable_swaps()
+            .iter()
+            .filter(|s| s.file.is_empty())
+            .count();
+        if count > 0 {
+            self.throw_status_message(format!(
+                "{} unsaved tab(s) without file left by a crashed frevi; !recover list",
+                count
+            ));
+        }
+    }
+
+    // Swap waiting for an answer about tab with that id
+    fn found_swap(&mut self, tab_id: usize) -> Option<(usize, SwapInfo)> {
+        let idx = self.tab_by_id(tab_id)?;
+        let path = self.tabs[idx].swap.found.clone()?;
+        match SwapInfo::read(&path) {
+            Some(info) => Some((idx, info)),
+            None => {
+                self.tabs[idx].swap.found = None;
+                self.throw_status_message("Swap file is gone".to_string());
+                None
+            }
+        }
+    }
+}
+
+fn recover_swap(app: &mut App, tab_id: usize) {
+    let Some((idx, info)) = app.found_swap(tab_id) else {
+        return;
+    };
+    let Some(text) = info.text() else {
+        app.throw_status_message("Can't read swap file".to_string());
+        return;
+    };
+    let tab: &mut Tab = &mut app.tabs[idx];
+    if tab.buf.to_string() == text {
+        app.throw_status_message("Swap has the same text as the file, removed it".to_string());
+    } else {
+        tab.replace_buf(TextBuffer::from_text(&text));
+        tab.format = info.format;
+        let line = info.cursor.1.min(tab.buf.last_line());
+        let x = info.cursor.0.min(tab.buf.line_len(line));
+        tab.cursor_xy = (tab.buf.grapheme_start(line, x), line);
+        if tab.filename.is_empty() {
+            tab.displayed_name = "recovered".to_string();
+        }
+        if idx == app.cur_tab {
+            app.clamp_normal_cursor();
+        }
+        app.throw_status_message("Recovered; !w saves it, u undoes it".to_string());
+    }
+    app.tabs[idx].swap.found = None;
+    let file = app.tabs[idx].abs_filename();
+    app.write_swap(idx, file);
+    let _ = fs::remove_file(&info.path);
+}
+
+fn diff_swap(app: &mut App, tab_id: usize) {
+    let Some((idx, info)) = app.found_swap(tab_id) else {
+        return;
+    };
+    let Some(text) = info.text() else {
+        app.throw_status_message("Can't read swap file".to_string());
+        return;
+    };
+    let tab: &Tab = &app.tabs[idx];
+    let old: Vec<String> = (0..=tab.buf.last_line()).map(|l| tab.buf.line(l)).collect();
+    let new: Vec<String> = text.split('\n').map(String::from).collect();
+    let name = match tab.filename.is_empty() {
+        true => tab.displayed_name.clone(),
+        false => tab.filename.clone(),
+    };
+    let res = diff::unified(&old, &new, &name, &format!("{} (swap)", name));
+    if res.is_empty() {
+        app.throw_status_message("Swap has the same text as the file".to_string());
+        app.ask_recover(idx, &info);
+        return;
+    }
+    let mut diff_tab = Tab::new(Some(format!("diff {}", name)));
+    diff_tab.str_into_buf(res);
+    app.tabs.push(diff_tab);
+    app.push_jump();
+    app.cur_tab = app.tabs.len() - 1;
+    app.throw_status_message("!recover asks about the swap again".to_string());
+}
+
+fn discard_swap(app: &mut App, tab_id: usize) {
+    let Some((idx, info)) = app.found_swap(tab_id) else {
+        return;
+    };
+    app.tabs[idx].swap.found = None;
+    match fs::remove_file(&info.path) {
+        Ok(_) => app.throw_status_message("Swap removed".to_string()),
+        Err(e) => app.throw_status_error(e.to_string()),
+    }
+}
+
+fn later_swap(app: &mut App, _tab_id: usize) {
+    app.throw_status_message("Swap kept; !recover asks about it again".to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileformat::Encoding;
+
+    // Empty dir of its own for every test, standing in for ~/.frevi
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("frevi_swap_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn app_in(dir: &Path) -> App {
+        let mut app = App::new_test();
+        app.config.cfg_path = dir.to_path_buf();
+        app
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let dir = temp_dir("header");
+        let mut tab = Tab::new(None);
+        tab.str_into_buf("a\n\nb".to_string());
+        tab.changed = true;
+        tab.cursor_xy = (1, 2);
+        tab.format = FileFormat {
+            ending: LineEnding::CrLf,
+            encoding: Encoding::Latin1,
+            bom: false,
+            final_newline: false,
+        };
+        let path = dir.join("x.swp");
+        let text = swap_header(&tab, "/tmp/x y.txt") + "a\n\nb";
+        fs::write(&path, text).unwrap();
+        let info = SwapInfo::read(&path).unwrap();
+        assert_eq!(info.pid, std::process::id());
+        assert_eq!(info.file, "/tmp/x y.txt");
+        assert!(info.modified);
+        assert_eq!(info.format, tab.format);
+        assert_eq!(info.cursor, (1, 2));
+        assert_eq!(info.text().as_deref(), Some("a\n\nb"));
+        assert_eq!(info.name(), "/tmp/x y.txt");
+        // our own pid is alive, nothing to recover
+        assert!(!info.recoverable());
+
+        fs::write(&path, "not a swap\n\ntext").unwrap();
+        assert!(SwapInfo::read(&path).is_none());
+        fs::write(&path, "frevi swap\npid x\n\n").unwrap();
+        assert!(SwapInfo::read(&path).is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn swap_names() {
+        let stem = swap_stem("/home/me/a.rs");
+        assert_eq!(stem, "%home%me%a.rs");
+        assert!(is_swap_of("%home%me%a.rs.swp", &stem));
+        assert!(is_swap_of("%home%me%a.rs.2.swp", &stem));
+        assert!(!is_swap_of("%home%me%a.rs.x.swp", &stem));
+        assert!(!is_swap_of("%home%me%a.rs.bak.swp", &stem));
+        assert!(!is_swap_of("%home%me%a.rs..swp", &stem));
+        assert!(!is_swap_of("%home%me%a.rsx.swp", &stem));
+    }
+
+    #[test]
+    fn recover_after_crash() {
+        let dir = temp_dir("recover");
+        let file = dir.join("a.txt").to_string_lossy().to_string();
+        fs::write(&file, "one\ntwo\n").unwrap();
+
+        // first instance changes the file and "crashes" with its swap left behind
+        let mut first = app_in(&dir);
+        first.tabs[0].readf(file.clone()).unwrap();
+        first.tabs[0].insert_text(1, 0, "new ", true);
+        first.tabs[0].cursor_xy = (4, 1);
+        let abs = first.tabs[0].abs_filename();
+        first.write_swap(0, abs.clone());
+        first.swap_write_done(0, true);
+        let left = first.tabs[0].swap.path.clone().unwrap();
+        let text = fs::read_to_string(&left).unwrap();
+        let dead = text.replace(&format!("pid {}\n", std::process::id()), "pid 4000000000\n");
+        fs::write(&left, dead).unwrap();
+        assert!(SwapInfo::read(&left).unwrap().recoverable());
+
+        let mut second = app_in(&dir);
+        second.tabs[0].readf(file.clone()).unwrap();
+        second.check_swap(0);
+        assert_eq!(second.tabs[0].swap.found.as_ref(), Some(&left));
+        // it writes a swap of its own beside the found one
+        let own = second.tabs[0].swap.path.clone().unwrap();
+        assert_ne!(own, left);
+        let tab_id = second.tabs[0].id;
+        recover_swap(&mut second, tab_id);
+        second.swap_write_done(0, true);
+        let tab = &second.tabs[0];
+        assert_eq!(tab.buf.rope().to_string(), "one\nnew two");
+        assert_eq!(tab.cursor_xy, (4, 1));
+        assert!(tab.changed);
+        assert!(tab.swap.found.is_none());
+        assert!(!left.exists());
+        assert!(SwapInfo::read(&own).unwrap().modified);
+
+        second.drop_swaps();
+        assert!(!own.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,490 @@
+use std::{collections::HashMap, fs, path::Path, sync::LazyLock};
+
+use ratatui::style::Style;
+use regex::Regex;
+
+use crate::app::App;
+use crate::tabs::Tab;
+
+// Syntax highlighting. A syntax is a TOML file (see syntax/*.toml, built in, and
+// ~/.frevi/syntax/*.toml, which win over built-in ones of the same name) with:
+//   words    - lists of words per group: keyword = ["fn", "let"]
+//   patterns - regexes, the first capture group is highlighted if there is one
+//   regions  - text from start to end, may span lines: strings and comments
+//   numbers  - whether words starting with a digit are numbers
+// At every position words are tried first, then patterns, regions and numbers.
+// Tokens are named by groups (comment, string, keyword...), themes give them styles.
+//
+// Lines are tokenized as they're drawn. A region left open at the end of a line
+// is its state, the next line starts in it. Tabs keep states of lines from the top
+// to the last shown one, edits drop the ones from the changed line down.
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("rust", include_str!("../syntax/rust.toml")),
+    ("lua", include_str!("../syntax/lua.toml")),
+    ("toml", include_str!("../syntax/toml.toml")),
+    ("markdown", include_str!("../syntax/markdown.toml")),
+    ("shell", include_str!("../syntax/shell.toml")),
+    ("json", include_str!("../syntax/json.toml")),
+];
+
+// How many lines from the top and the bottom are looked at for a modeline
+const MODELINE_LINES: usize = 5;
+
+// Region the line starts in and how deep it's nested: (region, depth)
+pub type LineState = Option<(usize, usize)>;
+
+#[derive(Debug, Clone)]
+struct Region {
+    start: String,
+    end: Option<String>, // None ends at line end
+    escape: Option<char>,
+    multiline: bool,
+    nested: bool,
+    group: String,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    regex: Regex,
+    group: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Syntax {
+    pub name: String,
+    extensions: Vec<String>,
+    filenames: Vec<String>,
+    shebangs: Vec<String>,
+    words: HashMap<String, String>, // word -> group
+    patterns: Vec<Pattern>,
+    regions: Vec<Region>,
+    numbers: bool,
+}
+
+fn str_list(table: &toml::Table, key: &str) -> Result<Vec<String>, String> {
+    match table.get(key) {
+        None => Ok(Vec::new()),
+        Some(toml::Value::Array(items)) => items
+            .iter()
+            .map(|v| {
+                v.as_str()
+                    .map(String::from)
+                    .ok_or(format!("{}: expected strings", key))
+            })
+            .collect(),
+        Some(_) => Err(format!("{}: expected a list of strings", key)),
+    }
+}
+
+fn tables<'a>(table: &'a toml::Table, key: &str) -> Result<Vec<&'a toml::Table>, String> {
+    match table.get(key) {
+        None => Ok(Vec::new()),
+        Some(toml::Value::Array(items)) => items
+            .iter()
+            .map(|v| {
+                v.as_table()
+                    .ok_or(format!("{}: expected [[{}]] tables", key, key))
+            })
+            .collect(),
+        Some(_) => Err(format!("{}: expected [[{}]] tables", key, key)),
+    }
+}
+
+fn get_str(table: &toml::Table, key: &str) -> Result<Option<String>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(toml::Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(format!("{}: expected a string", key)),
+    }
+}
+
+fn get_bool(table: &toml::Table, key: &str, default: bool) -> Result<bool, String> {
+    match table.get(key) {
+        None => Ok(default),
+        Some(toml::Value::Boolean(b)) => Ok(*b),
+        Some(_) => Err(format!("{}: expected true or false", key)),
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+impl Syntax {
+    pub fn parse(text: &str) -> Result<Syntax, String> {
+        let table = text.parse::<toml::Table>().map_err(|e| e.to_string())?;
+        let name = get_str(&table, "name")?.ok_or("name: missing".to_string())?;
+        let mut words: HashMap<String, String> = HashMap::new();
+        if let Some(groups) = table.get("words") {
+            let groups = groups
+                .as_table()
+                .ok_or("words: expected a table of lists".to_string())?;
+            for group in groups.keys() {
+                for word in str_list(groups, group)? {
+                    words.insert(word, group.clone());
+                }
+            }
+        }
+        let mut patterns: Vec<Pattern> = Vec::new();
+        for pat in tables(&table, "patterns")? {
+            let regex = get_str(pat, "regex")?.ok_or("patterns: regex missing".to_string())?;
+            patterns.push(Pattern {
+                regex: Regex::new(&regex).map_err(|e| format!("patterns: {}", e))?,
+                group: get_str(pat, "group")?.ok_or("patterns: group missing".to_string())?,
+            });
+        }
+        let mut regions: Vec<Region> = Vec::new();
+        for reg in tables(&table, "regions")? {
+            let start = get_str(reg, "start")?
+                .filter(|s| !s.is_empty())
+                .ok_or("regions: start missing".to_string())?;
+            let escape = get_str(reg, "escape")?;
+            if escape.as_ref().is_some_and(|e| e.chars().count() != 1) {
+                return Err("regions: escape must be one char".to_string());
+            }
+            regions.push(Region {
+                start,
+                end: get_str(reg, "end")?.filter(|s| !s.is_empty()),
+                escape: escape.and_then(|e| e.chars().next()),
+                multiline: get_bool(reg, "multiline", true)?,
+                nested: get_bool(reg, "nested", false)?,
+                group: get_str(reg, "group")?.ok_or("regions: group missing".to_string())?,
+            });
+        }
+        Ok(Syntax {
+            name,
+            extensions: str_list(&table, "extensions")?,
+            filenames: str_list(&table, "filenames")?,
+            shebangs: str_list(&table, "shebangs")?,
+            words,
+            patterns,
+            regions,
+            numbers: get_bool(&table, "numbers", false)?,
+        })
+    }
+
+    // Byte where region ends (after its end) and state for the next line if it doesn't
+    fn region_end(&self, idx: usize, depth: usize, line: &str, from: usize) -> (usize, LineState) {
+        let reg = &self.regions[idx];
+        let Some(end) = &reg.end else {
+            return (line.len(), None);
+        };
+        let mut depth = depth;
+        let mut pos = from;
+        while pos < line.len() {
+            let rest = &line[pos..];
+            if rest.starts_with(end.as_str()) {
+                depth -= 1;
+                pos += end.len();
+                if depth == 0 {
+                    return (pos, None);
+                }
+                continue;
+            }
+            if reg.nested && rest.starts_with(reg.start.as_str()) {
+                depth += 1;
+                pos += reg.start.len();
+                continue;
+            }
+            let ch = rest.chars().next().unwrap_or(' ');
+            pos += ch.len_utf8();
+            // escaped char can't end it
+            if Some(ch) == reg.escape
+                && let Some(next) = line[pos..].chars().next()
+            {
+                pos += next.len_utf8();
+            }
+        }
+        match reg.multiline {
+            true => (line.len(), Some((idx, depth))),
+            false => (line.len(), None),
+        }
+    }
+
+    // Highlighted parts of line as (from, to, group) in chars, and state for the next line
+    pub fn tokenize(&self, line: &str, state: LineState) -> (Vec<(usize, usize, &str)>, LineState) {
+        let mut toks: Vec<(usize, usize, &str)> = Vec::new(); // in bytes here
+        let mut pos = 0;
+        let mut next_state: LineState = None;
+        if let Some((idx, depth)) = state
+            && idx < self.regions.len()
+        {
+            let (end, st) = self.region_end(idx, depth, line, 0);
+            toks.push((0, end, &self.regions[idx].group));
+            pos = end;
+            next_state = st;
+        }
+        // next match of every pattern, computed again once pos passes its start
+        let mut matches: Vec<Option<(usize, usize, usize, usize)>> =
+            vec![None; self.patterns.len()];
+        while pos < line.len() && next_state.is_none() {
+            let rest = &line[pos..];
+            let ch = rest.chars().next().unwrap_or(' ');
+            let word_start =
+                is_word_char(ch) && !line[..pos].chars().next_back().is_some_and(is_word_char);
+            let word_len = match is_word_char(ch) {
+                true => rest.find(|c| !is_word_char(c)).unwrap_or(rest.len()),
+                false => ch.len_utf8(),
+            };
+            if word_start && let Some(group) = self.words.get(&rest[..word_len]) {
+                toks.push((pos, pos + word_len, group));
+                pos += word_len;
+                continue;
+            }
+
+            let mut hit = None;
+            for (i, pat) in self.patterns.iter().enumerate() {
+                let stale = matches[i].is_none_or(|m| m.0 < pos);
+                if stale {
+                    matches[i] = Some(match pat.regex.captures_at(line, pos) {
+                        Some(caps) => {
+                            let all = caps.get(0).map_or((pos, pos), |m| (m.start(), m.end()));
+                            let tok = caps.get(1).map_or(all, |m| (m.start(), m.end()));
+                            (all.0, all.1, tok.0, tok.1)
+                        }
+                        None => (usize::MAX, 0, 0, 0),
+                    });
+                }
+                if let Some(m) = matches[i]
+                    && m.0 == pos
+                    && m.1 > pos
+                {
+                    hit = Some((i, m));
+                    break;
+                }
+            }
+            if let Some((i, (_, end, from, to))) = hit {
+                if to > from {
+                    toks.push((from, to, &self.patterns[i].group));
+                }
+                pos = end;
+                continue;
+            }
+
+            let prev_word = line[..pos].chars().next_back().is_some_and(is_word_char);
+            let region = self.regions.iter().position(|r| {
+                rest.starts_with(r.start.as_str())
+                    && !(prev_word && r.start.starts_with(is_word_char))
+            });
+            if let Some(idx) = region {
+                let from = pos + self.regions[idx].start.len();
+                let (end, st) = self.region_end(idx, 1, line, from);
+                toks.push((pos, end, &self.regions[idx].group));
+                pos = end;
+                next_state = st;
+                continue;
+            }
+
+            if self.numbers && word_start && ch.is_ascii_digit() {
+                toks.push((pos, pos + word_len, "number"));
+            }
+            pos += word_len;
+        }
+
+        // bytes into chars
+        let mut res: Vec<(usize, usize, &str)> = Vec::new();
+        let (mut byte, mut chars) = (0, 0);
+        let mut to_chars = |b: usize| {
+            chars += line[byte..b].chars().count();
+            byte = b;
+            chars
+        };
+        for (from, to, group) in toks {
+            let from = to_chars(from);
+            let to = to_chars(to);
+            res.push((from, to, group));
+        }
+        (res, next_state)
+    }
+}
+
+// Built-in syntaxes and the ones from dir; bad files are skipped and reported in errors
+pub fn load_syntaxes(dir: Option<&Path>, errors: &mut Vec<String>) -> Vec<Syntax> {
+    let mut res: Vec<Syntax> = Vec::new();
+    for (name, text) in BUILTIN {
+        match Syntax::parse(text) {
+            Ok(syn) => res.push(syn),
+            Err(e) => errors.push(format!("built-in syntax {}: {}", name, e)),
+        }
+    }
+    let Some(Ok(entries)) = dir.map(fs::read_dir) else {
+        return res;
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "toml"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let parsed = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Syntax::parse(&text));
+        match parsed {
+            Ok(syn) => {
+                res.retain(|s| s.name != syn.name);
+                res.push(syn);
+            }
+            Err(e) => errors.push(format!("syntax {}: {}", path.display(), e)),
+        }
+    }
+    res
+}
+
+// vim: ft=rust, vim: set filetype=rust:, frevi: syntax=rust
+static MODELINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(?:vim?|ex|frevi):.*\b(?:ft|filetype|syntax)=([\w.+-]+)").unwrap()
+});
+
+// Program of #! line: "#!/usr/bin/env python3" gives python3
+fn shebang_program(line: &str) -> Option<String> {
+    let rest = line.strip_prefix("#!")?;
+    let mut words = rest.split_whitespace();
+    let mut prog = words.next()?.rsplit('/').next()?;
+    if prog == "env" {
+        prog = words.find(|w| !w.starts_with('-'))?;
+    }
+    Some(prog.to_string())
+}
+
+// Filetype of a text: modeline, then file name, then #! line
+pub fn detect(
+    syntaxes: &[Syntax],
+    filename: &str,
+    first: &[String],
+    last: &[String],
+) -> Option<String> {
+    if let Some(ft) = first
+        .iter()
+        .chain(last.iter())
+        .find_map(|l| MODELINE.captures(l).map(|c| c[1].to_string()))
+        && syntaxes.iter().any(|s| s.name == ft)
+    {
+        return Some(ft);
+    }
+    let base = Path::new(filename)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = Path::new(filename)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    for syn in syntaxes.iter().rev() {
+        if syn.filenames.contains(&base) || ext.as_ref().is_some_and(|e| syn.extensions.contains(e))
+        {
+            return Some(syn.name.clone());
+        }
+    }
+    let prog = shebang_program(first.first()?)?;
+    // python3.11 is python too
+    let bare = prog.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    syntaxes
+        .iter()
+        .rev()
+        .find(|s| s.shebangs.iter().any(|p| *p == prog || *p == bare))
+        .map(|s| s.name.clone())
+}
+
+// Per tab: its filetype and states of lines as far as they were needed
+#[derive(Debug, Clone, Default)]
+pub struct SyntaxState {
+    pub filetype: Option<String>,
+    pub manual: bool,             // set by !filetype, not detected again
+    detected_for: Option<String>, // file name detection ran for
+    states: Vec<LineState>,       // state at start of line i
+}
+
+impl SyntaxState {
+    // Filetype set by hand, None detects it again
+    pub fn set_filetype(&mut self, filetype: Option<String>) {
+        self.manual = filetype.is_some();
+        self.filetype = filetype;
+        self.detected_for = None;
+        self.states.clear();
+    }
+}
+
+impl App {
+    pub fn tab_syntax_on(&self, tab: &Tab) -> bool {
+        tab.syntax.unwrap_or(self.config.syntax)
+    }
+
+    pub fn find_syntax(&self, name: &str) -> Option<&Syntax> {
+        self.syntaxes.iter().find(|s| s.name == name)
+    }
+
+    // Before draw: for tabs shown in windows
+    pub fn update_syntax(&mut self) {
+        let (wins, _) = self.win_areas(self.screen_main_area());
+        for win in wins {
+            self.update_tab_syntax(win.tab, win.scroll.0 + win.text.height as usize);
+        }
+    }
+
+    // Filetype of tab is detected if its file or first line changed,
+    // line states are brought up to the last shown line
+    fn update_tab_syntax(&mut self, idx: usize, last_shown: usize) {
+        let tab: &mut Tab = &mut self.tabs[idx];
+        let edited_from = tab.buf.take_edited_from();
+        if let Some(line) = edited_from {
+            tab.hl.states.truncate(line + 1);
+        }
+        let redetect = tab.hl.detected_for.as_ref() != Some(&tab.filename)
+            || edited_from == Some(0)
+            || edited_from.is_some_and(|l| l + MODELINE_LINES >= tab.buf.len_lines());
+        if redetect && !tab.hl.manual {
+            let len = tab.buf.len_lines();
+            let first: Vec<String> = (0..len.min(MODELINE_LINES))
+                .map(|l| tab.buf.line(l))
+                .collect();
+            let last: Vec<String> = (len.saturating_sub(MODELINE_LINES).max(first.len())..len)
+                .map(|l| tab.buf.line(l))
+                .collect();
+            let ft = detect(&self.syntaxes, &tab.filename, &first, &last);
+            if ft != tab.hl.filetype {
+                tab.hl.states.clear();
+            }
+            tab.hl.filetype = ft;
+            tab.hl.detected_for = Some(tab.filename.clone());
+        }
+
+        if !self.tab_syntax_on(&self.tabs[idx]) {
+            return;
+        }
+        let tab: &mut Tab = &mut self.tabs[idx];
+        let Some(syn) = (self.syntaxes.iter()).find(|s| Some(&s.name) == tab.hl.filetype.as_ref())
+        else {
+            return;
+        };
+        // state after the last line isn't needed
+        let upto = last_shown.min(tab.buf.last_line());
+        let states = &mut tab.hl.states;
+        if states.is_empty() {
+            states.push(None);
+        }
+        while states.len() <= upto {
+            let line = states.len() - 1;
+            let (_, next) = syn.tokenize(&tab.buf.line(line), states[line]);
+            states.push(next);
+        }
+    }
+
+    // Highlighted parts of a shown line, empty if its state isn't known
+    pub fn syntax_ranges(&self, tab: &Tab, line: usize, text: &str) -> Vec<(usize, usize, Style)> {
+        if !self.tab_syntax_on(tab) {
+            return Vec::new();
+        }
+        let (Some(syn), Some(state)) = (
+            tab.hl.filetype.as_ref().and_then(|ft| self.find_syntax(ft)),
+            tab.hl.states.get(line),
+        ) else {
+            return Vec::new();
+        };
+        syn.tokenize(text, *state)
+            .0
+            .into_iter()
+            .map(|(from, to, group)| (from, to, self.theme.style(&format!("syntax.{}", group))))
+            .collect()
+    }
+}
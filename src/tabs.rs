@@ -1,18 +1,43 @@
-use crate::edits::Edit;
+use crate::buffer::TextBuffer;
+use crate::disk::{self, DiskInfo};
+use crate::edits::{self, Edit, EditHist, FileIdent};
+use crate::fileformat::FileFormat;
+use crate::swap::{self, SwapState};
+use crate::syntax::SyntaxState;
+use crate::wrap::LineNumbers;
+use ropey::Rope;
 use std::{
-    fs::File,
-    io::{BufRead, BufReader},
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
+// Tabs move around when others are closed, so jump list refers to them by id
+static NEXT_TAB_ID: AtomicUsize = AtomicUsize::new(1);
+
 #[derive(Debug, Clone)]
 pub struct Tab {
     pub filename: String,
-    pub buf: Vec<String>,
-    pub cursor_xy: (usize, usize),
+    pub buf: TextBuffer,
+    pub cursor_xy: (usize, usize), // (x, line) in the buffer, not on screen
     pub displayed_name: String,
     pub changed: bool,
-    pub scroll_offset: usize,
-    pub edit_hist: Vec<Edit>,
+    pub scroll_offset: usize, // first line on screen, follows cursor (view.rs)
+    pub left_col: usize,      // first cell shown when lines aren't wrapped
+    pub wrap: Option<bool>,   // own wrap mode set by !tab wrap, None follows config
+    pub autoreload: Option<bool>, // reload when file changes on disk, set by !tab autoreload
+    pub tabstop: Option<usize>, // own options set by !setl, None follows config
+    pub expandtab: Option<bool>,
+    pub numbers: Option<LineNumbers>,
+    pub syntax: Option<bool>, // highlighting on or off
+    pub hl: SyntaxState,      // filetype and line states for highlighting
+    pub format: FileFormat,   // line ending, encoding, final newline of the file
+    pub edit_hist: EditHist,
+    pub marks: HashMap<char, (usize, usize)>, // (x, line); a-z, A-Z set here, '< '> and ''
+    pub win_cursors: HashMap<usize, (usize, usize)>, // cursors of other windows showing the tab, by window id
+    pub id: usize,
+    pub swap: SwapState,
+    pub disk: DiskInfo,         // how the file looked when read or written
+    abs_file: (String, String), // filename and its absolute name, see abs_filename
 }
 
 impl Tab {
@@ -20,12 +45,12 @@ impl Tab {
     // Sets name "New tab" if other not passed
     pub fn new(displayed_name: Option<String>) -> Tab {
         let fname: String = String::new();
-        let buf: Vec<String> = vec![String::new()];
+        let buf: TextBuffer = TextBuffer::new();
         let cursor_pos: (usize, usize) = (0, 0);
         let displayed_n = displayed_name.unwrap_or("New tab".to_string());
         let changes: bool = false;
         let sc_offset: usize = 0;
-        let ed_h: Vec<Edit> = Vec::new();
+        let ed_h: EditHist = EditHist::new();
 
         Tab {
             filename: fname,
@@ -34,27 +59,235 @@ impl Tab {
             displayed_name: displayed_n,
             changed: changes,
             scroll_offset: sc_offset,
+            left_col: 0,
+            wrap: None,
+            autoreload: None,
+            tabstop: None,
+            expandtab: None,
+            numbers: None,
+            syntax: None,
+            hl: SyntaxState::default(),
+            format: FileFormat::default(),
             edit_hist: ed_h,
+            marks: HashMap::new(),
+            win_cursors: HashMap::new(),
+            id: NEXT_TAB_ID.fetch_add(1, Ordering::Relaxed),
+            swap: SwapState::default(),
+            disk: DiskInfo::default(),
+            abs_file: (String::new(), String::new()),
         }
     }
 
     // Reads file into tab
     pub fn readf(&mut self, filename: String) -> Result<(), std::io::Error> {
         self.filename = filename.clone();
-        let file: File = match File::open(filename) {
-            Ok(f) => f,
-            Err(e) => return Err(e),
-        };
-        let buf_reader = BufReader::new(file);
-        for line in buf_reader.lines() {
-            self.buf.push(line.expect("Can't parse line!"));
-        }
+        let (buf, format, stamp) = disk::read_file(&filename)?;
+        self.buf = buf;
+        self.format = format;
+        self.disk = DiskInfo::new(Some(stamp));
+        self.edit_hist = EditHist::new();
+        self.marks.clear();
+        self.mark_saved();
 
         Ok(())
     }
 
     pub fn str_into_buf(&mut self, content: String) {
-        let lines: Vec<String> = content.split('\n').map(|s| s.to_string()).collect();
-        self.buf = lines;
+        self.buf = TextBuffer::from_text(&content);
+    }
+
+    // Inserts text at (line, x) recording it into edit history.
+    // coalesce joins it with the running typing session.
+    // Returns position right after inserted text.
+    pub fn insert_text(
+        &mut self,
+        line: usize,
+        x: usize,
+        text: &str,
+        coalesce: bool,
+    ) -> (usize, usize) {
+        let text_rope = Rope::from_str(text);
+        // new line opened after the marked one (o, put below) leaves its marks in place
+        let sticky = text.starts_with('\n') && x >= self.buf.line_len(line);
+        self.buf.insert_rope(line, x, &text_rope);
+        let edit = Edit::new_at_curs((x, line), Rope::new(), text_rope);
+        let end = edits::text_end(line, x, &edit.inserted);
+        self.shift_marks((line, x), (line, x), end, sticky);
+        self.edit_hist.push(edit, coalesce);
+        self.changed = true;
+        end
+    }
+
+    // Removes text between two positions recording it into edit history.
+    pub fn remove_text(
+        &mut self,
+        start_line: usize,
+        start_x: usize,
+        end_line: usize,
+        end_x: usize,
+        coalesce: bool,
+    ) -> String {
+        let removed = self.buf.remove(start_line, start_x, end_line, end_x);
+        if removed.len_chars() == 0 {
+            return String::new();
+        }
+        let removed_str = removed.to_string();
+        let start = (start_line, start_x);
+        self.shift_marks(start, (end_line, end_x), start, false);
+        let edit = Edit::new_at_curs((start_x, start_line), removed, Rope::new());
+        self.edit_hist.push(edit, coalesce);
+        self.changed = true;
+        removed_str
+    }
+
+    // Replaces whole buffer as one undoable change
+    pub fn replace_buf(&mut self, new_buf: TextBuffer) {
+        let old_text = self.buf.rope().clone();
+        let new_text = new_buf.rope().clone();
+        self.buf = new_buf;
+        self.marks.clear();
+        let edit = Edit::new_at_curs((0, 0), old_text, new_text);
+        self.edit_hist.push(edit, false);
+        self.changed = true;
+    }
+
+    // Returns false if there's nothing to undo
+    pub fn undo(&mut self) -> bool {
+        match self.edit_hist.undo(&mut self.buf) {
+            Some(curs) => {
+                if let Some(group) = self.edit_hist.last_undone() {
+                    for edit in group.edits.clone().iter().rev() {
+                        let start = (edit.start_line, edit.start_x);
+                        let old_end = edits::text_end(start.0, start.1, &edit.inserted);
+                        let new_end = edits::text_end(start.0, start.1, &edit.removed);
+                        self.shift_marks(start, old_end, new_end, false);
+                    }
+                }
+                if let Some((before, _)) = self.edit_hist.last_undone().and_then(|g| g.file.clone())
+                {
+                    self.set_file(before);
+                }
+                self.cursor_xy = curs;
+                self.clamp_cursor();
+                self.changed = !self.edit_hist.is_saved();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.edit_hist.redo(&mut self.buf) {
+            Some(curs) => {
+                if let Some(group) = self.edit_hist.last_redone() {
+                    for edit in group.edits.clone().iter() {
+                        let start = (edit.start_line, edit.start_x);
+                        let old_end = edits::text_end(start.0, start.1, &edit.removed);
+                        let new_end = edits::text_end(start.0, start.1, &edit.inserted);
+                        self.shift_marks(start, old_end, new_end, false);
+                    }
+                }
+                if let Some((_, after)) = self.edit_hist.last_redone().and_then(|g| g.file.clone())
+                {
+                    self.set_file(after);
+                }
+                self.cursor_xy = curs;
+                self.clamp_cursor();
+                self.changed = !self.edit_hist.is_saved();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Absolute name of tab's file (swap::abs_path), looked up again only when filename changes
+    pub fn abs_filename(&mut self) -> String {
+        if self.abs_file.0 != self.filename {
+            self.abs_file = (self.filename.clone(), swap::abs_path(&self.filename));
+        }
+        self.abs_file.1.clone()
+    }
+
+    pub fn file_ident(&self) -> FileIdent {
+        FileIdent {
+            filename: self.filename.clone(),
+            format: self.format,
+            disk: self.disk.clone(),
+        }
+    }
+
+    pub fn set_file(&mut self, file: FileIdent) {
+        self.filename = file.filename;
+        self.format = file.format;
+        self.disk = file.disk;
+    }
+
+    // Called after the buffer was written to / read from disk
+    pub fn mark_saved(&mut self) {
+        self.edit_hist.mark_saved();
+        self.changed = false;
+    }
+
+    // Disk has other text than the buffer, whatever is undone
+    pub fn mark_unsaved(&mut self) {
+        self.edit_hist.mark_unsaved();
+        self.changed = true;
+    }
+
+    // Keeps marks on their text after an edit: text from `start` to `old_end` became
+    // text from `start` to `new_end`, all as (line, x). Marks on lines removed whole
+    // (with their newline) are dropped, other ones in removed text go to `start`.
+    // `sticky` keeps marks right at `start` in place. Cursors of other windows move
+    // the same way, but they're never dropped.
+    fn shift_marks(
+        &mut self,
+        start: (usize, usize),
+        old_end: (usize, usize),
+        new_end: (usize, usize),
+        sticky: bool,
+    ) {
+        self.marks.retain(
+            |_, mark| match shift_pos(*mark, start, old_end, new_end, sticky) {
+                Some(pos) => {
+                    *mark = pos;
+                    true
+                }
+                None => false,
+            },
+        );
+        for cursor in self.win_cursors.values_mut() {
+            *cursor =
+                shift_pos(*cursor, start, old_end, new_end, sticky).unwrap_or((start.1, start.0));
+        }
+    }
+
+    fn clamp_cursor(&mut self) {
+        self.cursor_xy.1 = self.cursor_xy.1.min(self.buf.last_line());
+        let line_len = self.buf.line_len(self.cursor_xy.1);
+        self.cursor_xy.0 = self.cursor_xy.0.min(line_len);
+    }
+}
+
+// Where (x, line) pos goes after an edit, see Tab::shift_marks; None if its line was removed
+fn shift_pos(
+    pos: (usize, usize),
+    start: (usize, usize),
+    old_end: (usize, usize),
+    new_end: (usize, usize),
+    sticky: bool,
+) -> Option<(usize, usize)> {
+    let (line, x) = (pos.1, pos.0);
+    if (line, x) < start || (sticky && (line, x) == start) {
+        return Some(pos);
+    }
+    if (line, x) >= old_end {
+        return Some(match line == old_end.0 {
+            true => (new_end.1 + x - old_end.1, new_end.0),
+            false => (x, line - old_end.0 + new_end.0),
+        });
+    }
+    if start <= (line, 0) && old_end >= (line + 1, 0) {
+        return None;
     }
+    Some((start.1, start.0))
 }
@@ -0,0 +1,313 @@
+use crate::buffer::TextBuffer;
+use crate::motions::{self, swap};
+use crate::operators::OpRange;
+
+// Text objects for operator-pending mode: iw, aw, i(, a" ...
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextObj {
+    pub inner: bool, // i or a
+    pub kind: TextObjKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextObjKind {
+    Word,
+    BigWord,
+    Sentence,
+    Paragraph,
+    Pair(char, char), // open, close
+    Quote(char),
+}
+
+// Parses object char after i/a
+pub fn parse_obj_kind(ch: char) -> Option<TextObjKind> {
+    let kind = match ch {
+        'w' => TextObjKind::Word,
+        'W' => TextObjKind::BigWord,
+        's' => TextObjKind::Sentence,
+        'p' => TextObjKind::Paragraph,
+        '(' | ')' | 'b' => TextObjKind::Pair('(', ')'),
+        '[' | ']' => TextObjKind::Pair('[', ']'),
+        '{' | '}' | 'B' => TextObjKind::Pair('{', '}'),
+        '<' | '>' => TextObjKind::Pair('<', '>'),
+        '"' | '\'' | '`' => TextObjKind::Quote(ch),
+        _ => return None,
+    };
+    Some(kind)
+}
+
+// Finds the range the object covers around cursor (x, line)
+pub fn select(buf: &TextBuffer, pos: (usize, usize), obj: TextObj) -> Option<OpRange> {
+    match obj.kind {
+        TextObjKind::Word => select_word(buf, pos, obj.inner, false),
+        TextObjKind::BigWord => select_word(buf, pos, obj.inner, true),
+        TextObjKind::Sentence => select_sentence(buf, pos, obj.inner),
+        TextObjKind::Paragraph => select_paragraph(buf, pos, obj.inner),
+        TextObjKind::Pair(open, close) => select_pair(buf, pos, obj.inner, open, close),
+        TextObjKind::Quote(q) => select_quote(buf, pos, obj.inner, q),
+    }
+}
+
+fn is_word_char(ch: char, big: bool) -> bool {
+    if big {
+        return !ch.is_whitespace();
+    }
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn same_class(a: char, b: char, big: bool) -> bool {
+    if a.is_whitespace() || b.is_whitespace() {
+        return a.is_whitespace() && b.is_whitespace();
+    }
+    is_word_char(a, big) == is_word_char(b, big)
+}
+
+fn select_word(buf: &TextBuffer, pos: (usize, usize), inner: bool, big: bool) -> Option<OpRange> {
+    let (x, line) = pos;
+    let chars: Vec<char> = buf.line(line).chars().collect();
+    if chars.is_empty() {
+        return None;
+    }
+    let x = x.min(chars.len() - 1);
+    let run = |from: usize| -> (usize, usize) {
+        let mut s = from;
+        let mut e = from + 1;
+        while s > 0 && same_class(chars[s - 1], chars[from], big) {
+            s -= 1;
+        }
+        while e < chars.len() && same_class(chars[e], chars[from], big) {
+            e += 1;
+        }
+        (s, e)
+    };
+    let (mut start, mut end) = run(x);
+    if !inner {
+        if chars[x].is_whitespace() {
+            // blanks plus the word after them
+            if end < chars.len() {
+                end = run(end).1;
+            }
+        } else if end < chars.len() && chars[end].is_whitespace() {
+            end = run(end).1;
+        } else if start > 0 && chars[start - 1].is_whitespace() {
+            start = run(start - 1).0;
+        }
+    }
+    Some(OpRange::chars((start, line), (end, line)))
+}
+
+fn select_sentence(buf: &TextBuffer, pos: (usize, usize), inner: bool) -> Option<OpRange> {
+    let (x, line) = pos;
+    if motions::is_blank_line(buf, line) {
+        return None;
+    }
+    // sentences never cross paragraph borders
+    let mut first = line;
+    while first > 0 && !motions::is_blank_line(buf, first - 1) {
+        first -= 1;
+    }
+    let mut last = line;
+    while last < buf.last_line() && !motions::is_blank_line(buf, last + 1) {
+        last += 1;
+    }
+    let par_start = buf.pos_to_char(first, 0);
+    let par_end = buf.pos_to_char(last, buf.line_len(last));
+    let cursor = buf.pos_to_char(line, x);
+    let chars: Vec<char> = buf.rope().slice(par_start..par_end).chars().collect();
+
+    // (start, end, end with trailing blanks), relative to par_start
+    let mut sentences: Vec<(usize, usize, usize)> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        if i >= chars.len() {
+            break;
+        }
+        let start = i;
+        let mut end = chars.len();
+        while i < chars.len() {
+            if ".!?".contains(chars[i]) {
+                let mut j = i + 1;
+                while j < chars.len() && ")]\"'".contains(chars[j]) {
+                    j += 1;
+                }
+                if j >= chars.len() || chars[j].is_whitespace() {
+                    end = j;
+                    break;
+                }
+            }
+            i += 1;
+        }
+        let mut trail = end;
+        while trail < chars.len() && chars[trail].is_whitespace() {
+            trail += 1;
+        }
+        sentences.push((start, end, trail));
+        i = trail;
+    }
+
+    let rel = cursor - par_start;
+    let idx = sentences
+        .iter()
+        .position(|&(_, _, trail)| rel < trail)
+        .unwrap_or(sentences.len().checked_sub(1)?);
+    let (start, end, trail) = sentences[idx];
+    let (mut from, mut to) = (start, end);
+    if !inner {
+        if trail > end {
+            to = trail;
+        } else if idx > 0 {
+            from = sentences[idx - 1].1;
+        }
+    }
+    Some(OpRange::chars(
+        swap(buf.char_to_pos(par_start + from)),
+        swap(buf.char_to_pos(par_start + to)),
+    ))
+}
+
+fn select_paragraph(buf: &TextBuffer, pos: (usize, usize), inner: bool) -> Option<OpRange> {
+    let line = pos.1;
+    let blank = motions::is_blank_line(buf, line);
+    let same = |l: usize| motions::is_blank_line(buf, l) == blank;
+    let mut first = line;
+    while first > 0 && same(first - 1) {
+        first -= 1;
+    }
+    let mut last = line;
+    while last < buf.last_line() && same(last + 1) {
+        last += 1;
+    }
+    if !inner {
+        if last < buf.last_line() {
+            // following run of the other kind
+            let other_blank = !blank;
+            while last < buf.last_line() && motions::is_blank_line(buf, last + 1) == other_blank {
+                last += 1;
+            }
+        } else if !blank {
+            while first > 0 && motions::is_blank_line(buf, first - 1) {
+                first -= 1;
+            }
+        }
+    }
+    Some(OpRange::lines(first, last))
+}
+
+fn select_pair(
+    buf: &TextBuffer,
+    pos: (usize, usize),
+    inner: bool,
+    open: char,
+    close: char,
+) -> Option<OpRange> {
+    let rope = buf.rope();
+    let len = rope.len_chars();
+    if len == 0 {
+        return None;
+    }
+    let cursor = buf.pos_to_char(pos.1, pos.0).min(len - 1);
+
+    // searching enclosing open bracket
+    let mut open_idx: Option<usize> = None;
+    let mut depth: usize = 0;
+    let mut i = cursor as isize;
+    if rope.char(cursor) == close {
+        i -= 1;
+    }
+    while i >= 0 {
+        let ch = rope.char(i as usize);
+        if ch == close {
+            depth += 1;
+        } else if ch == open {
+            if depth == 0 {
+                open_idx = Some(i as usize);
+                break;
+            }
+            depth -= 1;
+        }
+        i -= 1;
+    }
+    let open_idx = open_idx?;
+
+    let mut close_idx: Option<usize> = None;
+    depth = 0;
+    for j in open_idx + 1..len {
+        let ch = rope.char(j);
+        if ch == open {
+            depth += 1;
+        } else if ch == close {
+            if depth == 0 {
+                close_idx = Some(j);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let close_idx = close_idx?;
+
+    let (mut from, mut to) = (open_idx, close_idx + 1);
+    if inner {
+        from = open_idx + 1;
+        to = close_idx;
+        // {\n ... \n} : keep the lines with brackets
+        if from < to && rope.char(from) == '\n' {
+            from += 1;
+        }
+        let close_line = rope.char_to_line(close_idx);
+        let close_line_start = rope.line_to_char(close_line);
+        if close_line > rope.char_to_line(open_idx)
+            && from <= close_line_start
+            && rope
+                .slice(close_line_start..close_idx)
+                .chars()
+                .all(|c| c.is_whitespace())
+        {
+            to = close_line_start;
+        }
+    }
+    Some(OpRange::chars(
+        swap(buf.char_to_pos(from)),
+        swap(buf.char_to_pos(to.max(from))),
+    ))
+}
+
+fn select_quote(buf: &TextBuffer, pos: (usize, usize), inner: bool, q: char) -> Option<OpRange> {
+    let (x, line) = pos;
+    let chars: Vec<char> = buf.line(line).chars().collect();
+    let quotes: Vec<usize> = (0..chars.len())
+        .filter(|&i| chars[i] == q && (i == 0 || chars[i - 1] != '\\'))
+        .collect();
+    let mut pair: Option<(usize, usize)> = None;
+    for k in (0..quotes.len().saturating_sub(1)).step_by(2) {
+        if quotes[k] <= x && x <= quotes[k + 1] {
+            pair = Some((quotes[k], quotes[k + 1]));
+            break;
+        }
+    }
+    if pair.is_none() {
+        // first quoted string after cursor
+        let after: Vec<usize> = quotes.iter().copied().filter(|&i| i > x).collect();
+        if after.len() >= 2 {
+            pair = Some((after[0], after[1]));
+        }
+    }
+    let (open, close) = pair?;
+    if inner {
+        return Some(OpRange::chars((open + 1, line), (close, line)));
+    }
+    let mut from = open;
+    let mut to = close + 1;
+    if to < chars.len() && chars[to].is_whitespace() {
+        while to < chars.len() && chars[to].is_whitespace() {
+            to += 1;
+        }
+    } else {
+        while from > 0 && chars[from - 1].is_whitespace() {
+            from -= 1;
+        }
+    }
+    Some(OpRange::chars((from, line), (to, line)))
+}
@@ -0,0 +1,246 @@
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use ratatui::style::{Color, Modifier, Style};
+
+use crate::app::{App, StatusLevel};
+
+// Color themes. A theme is a TOML file (see themes/*.toml, built in, and
+// ~/.frevi/themes/*.toml, which win over built-in ones of the same name) giving
+// styles to highlight groups:
+//   title = { fg = "yellow", bold = true }
+//   showbreak = "darkgray"                      - a string is just fg
+//   cursorline = { bg = ["#2c313c", "reset"] }  - second color is for 16-color terminals
+//   [syntax] comment = "cyan"                   - token groups of syntaxes
+// Colors are names (red, lightblue...), 0..255 or #rrggbb. Without truecolor #rrggbb
+// and 16..255 become the nearest basic color. Groups a theme leaves out come from default.
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("default", include_str!("../themes/default.toml")),
+    ("dark", include_str!("../themes/dark.toml")),
+];
+
+// Basic colors as most terminals show them, for picking the nearest one
+const BASIC: &[(Color, (u8, u8, u8))] = &[
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub name: String,
+    groups: HashMap<String, Style>,
+}
+
+impl Theme {
+    // Style of a group, plain if the theme doesn't have it
+    pub fn style(&self, group: &str) -> Style {
+        self.groups.get(group).copied().unwrap_or_default()
+    }
+
+    // Groups of text are laid over base ones
+    fn parse(
+        name: &str,
+        text: &str,
+        base: Option<&Theme>,
+        truecolor: bool,
+    ) -> Result<Theme, String> {
+        let table = text.parse::<toml::Table>().map_err(|e| e.to_string())?;
+        let mut groups = base.map(|b| b.groups.clone()).unwrap_or_default();
+        flat_styles(&table, "", truecolor, &mut groups)?;
+        Ok(Theme {
+            name: name.to_string(),
+            groups,
+        })
+    }
+}
+
+// [syntax] comment = ... becomes group syntax.comment
+fn flat_styles(
+    table: &toml::Table,
+    prefix: &str,
+    truecolor: bool,
+    res: &mut HashMap<String, Style>,
+) -> Result<(), String> {
+    for (key, value) in table.iter() {
+        let name = format!("{}{}", prefix, key);
+        match (name.as_str(), value) {
+            ("syntax", toml::Value::Table(t)) => flat_styles(t, "syntax.", truecolor, res)?,
+            (_, v) => {
+                let style = parse_style(v, truecolor).map_err(|e| format!("{}: {}", name, e))?;
+                res.insert(name, style);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_style(value: &toml::Value, truecolor: bool) -> Result<Style, String> {
+    let table = match value {
+        toml::Value::String(_) => return Ok(Style::default().fg(parse_color(value, truecolor)?)),
+        toml::Value::Table(t) => t,
+        other => {
+            return Err(format!(
+                "expected a table or color, got {}",
+                other.type_str()
+            ));
+        }
+    };
+    let mut style = Style::default();
+    for (key, val) in table.iter() {
+        let modifier = match key.as_str() {
+            "fg" => {
+                style = style.fg(parse_color(val, truecolor)?);
+                continue;
+            }
+            "bg" => {
+                style = style.bg(parse_color(val, truecolor)?);
+                continue;
+            }
+            "bold" => Modifier::BOLD,
+            "italic" => Modifier::ITALIC,
+            "underlined" => Modifier::UNDERLINED,
+            "reversed" => Modifier::REVERSED,
+            "dim" => Modifier::DIM,
+            _ => return Err(format!("unknown key {}", key)),
+        };
+        match val.as_bool() {
+            Some(true) => style = style.add_modifier(modifier),
+            Some(false) => style = style.remove_modifier(modifier),
+            None => return Err(format!("{}: expected true or false", key)),
+        }
+    }
+    Ok(style)
+}
+
+// "name", "#rrggbb" or ["#rrggbb", "fallback"]
+fn parse_color(value: &toml::Value, truecolor: bool) -> Result<Color, String> {
+    let (color, fallback) = match value {
+        toml::Value::String(s) => (s.as_str(), None),
+        toml::Value::Array(a) => match a.as_slice() {
+            [toml::Value::String(c), toml::Value::String(f)] => (c.as_str(), Some(f.as_str())),
+            _ => return Err("expected a pair of colors".to_string()),
+        },
+        other => return Err(format!("expected a color, got {}", other.type_str())),
+    };
+    let parse = |s: &str| Color::from_str(s).map_err(|_| format!("unknown color {}", s));
+    let color = parse(color)?;
+    let fallback = fallback.map(parse).transpose()?;
+    Ok(match (color, truecolor) {
+        (Color::Rgb(r, g, b), false) => fallback.unwrap_or(nearest_basic(r, g, b)),
+        (Color::Indexed(n), false) if n > 15 => {
+            let (r, g, b) = indexed_rgb(n);
+            fallback.unwrap_or(nearest_basic(r, g, b))
+        }
+        _ => color,
+    })
+}
+
+// xterm 256 palette: 16..231 is a 6x6x6 cube, 232..255 grays
+fn indexed_rgb(n: u8) -> (u8, u8, u8) {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match n {
+        16..=231 => {
+            let i = (n - 16) as usize;
+            (LEVELS[i / 36], LEVELS[i / 6 % 6], LEVELS[i % 6])
+        }
+        232..=255 => {
+            let v = 8 + 10 * (n - 232);
+            (v, v, v)
+        }
+        _ => BASIC[n as usize].1,
+    }
+}
+
+fn nearest_basic(r: u8, g: u8, b: u8) -> Color {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2);
+    BASIC
+        .iter()
+        .min_by_key(|(_, (br, bg, bb))| d(r, *br) + d(g, *bg) + d(b, *bb))
+        .map(|(c, _)| *c)
+        .unwrap_or(Color::Reset)
+}
+
+// Terminals with 24-bit colors say so in COLORTERM
+pub fn detect_truecolor() -> bool {
+    std::env::var("COLORTERM").is_ok_and(|v| v == "truecolor" || v == "24bit")
+}
+
+// User's file in dir wins over built-in theme of the same name
+pub fn load_theme(name: &str, dir: Option<&Path>, truecolor: bool) -> Result<Theme, String> {
+    let default = Theme::parse("default", BUILTIN[0].1, None, truecolor)
+        .map_err(|e| format!("built-in theme default: {}", e))?;
+    let path = dir.map(|d| d.join(format!("{}.toml", name)));
+    if let Some(path) = path.filter(|p| p.is_file()) {
+        let text = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        return Theme::parse(name, &text, Some(&default), truecolor)
+            .map_err(|e| format!("theme {}: {}", path.display(), e));
+    }
+    match BUILTIN.iter().find(|(n, _)| *n == name) {
+        Some((_, text)) => Theme::parse(name, text, Some(&default), truecolor)
+            .map_err(|e| format!("built-in theme {}: {}", name, e)),
+        None => Err(format!(
+            "No theme {}; there are: {}",
+            name,
+            theme_names(dir).join(", ")
+        )),
+    }
+}
+
+// Built-in and user themes, sorted
+pub fn theme_names(dir: Option<&Path>) -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN.iter().map(|(n, _)| n.to_string()).collect();
+    if let Some(Ok(entries)) = dir.map(fs::read_dir) {
+        names.extend(
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|e| e == "toml"))
+                .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().to_string())),
+        );
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+impl StatusLevel {
+    pub fn group(&self) -> &'static str {
+        match self {
+            StatusLevel::Info => "status",
+            StatusLevel::Warning => "status_warning",
+            StatusLevel::Error => "status_error",
+        }
+    }
+}
+
+impl App {
+    pub fn themes_dir(&self) -> std::path::PathBuf {
+        self.config.cfg_path.join("themes")
+    }
+
+    pub fn truecolor(&self) -> bool {
+        self.config.truecolor.unwrap_or_else(detect_truecolor)
+    }
+
+    // Loads theme and makes it the config one, current theme stays on error
+    pub fn set_theme(&mut self, name: &str) -> Result<(), String> {
+        let dir = self.themes_dir();
+        self.theme = load_theme(name, Some(dir.as_path()), self.truecolor())?;
+        self.config.theme = name.to_string();
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+use crate::app::{App, Mode};
+use crate::grapheme;
+use crate::tabs::Tab;
+use crate::wrap;
+
+// z commands: where zt / zz / zb put the cursor line, zh / zl / zH / zL scroll sideways
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScrollTo {
+    Top,
+    Center,
+    Bottom,
+    Left { half: bool },
+    Right { half: bool },
+}
+
+impl ScrollTo {
+    // Char after z
+    pub fn from_char(ch: char) -> Option<ScrollTo> {
+        match ch {
+            't' => Some(ScrollTo::Top),
+            'z' | '.' => Some(ScrollTo::Center),
+            'b' | '-' => Some(ScrollTo::Bottom),
+            'h' | 'H' => Some(ScrollTo::Left { half: ch == 'H' }),
+            'l' | 'L' => Some(ScrollTo::Right { half: ch == 'L' }),
+            _ => None,
+        }
+    }
+}
+
+// Cursor is kept in buffer coordinates, the tab's scroll_offset is the first shown line
+// (left_col the first shown cell without wrap). Cursor moves scroll the view,
+// scrolling moves the cursor, so it never leaves the screen.
+impl App {
+    // Lines of text fitting in focused window, same math as in draw
+    pub fn text_area_height(&self) -> usize {
+        (self.focused_text_area().height as usize).max(1)
+    }
+
+    // Cells for text and line numbers of focused window
+    pub fn text_area_width(&self) -> usize {
+        self.focused_text_area().width as usize
+    }
+
+    pub fn tab_wraps(&self, tab: &Tab) -> bool {
+        tab.wrap.unwrap_or(self.config.wrap)
+    }
+
+    // Cells left for text of line after its number
+    fn line_width(&self, line: usize) -> usize {
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let numbers = self.tab_numbers(cur_tab);
+        self.text_area_width()
+            .saturating_sub(wrap::gutter_width(line, cur_tab.cursor_xy.1, numbers))
+            .max(1)
+    }
+
+    // Screen rows taken by line of current tab
+    fn line_height(&self, line: usize) -> usize {
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        if !self.tab_wraps(cur_tab) {
+            return 1;
+        }
+        let text = cur_tab.buf.line(line);
+        wrap::line_rows(&text, self.line_width(line), self.tab_tabstop(cur_tab))
+            .starts
+            .len()
+    }
+
+    // Row of cursor line the cursor is on
+    fn cursor_row(&self) -> usize {
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        if !self.tab_wraps(cur_tab) {
+            return 0;
+        }
+        let (x, line) = cur_tab.cursor_xy;
+        let text = cur_tab.buf.line(line);
+        wrap::cursor_cell(&text, x, self.line_width(line), self.tab_tabstop(cur_tab)).0
+    }
+
+    // Lines kept between cursor and screen edge, can't be more than half of the screen
+    fn scrolloff(&self, height: usize) -> usize {
+        self.config.scrolloff.min(height.saturating_sub(1) / 2)
+    }
+
+    // First line to show so cursor row has `rows` rows above it
+    fn top_for_cursor_at(&self, rows: usize) -> usize {
+        let mut top = self.tabs[self.cur_tab].cursor_xy.1;
+        let mut above = self.cursor_row();
+        while top > 0 && above + self.line_height(top - 1) <= rows {
+            top -= 1;
+            above += self.line_height(top);
+        }
+        top
+    }
+
+    // Scrolls current tab so cursor line is visible with scrolloff lines around it.
+    // Small moves scroll just enough, far jumps put the cursor line in the middle.
+    pub fn scroll_to_cursor(&mut self) {
+        let height = self.text_area_height();
+        let so = self.scrolloff(height);
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let (line, last_line) = (cur_tab.cursor_xy.1, cur_tab.buf.last_line());
+        let mut top = cur_tab.scroll_offset;
+        if line + height / 2 < top || line >= top + height + height / 2 {
+            top = self.top_for_cursor_at(height / 2);
+        } else if line < top + so {
+            top = line.saturating_sub(so);
+        }
+        // rows down to the cursor and scrolloff after it have to fit,
+        // no margin needed after the last line
+        let mut rows = (top..line).map(|l| self.line_height(l)).sum::<usize>()
+            + self.cursor_row()
+            + 1
+            + so.min(last_line - line);
+        while rows > height && top < line {
+            rows -= self.line_height(top);
+            top += 1;
+        }
+        self.tabs[self.cur_tab].scroll_offset = top;
+        self.follow_cursor_x();
+    }
+
+    // Without wrap: scrolls sideways so cursor cell is on screen
+    fn follow_cursor_x(&mut self) {
+        let wraps = self.tab_wraps(&self.tabs[self.cur_tab]);
+        let tabstop = self.tab_tabstop(&self.tabs[self.cur_tab]);
+        let width = self.line_width(self.tabs[self.cur_tab].cursor_xy.1);
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        if wraps {
+            cur_tab.left_col = 0;
+            return;
+        }
+        let (x, line) = cur_tab.cursor_xy;
+        let text = cur_tab.buf.line(line);
+        let col = grapheme::display_col(&text, x, tabstop);
+        let next_col = grapheme::display_col(&text, grapheme::next(&text, x, 1), tabstop);
+        let cell_w = (next_col - col).max(1);
+        if col < cur_tab.left_col {
+            cur_tab.left_col = col;
+        } else if col + cell_w > cur_tab.left_col + width {
+            cur_tab.left_col = col + cell_w - width;
+        }
+    }
+
+    // After scrolling: cursor goes to the closest line still on screen
+    fn cursor_into_view(&mut self) {
+        let height = self.text_area_height();
+        let so = self.scrolloff(height);
+        let insert = self.mode == Mode::Insert;
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let top = cur_tab.scroll_offset;
+        let last_line = cur_tab.buf.last_line();
+        let mut last_shown = top;
+        let mut rows = self.line_height(top);
+        while last_shown < last_line && rows + self.line_height(last_shown + 1) <= height {
+            last_shown += 1;
+            rows += self.line_height(last_shown);
+        }
+        let min_line = match top {
+            0 => 0,
+            _ => top + so,
+        };
+        let max_line = match last_shown == last_line {
+            true => last_line,
+            false => last_shown.saturating_sub(so).max(top),
+        };
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let line = cur_tab.cursor_xy.1.clamp(min_line.min(max_line), max_line);
+        if line == cur_tab.cursor_xy.1 {
+            return;
+        }
+        cur_tab.edit_hist.seal();
+        cur_tab.cursor_xy.1 = line;
+        let line_len = cur_tab.buf.line_len(line);
+        let x = match insert {
+            true => cur_tab.cursor_xy.0.min(line_len),
+            false => cur_tab.cursor_xy.0.min(line_len.saturating_sub(1)),
+        };
+        cur_tab.cursor_xy.0 = cur_tab.buf.grapheme_start(line, x);
+    }
+
+    pub fn tab_update_scroll(&mut self, new_scroll_offset: usize) {
+        let cur_tab = &mut self.tabs[self.cur_tab];
+        let max_scroll = cur_tab.buf.last_line();
+        cur_tab.scroll_offset = new_scroll_offset.clamp(0, max_scroll);
+        self.cursor_into_view();
+    }
+
+    // PageUp/PageDown, mouse wheel
+    pub fn tab_update_scroll_delta(&mut self, delta: isize) {
+        let cur_tab = &mut self.tabs[self.cur_tab];
+        let max_scroll = cur_tab.buf.last_line();
+        let new_scroll = (cur_tab.scroll_offset as isize + delta).clamp(0, max_scroll as isize);
+        cur_tab.scroll_offset = new_scroll as usize;
+        self.cursor_into_view();
+    }
+
+    // zh / zl, sideways wheel: moves view by cells when lines aren't wrapped,
+    // cursor goes to the nearest char still on screen
+    pub fn scroll_sideways(&mut self, delta: isize) {
+        if self.tab_wraps(&self.tabs[self.cur_tab]) {
+            return;
+        }
+        self.seal_edit();
+        let tabstop = self.tab_tabstop(&self.tabs[self.cur_tab]);
+        let width = self.line_width(self.tabs[self.cur_tab].cursor_xy.1);
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        cur_tab.left_col = cur_tab.left_col.saturating_add_signed(delta);
+        let (x, line) = cur_tab.cursor_xy;
+        let text = cur_tab.buf.line(line);
+        let left = cur_tab.left_col;
+        let col = grapheme::display_col(&text, x, tabstop);
+        if col < left {
+            let x = grapheme::x_at_col(&text, left, tabstop);
+            cur_tab.cursor_xy.0 = match grapheme::display_col(&text, x, tabstop) < left {
+                true => grapheme::next(&text, x, 1),
+                false => x,
+            };
+        } else if col >= left + width {
+            let x = grapheme::x_at_col(&text, left + width - 1, tabstop);
+            let end = grapheme::display_col(&text, grapheme::next(&text, x, 1), tabstop);
+            cur_tab.cursor_xy.0 = match end > left + width {
+                true => grapheme::prev(&text, x, 1),
+                false => x,
+            };
+        }
+        if self.mode != Mode::Insert {
+            self.clamp_normal_cursor();
+        }
+    }
+
+    // Ctrl-D / Ctrl-U: view and cursor move by half a screen
+    pub fn scroll_half_page(&mut self, down: bool) {
+        self.seal_edit();
+        let half = (self.text_area_height() / 2).max(1) as isize;
+        let delta = if down { half } else { -half };
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let last_line = cur_tab.buf.last_line() as isize;
+        cur_tab.cursor_xy.1 = (cur_tab.cursor_xy.1 as isize + delta).clamp(0, last_line) as usize;
+        self.tab_update_scroll_delta(delta);
+        self.clamp_normal_cursor();
+        self.scroll_to_cursor();
+    }
+
+    // zt / zz / zb with count go to that line first; zh / zl count is cells
+    pub fn scroll_cursor_to(&mut self, to: ScrollTo, count: Option<usize>) {
+        let half_width = (self.line_width(self.tabs[self.cur_tab].cursor_xy.1) / 2) as isize;
+        let n = count.unwrap_or(1) as isize;
+        match to {
+            ScrollTo::Left { half } => {
+                return self.scroll_sideways(if half { -half_width } else { -n });
+            }
+            ScrollTo::Right { half } => {
+                return self.scroll_sideways(if half { half_width } else { n });
+            }
+            _ => {}
+        }
+        if let Some(n) = count {
+            self.goto_line(n.saturating_sub(1));
+        }
+        let height = self.text_area_height();
+        let so = self.scrolloff(height);
+        let top = match to {
+            ScrollTo::Center => self.top_for_cursor_at(height / 2),
+            ScrollTo::Bottom => self.top_for_cursor_at(height.saturating_sub(so + 1)),
+            _ => self.tabs[self.cur_tab].cursor_xy.1.saturating_sub(so),
+        };
+        self.tabs[self.cur_tab].scroll_offset = top;
+    }
+}
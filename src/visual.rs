@@ -0,0 +1,437 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::app::{App, Mode};
+use crate::motions::{Motion, swap};
+use crate::normal::{self, Parsed};
+use crate::operators::{OpRange, Operator};
+use crate::tabs::Tab;
+use crate::textobj::{self, TextObj};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VisualKind {
+    Char,  // v
+    Line,  // V
+    Block, // Ctrl-V
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseOp {
+    Toggle, // ~
+    Lower,  // u
+    Upper,  // U
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VisualCmd {
+    Move(Motion),
+    Object(TextObj),
+    SwapEnds { horizontal: bool }, // o, O
+    Switch(VisualKind),
+    Operate(Operator, bool), // bool forces whole lines (X, D, Y, C, S, R)
+    Case(CaseOp),
+    BlockInsert { append: bool }, // I, A
+    Filter,                       // !
+    CommandLine,                  // :
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VisualInput {
+    pub reg: Option<char>,
+    pub count: Option<usize>,
+    pub cmd: VisualCmd,
+}
+
+// Text typed after blockwise I/A/c, copied to the other lines on Esc
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockInsert {
+    pub first: usize,
+    pub last: usize,
+    pub col: usize,
+    pub append: bool,
+}
+
+pub fn parse_visual(keys: &str) -> Parsed<VisualInput> {
+    let (reg, count, rest) = match normal::parse_register(keys) {
+        Parsed::Done(r) => r,
+        Parsed::Pending => return Parsed::Pending,
+        Parsed::Invalid => return Parsed::Invalid,
+    };
+    let mut chars = rest.chars();
+    let first = match chars.next() {
+        Some(c) => c,
+        None => return Parsed::Pending,
+    };
+    let cmd = match first {
+        'i' | 'a' => match chars.next() {
+            None => return Parsed::Pending,
+            Some(ch) => match textobj::parse_obj_kind(ch) {
+                Some(kind) => VisualCmd::Object(TextObj {
+                    inner: first == 'i',
+                    kind,
+                }),
+                None => return Parsed::Invalid,
+            },
+        },
+        'o' => VisualCmd::SwapEnds { horizontal: false },
+        'O' => VisualCmd::SwapEnds { horizontal: true },
+        'v' => VisualCmd::Switch(VisualKind::Char),
+        'V' => VisualCmd::Switch(VisualKind::Line),
+        'd' | 'x' => VisualCmd::Operate(Operator::Delete, false),
+        'X' | 'D' => VisualCmd::Operate(Operator::Delete, true),
+        'y' => VisualCmd::Operate(Operator::Yank, false),
+        'Y' => VisualCmd::Operate(Operator::Yank, true),
+        'c' | 's' => VisualCmd::Operate(Operator::Change, false),
+        'C' | 'S' | 'R' => VisualCmd::Operate(Operator::Change, true),
+        '>' => VisualCmd::Operate(Operator::Indent, true),
+        '<' => VisualCmd::Operate(Operator::Dedent, true),
+        '~' => VisualCmd::Case(CaseOp::Toggle),
+        'u' => VisualCmd::Case(CaseOp::Lower),
+        'U' => VisualCmd::Case(CaseOp::Upper),
+        'I' => VisualCmd::BlockInsert { append: false },
+        'A' => VisualCmd::BlockInsert { append: true },
+        '!' => VisualCmd::Filter,
+        ':' => VisualCmd::CommandLine,
+        _ => match normal::parse_motion(rest) {
+            Parsed::Done(m) => VisualCmd::Move(m),
+            Parsed::Pending => return Parsed::Pending,
+            Parsed::Invalid => return Parsed::Invalid,
+        },
+    };
+    Parsed::Done(VisualInput { reg, count, cmd })
+}
+
+fn map_case(text: &str, op: CaseOp) -> String {
+    match op {
+        CaseOp::Lower => text.to_lowercase(),
+        CaseOp::Upper => text.to_uppercase(),
+        CaseOp::Toggle => text
+            .chars()
+            .map(|c| match c.is_uppercase() {
+                true => c.to_lowercase().to_string(),
+                false => c.to_uppercase().to_string(),
+            })
+            .collect(),
+    }
+}
+
+impl App {
+    pub fn enter_visual(&mut self, kind: VisualKind) {
+        self.seal_edit();
+        self.visual_anchor = self.tabs[self.cur_tab].cursor_xy;
+        self.mode = Mode::Visual(kind);
+    }
+
+    // Back to normal mode, remembering selection in '< and '> marks
+    pub fn exit_visual(&mut self) {
+        if let Mode::Visual(kind) = self.mode {
+            let (start, end) = match kind {
+                VisualKind::Block => {
+                    let (first, last, c1, c2) = self.block_bounds();
+                    ((c1, first), (c2, last))
+                }
+                _ => self.visual_bounds(),
+            };
+            let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+            cur_tab.marks.insert('<', start);
+            cur_tab.marks.insert('>', end);
+        }
+        self.mode = Mode::Normal;
+        self.normal_keys.clear();
+        self.clamp_normal_cursor();
+    }
+
+    // Anchor and cursor ordered by position, as (x, line)
+    pub fn visual_bounds(&self) -> ((usize, usize), (usize, usize)) {
+        let cursor = self.tabs[self.cur_tab].cursor_xy;
+        let anchor = self.visual_anchor;
+        if swap(anchor) <= swap(cursor) {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        }
+    }
+
+    // first line, last line, first col, last col (inclusive)
+    fn block_bounds(&self) -> (usize, usize, usize, usize) {
+        let (cx, cl) = self.tabs[self.cur_tab].cursor_xy;
+        let (ax, al) = self.visual_anchor;
+        (cl.min(al), cl.max(al), cx.min(ax), cx.max(ax))
+    }
+
+    // Selected chars of the line as [from, to).
+    // `to` past the line length means the newline is selected too.
+    pub fn visual_cols(&self, line: usize) -> Option<(usize, usize)> {
+        let kind = match self.mode {
+            Mode::Visual(k) => k,
+            _ => return None,
+        };
+        let (start, end) = self.visual_bounds();
+        if line < start.1 || line > end.1 {
+            return None;
+        }
+        let buf = &self.tabs[self.cur_tab].buf;
+        let len = buf.line_len(line);
+        match kind {
+            VisualKind::Line => Some((0, len + 1)),
+            VisualKind::Char => {
+                let from = if line == start.1 { start.0 } else { 0 };
+                let to = match line == end.1 && end.0 < len {
+                    true => buf.next_grapheme(line, end.0, 1),
+                    false => len + 1,
+                };
+                Some((from, to.min(len + 1)))
+            }
+            VisualKind::Block => {
+                let (_, _, c1, c2) = self.block_bounds();
+                Some((c1, c2 + 1))
+            }
+        }
+    }
+
+    // Charwise or linewise selection as operator range
+    fn visual_op_range(&self, kind: VisualKind) -> OpRange {
+        let (start, end) = self.visual_bounds();
+        if kind == VisualKind::Line {
+            return OpRange::lines(start.1, end.1);
+        }
+        let buf = &self.tabs[self.cur_tab].buf;
+        let end_excl = if end.0 < buf.line_len(end.1) {
+            (buf.next_grapheme(end.1, end.0, 1), end.1)
+        } else if end.1 < buf.last_line() {
+            (0, end.1 + 1)
+        } else {
+            (buf.line_len(end.1), end.1)
+        };
+        OpRange::chars(start, end_excl)
+    }
+
+    pub fn handle_visual_key(&mut self, key: KeyEvent) {
+        let kind = match self.mode {
+            Mode::Visual(k) => k,
+            _ => return,
+        };
+        match key.code {
+            KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.normal_keys.clear();
+                self.run_visual_cmd(
+                    kind,
+                    VisualInput {
+                        reg: None,
+                        count: None,
+                        cmd: VisualCmd::Switch(VisualKind::Block),
+                    },
+                );
+            }
+            KeyCode::Char('d') | KeyCode::Char('u')
+                if key.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                self.normal_keys.clear();
+                self.scroll_half_page(key.code == KeyCode::Char('d'));
+            }
+            KeyCode::Char(ch) => {
+                self.normal_keys.push(ch);
+                let keys = self.normal_keys.clone();
+                match parse_visual(&keys) {
+                    Parsed::Pending => {}
+                    Parsed::Invalid => self.normal_keys.clear(),
+                    Parsed::Done(input) => {
+                        self.normal_keys.clear();
+                        self.run_visual_cmd(kind, input);
+                    }
+                }
+            }
+            KeyCode::Esc => self.exit_visual(),
+            KeyCode::Left | KeyCode::Backspace => self.do_motion(Motion::Left, None),
+            KeyCode::Right => self.do_motion(Motion::Right, None),
+            KeyCode::Up => self.do_motion(Motion::Up, None),
+            KeyCode::Down => self.do_motion(Motion::Down, None),
+            _ => {}
+        }
+    }
+
+    fn run_visual_cmd(&mut self, kind: VisualKind, input: VisualInput) {
+        match input.cmd {
+            VisualCmd::Move(m) => self.do_motion(m, input.count),
+            VisualCmd::Object(obj) => self.visual_select_object(obj),
+            VisualCmd::SwapEnds { horizontal } => {
+                let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+                if horizontal && kind == VisualKind::Block {
+                    std::mem::swap(&mut cur_tab.cursor_xy.0, &mut self.visual_anchor.0);
+                } else {
+                    std::mem::swap(&mut cur_tab.cursor_xy, &mut self.visual_anchor);
+                }
+            }
+            VisualCmd::Switch(new_kind) => match new_kind == kind {
+                true => self.exit_visual(),
+                false => self.mode = Mode::Visual(new_kind),
+            },
+            VisualCmd::Operate(op, whole_lines) => {
+                let kind = if whole_lines { VisualKind::Line } else { kind };
+                if kind == VisualKind::Block {
+                    self.block_operate(op, input.reg);
+                    return;
+                }
+                let range = self.visual_op_range(kind);
+                self.exit_visual();
+                match op {
+                    Operator::Indent | Operator::Dedent => {
+                        let times = input.count.unwrap_or(1).max(1);
+                        self.shift_lines(range.start.1, range.end.1, op == Operator::Indent, times);
+                    }
+                    _ => self.apply_operator(op, range, input.reg),
+                }
+            }
+            VisualCmd::Case(op) => self.visual_change_case(kind, op),
+            VisualCmd::BlockInsert { append } => {
+                if kind != VisualKind::Block {
+                    return;
+                }
+                let (first, last, c1, c2) = self.block_bounds();
+                self.exit_visual();
+                let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+                let col = match append {
+                    true => (c2 + 1).min(cur_tab.buf.line_len(first)),
+                    false => c1.min(cur_tab.buf.line_len(first)),
+                };
+                cur_tab.cursor_xy = (col, first);
+                self.block_insert = Some(BlockInsert {
+                    first,
+                    last,
+                    col,
+                    append,
+                });
+                self.mode = Mode::Insert;
+            }
+            VisualCmd::Filter | VisualCmd::CommandLine => {
+                self.exit_visual();
+                self.mode = Mode::Command;
+                self.status_message = false;
+                self.command_buf.clear();
+                match input.cmd {
+                    VisualCmd::Filter => self.command_buf.push_str("!filter "),
+                    // command works on selected lines by default, like in vim
+                    _ => self.command_buf.push_str("'<,'>"),
+                }
+                self.cursor_pos_xy.0 = self.command_buf.chars().count() as u16;
+            }
+        }
+    }
+
+    fn visual_select_object(&mut self, obj: TextObj) {
+        let cur_tab: &Tab = &self.tabs[self.cur_tab];
+        let range = match textobj::select(&cur_tab.buf, cur_tab.cursor_xy, obj) {
+            Some(r) => r,
+            None => return,
+        };
+        if range.linewise {
+            self.visual_anchor = (0, range.start.1);
+            self.tabs[self.cur_tab].cursor_xy = (0, range.end.1);
+            self.mode = Mode::Visual(VisualKind::Line);
+            return;
+        }
+        let buf = &cur_tab.buf;
+        let end_idx = buf.pos_to_char(range.end.1, range.end.0);
+        let start_idx = buf.pos_to_char(range.start.1, range.start.0);
+        if end_idx <= start_idx {
+            return;
+        }
+        let last = swap(buf.char_to_pos(end_idx - 1));
+        self.visual_anchor = range.start;
+        self.tabs[self.cur_tab].cursor_xy = last;
+        self.mode = Mode::Visual(VisualKind::Char);
+    }
+
+    // d, y, c on a block: same columns cut out of every line
+    fn block_operate(&mut self, op: Operator, reg: Option<char>) {
+        let (first, last, c1, c2) = self.block_bounds();
+        self.exit_visual();
+        if reg == Some('%') && op != Operator::Yank {
+            self.throw_status_message("Register % is read only".to_string());
+            return;
+        }
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let mut pieces: Vec<String> = Vec::new();
+        for line in first..=last {
+            let len = cur_tab.buf.line_len(line);
+            if c1 >= len {
+                pieces.push(String::new());
+                continue;
+            }
+            let to = (c2 + 1).min(len);
+            match op {
+                Operator::Yank => pieces.push(cur_tab.buf.slice(line, c1, line, to)),
+                _ => pieces.push(cur_tab.remove_text(line, c1, line, to, true)),
+            }
+        }
+        cur_tab.cursor_xy = (c1, first);
+        self.store_register(reg, pieces.join("\n"), false, op != Operator::Yank);
+        if op == Operator::Change {
+            let col = c1.min(self.tabs[self.cur_tab].buf.line_len(first));
+            self.tabs[self.cur_tab].cursor_xy.0 = col;
+            self.block_insert = Some(BlockInsert {
+                first,
+                last,
+                col,
+                append: false,
+            });
+            self.mode = Mode::Insert;
+            return;
+        }
+        self.seal_edit();
+        self.clamp_normal_cursor();
+    }
+
+    fn visual_change_case(&mut self, kind: VisualKind, op: CaseOp) {
+        let (start, end) = self.visual_bounds();
+        let segments: Vec<(usize, usize, usize)> = (start.1..=end.1)
+            .filter_map(|line| self.visual_cols(line).map(|(from, to)| (line, from, to)))
+            .collect();
+        let new_cursor = match kind {
+            VisualKind::Block => {
+                let (first, _, c1, _) = self.block_bounds();
+                (c1, first)
+            }
+            _ => start,
+        };
+        self.exit_visual();
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        for (line, from, to) in segments {
+            let to = to.min(cur_tab.buf.line_len(line));
+            if from >= to {
+                continue;
+            }
+            let text = cur_tab.buf.slice(line, from, line, to);
+            let new_text = map_case(&text, op);
+            if new_text != text {
+                cur_tab.remove_text(line, from, line, to, true);
+                cur_tab.insert_text(line, from, &new_text, true);
+            }
+        }
+        cur_tab.cursor_xy = new_cursor;
+        self.seal_edit();
+        self.clamp_normal_cursor();
+    }
+
+    // Copies text typed on the first block line to the rest of them
+    pub fn finish_block_insert(&mut self, bi: BlockInsert) {
+        let cur_tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        let (x, line) = cur_tab.cursor_xy;
+        if line != bi.first || x <= bi.col {
+            return;
+        }
+        let text = cur_tab.buf.slice(line, bi.col, line, x);
+        for l in bi.first + 1..=bi.last.min(cur_tab.buf.last_line()) {
+            let len = cur_tab.buf.line_len(l);
+            if bi.col > len {
+                // short lines get padded for A and skipped for I
+                if !bi.append {
+                    continue;
+                }
+                let pad = " ".repeat(bi.col - len);
+                cur_tab.insert_text(l, len, &(pad + &text), true);
+                continue;
+            }
+            cur_tab.insert_text(l, bi.col, &text, true);
+        }
+    }
+}
@@ -0,0 +1,799 @@
+use ratatui::layout::Rect;
+
+use crate::app::App;
+use crate::grapheme;
+use crate::tabs::Tab;
+use crate::wrap;
+
+// Windows split the text area between them. The layout is a tree: a split puts
+// two parts one above the other (Rows) or side by side (Cols), a leaf is a window.
+// Every window shows a tab, several may show the same one and see its edits live.
+//
+// The focused window shows tabs[cur_tab] and its cursor and viewport are the tab's
+// own cursor_xy, scroll_offset and left_col, so everything working on the current
+// tab works in it. Other windows keep their viewport here and their cursor in
+// Tab::win_cursors, where it follows edits like marks do.
+
+// Rows a window needs: its text and its bar
+const MIN_HEIGHT: u16 = 2;
+const MIN_WIDTH: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitDir {
+    Rows, // one above the other, !win split
+    Cols, // side by side, !win vsplit
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FocusDir {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+impl FocusDir {
+    pub fn from_char(ch: char) -> Option<FocusDir> {
+        match ch {
+            'h' => Some(FocusDir::Left),
+            'j' => Some(FocusDir::Down),
+            'k' => Some(FocusDir::Up),
+            'l' => Some(FocusDir::Right),
+            _ => None,
+        }
+    }
+}
+
+// Ctrl-W commands of normal mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WinCmd {
+    Split(SplitDir),         // s v
+    Focus(FocusDir),         // h j k l
+    Next { back: bool },     // w W, with count goes to window N
+    Close,                   // c q
+    Only,                    // o
+    Resize(SplitDir, isize), // + - > <
+    Equal,                   // =
+}
+
+impl WinCmd {
+    // Char after Ctrl-W
+    pub fn from_char(ch: char) -> Option<WinCmd> {
+        Some(match ch {
+            's' | 'S' => WinCmd::Split(SplitDir::Rows),
+            'v' => WinCmd::Split(SplitDir::Cols),
+            'w' | '\u{17}' => WinCmd::Next { back: false },
+            'W' => WinCmd::Next { back: true },
+            'c' | 'q' => WinCmd::Close,
+            'o' => WinCmd::Only,
+            '+' => WinCmd::Resize(SplitDir::Rows, 1),
+            '-' => WinCmd::Resize(SplitDir::Rows, -1),
+            '>' => WinCmd::Resize(SplitDir::Cols, 1),
+            '<' => WinCmd::Resize(SplitDir::Cols, -1),
+            '=' => WinCmd::Equal,
+            _ => WinCmd::Focus(FocusDir::from_char(ch)?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layout {
+    Win(usize), // window id
+    Split {
+        dir: SplitDir,
+        ratio: f32, // part of the space the first one takes
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+// Splits area for two parts, side by side ones get a separator column between them
+fn split_area(dir: SplitDir, ratio: f32, area: Rect) -> (Rect, Option<Rect>, Rect) {
+    let (total, min) = match dir {
+        SplitDir::Rows => (area.height, MIN_HEIGHT),
+        SplitDir::Cols => (area.width.saturating_sub(1), MIN_WIDTH),
+    };
+    let first = ((total as f32 * ratio).round() as u16)
+        .clamp(min.min(total / 2), total.saturating_sub(min).max(total / 2));
+    match dir {
+        SplitDir::Rows => (
+            Rect::new(area.x, area.y, area.width, first),
+            None,
+            Rect::new(area.x, area.y + first, area.width, total - first),
+        ),
+        SplitDir::Cols => (
+            Rect::new(area.x, area.y, first, area.height),
+            Some(Rect::new(
+                area.x + first,
+                area.y,
+                1.min(area.width),
+                area.height,
+            )),
+            Rect::new(area.x + first + 1, area.y, total - first, area.height),
+        ),
+    }
+}
+
+impl Layout {
+    // Window ids from top left to bottom right
+    pub fn ids(&self) -> Vec<usize> {
+        match self {
+            Layout::Win(id) => vec![*id],
+            Layout::Split { first, second, .. } => {
+                let mut res = first.ids();
+                res.extend(second.ids());
+                res
+            }
+        }
+    }
+
+    fn contains(&self, id: usize) -> bool {
+        match self {
+            Layout::Win(w) => *w == id,
+            Layout::Split { first, second, .. } => first.contains(id) || second.contains(id),
+        }
+    }
+
+    // Areas of windows, and separators of side by side ones
+    pub fn areas(&self, area: Rect, wins: &mut Vec<(usize, Rect)>, seps: &mut Vec<Rect>) {
+        match self {
+            Layout::Win(id) => wins.push((*id, area)),
+            Layout::Split {
+                dir,
+                ratio,
+                first,
+                second,
+            } => {
+                let (a, sep, b) = split_area(*dir, *ratio, area);
+                first.areas(a, wins, seps);
+                seps.extend(sep);
+                second.areas(b, wins, seps);
+            }
+        }
+    }
+
+    // Window id becomes a split of it and new one after it
+    fn split(&mut self, id: usize, dir: SplitDir, new_id: usize) {
+        match self {
+            Layout::Win(w) if *w == id => {
+                *self = Layout::Split {
+                    dir,
+                    ratio: 0.5,
+                    first: Box::new(Layout::Win(id)),
+                    second: Box::new(Layout::Win(new_id)),
+                }
+            }
+            Layout::Win(_) => {}
+            Layout::Split { first, second, .. } => {
+                first.split(id, dir, new_id);
+                second.split(id, dir, new_id);
+            }
+        }
+    }
+
+    // Other part of the split takes place of the window. Returns false if it's the last one.
+    fn remove(&mut self, id: usize) -> bool {
+        let Layout::Split { first, second, .. } = self else {
+            return false;
+        };
+        let rest = match (first.as_ref(), second.as_ref()) {
+            (Layout::Win(w), _) if *w == id => second.as_ref().clone(),
+            (_, Layout::Win(w)) if *w == id => first.as_ref().clone(),
+            _ => return first.remove(id) || second.remove(id),
+        };
+        *self = rest;
+        true
+    }
+
+    // Moves the nearest border along dir of window id by delta cells, growing the window
+    fn resize(&mut self, id: usize, dir: SplitDir, delta: isize, area: Rect) -> bool {
+        let Layout::Split {
+            dir: split_dir,
+            ratio,
+            first,
+            second,
+        } = self
+        else {
+            return false;
+        };
+        let (a, _, b) = split_area(*split_dir, *ratio, area);
+        let in_first = first.contains(id);
+        let inner = match in_first {
+            true => first.resize(id, dir, delta, a),
+            false => second.contains(id) && second.resize(id, dir, delta, b),
+        };
+        if inner || *split_dir != dir || !(in_first || second.contains(id)) {
+            return inner;
+        }
+        let (size, total, min) = match dir {
+            SplitDir::Rows => (a.height, a.height + b.height, MIN_HEIGHT),
+            SplitDir::Cols => (a.width, a.width + b.width, MIN_WIDTH),
+        };
+        if total < min * 2 {
+            return true;
+        }
+        let delta = match in_first {
+            true => delta,
+            false => -delta,
+        };
+        let size = (size as isize + delta).clamp(min as isize, (total - min) as isize);
+        *ratio = size as f32 / total as f32;
+        true
+    }
+
+    // Windows in a row along dir, for giving them equal sizes
+    fn slots(&self, dir: SplitDir) -> usize {
+        match self {
+            Layout::Split {
+                dir: d,
+                first,
+                second,
+                ..
+            } if *d == dir => first.slots(dir) + second.slots(dir),
+            _ => 1,
+        }
+    }
+
+    fn equalize(&mut self) {
+        if let Layout::Split {
+            dir,
+            ratio,
+            first,
+            second,
+        } = self
+        {
+            let (a, b) = (first.slots(*dir), second.slots(*dir));
+            *ratio = a as f32 / (a + b) as f32;
+            first.equalize();
+            second.equalize();
+        }
+    }
+
+    // "v0.500 w h0.300 w w": splits in prefix order, w takes the next window
+    pub fn to_text(&self) -> String {
+        match self {
+            Layout::Win(_) => "w".to_string(),
+            Layout::Split {
+                dir,
+                ratio,
+                first,
+                second,
+            } => {
+                let d = match dir {
+                    SplitDir::Rows => 'h',
+                    SplitDir::Cols => 'v',
+                };
+                format!("{}{:.3} {} {}", d, ratio, first.to_text(), second.to_text())
+            }
+        }
+    }
+
+    // Leaves get ids from next_id on, in order
+    pub fn parse<'a>(
+        words: &mut impl Iterator<Item = &'a str>,
+        next_id: &mut usize,
+    ) -> Option<Layout> {
+        let word = words.next()?;
+        if word == "w" {
+            *next_id += 1;
+            return Some(Layout::Win(*next_id - 1));
+        }
+        let dir = match word.chars().next()? {
+            'h' => SplitDir::Rows,
+            'v' => SplitDir::Cols,
+            _ => return None,
+        };
+        let ratio: f32 = word[1..].parse().ok()?;
+        Some(Layout::Split {
+            dir,
+            ratio: ratio.clamp(0.0, 1.0),
+            first: Box::new(Layout::parse(words, next_id)?),
+            second: Box::new(Layout::parse(words, next_id)?),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub id: usize,
+    pub tab_id: usize,
+    pub scroll_offset: usize, // viewport while not focused
+    pub left_col: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Windows {
+    pub layout: Layout,
+    pub list: Vec<Window>,
+    pub focus: usize, // id of focused window
+    next_id: usize,
+}
+
+impl Windows {
+    pub fn new(tab_id: usize) -> Windows {
+        Windows {
+            layout: Layout::Win(0),
+            list: vec![Window {
+                id: 0,
+                tab_id,
+                scroll_offset: 0,
+                left_col: 0,
+            }],
+            focus: 0,
+            next_id: 1,
+        }
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Window> {
+        self.list.iter().find(|w| w.id == id)
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Window> {
+        self.list.iter_mut().find(|w| w.id == id)
+    }
+
+    pub fn is_split(&self) -> bool {
+        self.list.len() > 1
+    }
+
+    // Windows of a loaded session, in layout order; layout that doesn't fit them is dropped
+    pub fn from_parts(layout_text: &str, wins: Vec<Window>, focus: usize) -> Option<Windows> {
+        let mut next_id = 0;
+        let layout = Layout::parse(&mut layout_text.split_whitespace(), &mut next_id)?;
+        if next_id != wins.len() || wins.is_empty() {
+            return None;
+        }
+        let list = wins
+            .into_iter()
+            .enumerate()
+            .map(|(id, w)| Window { id, ..w })
+            .collect();
+        Some(Windows {
+            layout,
+            list,
+            focus: focus.min(next_id - 1),
+            next_id,
+        })
+    }
+}
+
+// What a shown window needs for drawing
+#[derive(Debug, Clone)]
+pub struct WinArea {
+    pub tab: usize,             // index into tabs
+    pub cursor: (usize, usize), // (x, line)
+    pub scroll: (usize, usize), // scroll_offset, left_col
+    pub text: Rect,
+    pub bar: Option<Rect>, // name line under the text when there are several windows
+    pub focused: bool,
+}
+
+impl App {
+    // Space under title and tab bar, above status line; same math as in draw
+    pub fn main_area(&self, screen: Rect) -> Rect {
+        let title_len = match self.tabs[self.cur_tab].filename.is_empty() {
+            true => 2,
+            false => 3,
+        };
+        Rect::new(
+            screen.x,
+            screen.y + title_len.min(screen.height),
+            screen.width,
+            screen.height.saturating_sub(title_len + 1),
+        )
+    }
+
+    pub fn screen_main_area(&self) -> Rect {
+        // tests don't depend on the terminal they run in
+        let (cols, rows) = match cfg!(test) {
+            true => (80, 24),
+            false => crossterm::terminal::size().unwrap_or((80, 24)),
+        };
+        self.main_area(Rect::new(0, 0, cols, rows))
+    }
+
+    fn tab_index(&self, id: usize) -> Option<usize> {
+        self.tabs.iter().position(|t| t.id == id)
+    }
+
+    // Shown windows with their tabs and views, plus separators between side by side ones
+    pub fn win_areas(&self, main: Rect) -> (Vec<WinArea>, Vec<Rect>) {
+        let mut areas: Vec<(usize, Rect)> = Vec::new();
+        let mut seps: Vec<Rect> = Vec::new();
+        self.windows.layout.areas(main, &mut areas, &mut seps);
+        let split = self.windows.is_split();
+        let res = areas
+            .into_iter()
+            .filter_map(|(id, area)| {
+                let win = self.windows.get(id)?;
+                let focused = id == self.windows.focus;
+                let (tab, cursor, scroll) = match focused {
+                    true => {
+                        let t = &self.tabs[self.cur_tab];
+                        (self.cur_tab, t.cursor_xy, (t.scroll_offset, t.left_col))
+                    }
+                    false => {
+                        let idx = self.tab_index(win.tab_id).unwrap_or(self.cur_tab);
+                        let t = &self.tabs[idx];
+                        let last = t.buf.last_line();
+                        let (x, line) = t.win_cursors.get(&id).copied().unwrap_or(t.cursor_xy);
+                        let line = line.min(last);
+                        let x = x.min(t.buf.line_len(line));
+                        (idx, (x, line), (win.scroll_offset.min(last), win.left_col))
+                    }
+                };
+                let (text, bar) = match split && area.height > 0 {
+                    true => (
+                        Rect::new(area.x, area.y, area.width, area.height - 1),
+                        Some(Rect::new(area.x, area.y + area.height - 1, area.width, 1)),
+                    ),
+                    false => (area, None),
+                };
+                Some(WinArea {
+                    tab,
+                    cursor,
+                    scroll,
+                    text,
+                    bar,
+                    focused,
+                })
+            })
+            .collect();
+        (res, seps)
+    }
+
+    // Text area of focused window on the terminal
+    pub fn focused_text_area(&self) -> Rect {
+        let main = self.screen_main_area();
+        self.win_areas(main)
+            .0
+            .into_iter()
+            .find(|w| w.focused)
+            .map(|w| w.text)
+            .unwrap_or(main)
+    }
+
+    // Focused window follows cur_tab when tabs are switched; windows of closed
+    // tabs show the current one
+    pub fn update_windows(&mut self) {
+        let cur_id = self.tabs[self.cur_tab].id;
+        let focus = self.windows.focus;
+        let ids: Vec<usize> = self.tabs.iter().map(|t| t.id).collect();
+        for win in self.windows.list.iter_mut() {
+            if win.id == focus || !ids.contains(&win.tab_id) {
+                win.tab_id = cur_id;
+            }
+        }
+    }
+
+    // Focused view goes back into its window, cursor into the tab's win_cursors
+    fn store_focused_view(&mut self) {
+        self.update_windows();
+        let focus = self.windows.focus;
+        let tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        tab.win_cursors.insert(focus, tab.cursor_xy);
+        let (scroll, left_col) = (tab.scroll_offset, tab.left_col);
+        if let Some(win) = self.windows.get_mut(focus) {
+            win.scroll_offset = scroll;
+            win.left_col = left_col;
+        }
+    }
+
+    // Window's tab becomes current one and gets the window's view
+    fn load_focused_view(&mut self, id: usize) {
+        let Some(win) = self.windows.get(id).cloned() else {
+            return;
+        };
+        self.windows.focus = id;
+        self.cur_tab = self.tab_index(win.tab_id).unwrap_or(self.cur_tab);
+        let tab: &mut Tab = &mut self.tabs[self.cur_tab];
+        if let Some(cursor) = tab.win_cursors.remove(&id) {
+            tab.cursor_xy = cursor;
+            tab.scroll_offset = win.scroll_offset;
+            tab.left_col = win.left_col;
+        }
+        self.clamp_normal_cursor();
+        self.scroll_to_cursor();
+    }
+
+    pub fn focus_window(&mut self, id: usize) {
+        if id == self.windows.focus || self.windows.get(id).is_none() {
+            return;
+        }
+        self.seal_edit();
+        self.store_focused_view();
+        self.load_focused_view(id);
+    }
+
+    // New window next to focused one, showing tab (current one if None); it gets focus
+    pub fn split_window(&mut self, dir: SplitDir, tab: Option<usize>) -> Result<(), String> {
+        if tab.is_some_and(|t| t >= self.tabs.len()) {
+            return Err("Tab with specified indice not opened".to_string());
+        }
+        let area = self.focused_text_area();
+        let (size, min) = match (dir, self.windows.is_split()) {
+            (SplitDir::Rows, true) => (area.height + 1, MIN_HEIGHT * 2),
+            (SplitDir::Rows, false) => (area.height, MIN_HEIGHT * 2),
+            (SplitDir::Cols, _) => (area.width, MIN_WIDTH * 2 + 1),
+        };
+        if size < min {
+            return Err("Not enough room for another window".to_string());
+        }
+        self.seal_edit();
+        self.store_focused_view();
+        let old = self.windows.focus;
+        let new_id = self.windows.next_id;
+        self.windows.next_id += 1;
+        let tab = tab.unwrap_or(self.cur_tab);
+        let cur: &Tab = &self.tabs[self.cur_tab];
+        let view = match tab == self.cur_tab {
+            true => (cur.cursor_xy, cur.scroll_offset, cur.left_col),
+            false => {
+                let t = &self.tabs[tab];
+                (t.cursor_xy, t.scroll_offset, t.left_col)
+            }
+        };
+        self.windows.list.push(Window {
+            id: new_id,
+            tab_id: self.tabs[tab].id,
+            scroll_offset: view.1,
+            left_col: view.2,
+        });
+        self.tabs[tab].win_cursors.insert(new_id, view.0);
+        self.windows.layout.split(old, dir, new_id);
+        self.load_focused_view(new_id);
+        Ok(())
+    }
+
+    // Closes focused window, the one taking its place gets focus
+    pub fn close_window(&mut self) -> Result<(), String> {
+        let id = self.windows.focus;
+        let order = self.windows.layout.ids();
+        if !self.windows.layout.remove(id) {
+            return Err("Can't close last window".to_string());
+        }
+        self.seal_edit();
+        self.windows.list.retain(|w| w.id != id);
+        let pos = order.iter().position(|w| *w == id).unwrap_or(0);
+        let next = self.windows.layout.ids();
+        let next = next[pos.saturating_sub(1).min(next.len() - 1)];
+        self.load_focused_view(next);
+        Ok(())
+    }
+
+    // Closes all windows but the focused one
+    pub fn only_window(&mut self) {
+        let focus = self.windows.focus;
+        for tab in self.tabs.iter_mut() {
+            tab.win_cursors.clear();
+        }
+        self.windows.list.retain(|w| w.id == focus);
+        self.windows.layout = Layout::Win(focus);
+    }
+
+    // Window count places away in layout order, or window number n (1 based)
+    pub fn focus_next_window(&mut self, count: Option<usize>, back: bool) {
+        let ids = self.windows.layout.ids();
+        let pos = ids
+            .iter()
+            .position(|w| *w == self.windows.focus)
+            .unwrap_or(0);
+        let target = match count {
+            Some(n) => n.clamp(1, ids.len()) - 1,
+            None if back => (pos + ids.len() - 1) % ids.len(),
+            None => (pos + 1) % ids.len(),
+        };
+        self.focus_window(ids[target]);
+    }
+
+    // Nearest window on that side of focused one; among several the one next to the cursor
+    pub fn focus_window_dir(&mut self, dir: FocusDir, count: usize) {
+        for _ in 0..count.max(1) {
+            let main = self.screen_main_area();
+            let mut areas: Vec<(usize, Rect)> = Vec::new();
+            self.windows.layout.areas(main, &mut areas, &mut Vec::new());
+            let Some(&(_, cur)) = areas.iter().find(|(id, _)| *id == self.windows.focus) else {
+                return;
+            };
+            let tab = &self.tabs[self.cur_tab];
+            let (x, line) = tab.cursor_xy;
+            let row = cur.y + (line.saturating_sub(tab.scroll_offset) as u16).min(cur.height);
+            // screen column of the cursor, as draw puts it
+            let text = tab.buf.line(line);
+            let tabstop = self.tab_tabstop(tab);
+            let gutter = wrap::gutter_width(line, line, self.tab_numbers(tab));
+            let cell = match self.tab_wraps(tab) {
+                true => {
+                    let width = (cur.width as usize).saturating_sub(gutter);
+                    wrap::cursor_cell(&text, x, width, tabstop).1
+                }
+                false => grapheme::display_col(&text, x, tabstop).saturating_sub(tab.left_col),
+            };
+            let col = cur.x + ((gutter + cell) as u16).min(cur.width);
+            // distance to window on that side, None if it's not beside this one
+            let beside = |r: &Rect| -> Option<(u16, bool)> {
+                let rows = r.y < cur.y + cur.height && cur.y < r.y + r.height;
+                let cols = r.x < cur.x + cur.width && cur.x < r.x + r.width;
+                let at_row = r.y <= row && row < r.y + r.height;
+                let at_col = r.x <= col && col < r.x + r.width;
+                match dir {
+                    FocusDir::Left if rows && r.x + r.width <= cur.x => {
+                        Some((cur.x - r.x - r.width, !at_row))
+                    }
+                    FocusDir::Right if rows && r.x >= cur.x + cur.width => {
+                        Some((r.x - cur.x - cur.width, !at_row))
+                    }
+                    FocusDir::Up if cols && r.y + r.height <= cur.y => {
+                        Some((cur.y - r.y - r.height, !at_col))
+                    }
+                    FocusDir::Down if cols && r.y >= cur.y + cur.height => {
+                        Some((r.y - cur.y - cur.height, !at_col))
+                    }
+                    _ => None,
+                }
+            };
+            let Some(&(id, _)) = areas
+                .iter()
+                .filter_map(|a| beside(&a.1).map(|d| (a, d)))
+                .min_by_key(|(_, d)| *d)
+                .map(|(a, _)| a)
+            else {
+                return;
+            };
+            self.focus_window(id);
+        }
+    }
+
+    // Grows (or shrinks with negative delta) focused window by cells along dir
+    pub fn resize_window(&mut self, dir: SplitDir, delta: isize) {
+        let main = self.screen_main_area();
+        let focus = self.windows.focus;
+        self.windows.layout.resize(focus, dir, delta, main);
+        self.scroll_to_cursor();
+    }
+
+    // Sets size of focused window along dir
+    pub fn set_window_size(&mut self, dir: SplitDir, size: usize) {
+        let area = self.focused_text_area();
+        let cur = match dir {
+            SplitDir::Rows => area.height,
+            SplitDir::Cols => area.width,
+        };
+        self.resize_window(dir, size as isize - cur as isize);
+    }
+
+    pub fn equalize_windows(&mut self) {
+        self.windows.layout.equalize();
+        self.scroll_to_cursor();
+    }
+
+    pub fn run_win_cmd(&mut self, cmd: WinCmd, count: Option<usize>) {
+        match cmd {
+            WinCmd::Split(dir) => {
+                if let Err(e) = self.split_window(dir, None) {
+                    self.throw_status_error(e);
+                }
+            }
+            WinCmd::Focus(dir) => self.focus_window_dir(dir, count.unwrap_or(1)),
+            WinCmd::Next { back } => self.focus_next_window(count, back),
+            WinCmd::Close => {
+                if let Err(e) = self.close_window() {
+                    self.throw_status_error(e);
+                }
+            }
+            WinCmd::Only => self.only_window(),
+            WinCmd::Resize(dir, sign) => {
+                self.resize_window(dir, sign * count.unwrap_or(1) as isize)
+            }
+            WinCmd::Equal => self.equalize_windows(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Option<(Layout, usize)> {
+        let mut next_id = 0;
+        let layout = Layout::parse(&mut text.split_whitespace(), &mut next_id)?;
+        Some((layout, next_id))
+    }
+
+    fn win(tab_id: usize) -> Window {
+        Window {
+            id: 0,
+            tab_id,
+            scroll_offset: 0,
+            left_col: 0,
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        for text in [
+            "w",
+            "v0.500 w w",
+            "v0.500 w h0.300 w w",
+            "h0.250 v0.600 w w w",
+        ] {
+            let (layout, count) = parse(text).unwrap();
+            assert_eq!(layout.to_text(), text);
+            assert_eq!(layout.ids(), (0..count).collect::<Vec<usize>>());
+        }
+        // ratio is kept in 0..1
+        assert_eq!(parse("h1.500 w w").unwrap().0.to_text(), "h1.000 w w");
+        assert!(parse("").is_none());
+        assert!(parse("v0.5 w").is_none());
+        assert!(parse("x0.5 w w").is_none());
+        assert!(parse("vx w w").is_none());
+    }
+
+    #[test]
+    fn remove() {
+        let (mut layout, _) = parse("v0.500 w h0.300 w w").unwrap();
+        assert!(layout.remove(1));
+        assert_eq!(layout.to_text(), "v0.500 w w");
+        assert_eq!(layout.ids(), [0, 2]);
+        // last window of the split gives its place to the other one
+        assert!(layout.remove(0));
+        assert_eq!(layout, Layout::Win(2));
+        assert!(!layout.remove(2));
+        assert_eq!(layout, Layout::Win(2));
+    }
+
+    #[test]
+    fn resize() {
+        let area = Rect::new(0, 0, 81, 20);
+        let (mut layout, _) = parse("v0.500 w h0.500 w w").unwrap();
+        assert!(layout.resize(0, SplitDir::Cols, 10, area));
+        assert_eq!(layout.to_text(), "v0.625 w h0.500 w w");
+        // second window grows by moving the border the other way
+        assert!(layout.resize(2, SplitDir::Cols, 20, area));
+        assert_eq!(layout.to_text(), "v0.375 w h0.500 w w");
+        assert!(layout.resize(2, SplitDir::Rows, 5, area));
+        assert_eq!(layout.to_text(), "v0.375 w h0.250 w w");
+        // no room under min sizes, and no split along that dir
+        assert!(layout.resize(0, SplitDir::Cols, -100, area));
+        assert_eq!(
+            layout.to_text(),
+            format!("v{:.3} w h0.250 w w", MIN_WIDTH as f32 / 80.0)
+        );
+        assert!(!Layout::Win(0).resize(0, SplitDir::Cols, 5, area));
+        let (mut rows, _) = parse("h0.500 w w").unwrap();
+        assert!(!rows.resize(0, SplitDir::Cols, 5, area));
+    }
+
+    #[test]
+    fn from_parts() {
+        let wins = Windows::from_parts("v0.500 w w", vec![win(3), win(4)], 5).unwrap();
+        assert_eq!(
+            wins.list
+                .iter()
+                .map(|w| (w.id, w.tab_id))
+                .collect::<Vec<_>>(),
+            [(0, 3), (1, 4)]
+        );
+        assert_eq!(wins.focus, 1);
+        assert!(Windows::from_parts("v0.500 w w", vec![win(0)], 0).is_none());
+        assert!(Windows::from_parts("w", vec![win(0), win(1)], 0).is_none());
+        assert!(Windows::from_parts("w", vec![], 0).is_none());
+        assert!(Windows::from_parts("bad", vec![win(0)], 0).is_none());
+    }
+
+    #[test]
+    fn focus_down_by_display_column() {
+        let mut app = App::new_test();
+        // cell 48 with the gutter is in the right half of the screen, char x 12 isn't
+        let x = 12;
+        app.tabs[0].str_into_buf("\t".repeat(x + 1));
+        app.split_window(SplitDir::Rows, None).unwrap();
+        app.split_window(SplitDir::Cols, None).unwrap();
+        app.focus_window(0);
+        app.tabs[app.cur_tab].cursor_xy = (x, 0);
+        app.focus_window_dir(FocusDir::Down, 1);
+        assert_eq!(app.windows.focus, 2);
+        app.focus_window_dir(FocusDir::Left, 1);
+        assert_eq!(app.windows.focus, 1);
+    }
+}
@@ -0,0 +1,166 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::grapheme;
+
+// Drawn at the start of continuation rows of a wrapped line
+pub const SHOWBREAK: &str = "↪ ";
+
+// How lines are numbered: relative ones count from the cursor line,
+// hybrid shows the real number on the cursor line itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineNumbers {
+    Absolute,
+    Relative,
+    Hybrid,
+    Off,
+}
+
+impl LineNumbers {
+    pub fn from_name(name: &str) -> Option<LineNumbers> {
+        match name {
+            "absolute" => Some(LineNumbers::Absolute),
+            "relative" => Some(LineNumbers::Relative),
+            "hybrid" => Some(LineNumbers::Hybrid),
+            "off" => Some(LineNumbers::Off),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineNumbers::Absolute => "absolute",
+            LineNumbers::Relative => "relative",
+            LineNumbers::Hybrid => "hybrid",
+            LineNumbers::Off => "off",
+        }
+    }
+}
+
+// Line number column: "12: ", empty when numbers are off
+pub fn gutter(line: usize, cursor_line: usize, style: LineNumbers) -> String {
+    let num = match style {
+        LineNumbers::Off => return String::new(),
+        LineNumbers::Absolute => line + 1,
+        LineNumbers::Hybrid if line == cursor_line => line + 1,
+        LineNumbers::Relative | LineNumbers::Hybrid => line.abs_diff(cursor_line),
+    };
+    format!("{}: ", num)
+}
+
+pub fn gutter_width(line: usize, cursor_line: usize, style: LineNumbers) -> usize {
+    gutter(line, cursor_line, style).len()
+}
+
+// How a buffer line is cut into screen rows in wrap mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineRows {
+    pub starts: Vec<usize>, // char x where every row begins, first one is 0
+    pub indent: usize,      // cells before text on continuation rows, SHOWBREAK included
+}
+
+impl LineRows {
+    // Row holding char x
+    pub fn row_of(&self, x: usize) -> usize {
+        self.starts.iter().rposition(|&s| s <= x).unwrap_or(0)
+    }
+}
+
+// Continuation rows keep indentation of the line, unless it eats
+// more than half of the width
+fn cont_indent(text: &str, width: usize, tabstop: usize) -> usize {
+    let lead = text.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+    let indent = grapheme::display_col(text, lead, tabstop) + SHOWBREAK.width();
+    match indent * 2 > width {
+        true => SHOWBREAK.width().min(width.saturating_sub(1)),
+        false => indent,
+    }
+}
+
+// Splits line into rows `width` cells wide. Clusters are never cut, a wide char
+// not fitting at row end goes to the next row.
+pub fn line_rows(text: &str, width: usize, tabstop: usize) -> LineRows {
+    let width = width.max(1);
+    let indent = cont_indent(text, width, tabstop);
+    let mut starts: Vec<usize> = vec![0];
+    let (mut x, mut col, mut used) = (0, 0, 0);
+    for g in text.graphemes(true) {
+        let w = grapheme::cell_width(g, col, tabstop);
+        let room = match starts.len() {
+            1 => width,
+            _ => (width - indent).max(1),
+        };
+        if used > 0 && used + w > room {
+            starts.push(x);
+            used = 0;
+        }
+        used += w;
+        col += w;
+        x += g.chars().count();
+    }
+    LineRows { starts, indent }
+}
+
+// Row of the line and cell in it where char x is drawn (wrap mode)
+pub fn cursor_cell(text: &str, x: usize, width: usize, tabstop: usize) -> (usize, usize) {
+    let rows = line_rows(text, width, tabstop);
+    let row = rows.row_of(x);
+    let start = rows.starts[row];
+    let cell =
+        grapheme::display_col(text, x, tabstop) - grapheme::display_col(text, start, tabstop);
+    match row {
+        0 => (0, cell),
+        _ => (row, rows.indent + cell),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(text: &str, width: usize) -> (Vec<usize>, usize) {
+        let rows = line_rows(text, width, 4);
+        (rows.starts, rows.indent)
+    }
+
+    #[test]
+    fn showbreak_padding() {
+        // continuation rows get the line's indent plus showbreak
+        assert_eq!(rows("  abcdefghij", 8), (vec![0, 8], 4));
+        assert_eq!(cursor_cell("  abcdefghij", 9, 8, 4), (1, 5));
+        assert_eq!(cursor_cell("  abcdefghij", 3, 8, 4), (0, 3));
+        assert_eq!(rows("short", 8), (vec![0], 2));
+    }
+
+    #[test]
+    fn indent_wider_than_half() {
+        // 8 spaces and showbreak would eat the row, only showbreak is left
+        assert_eq!(rows("        abcdefgh", 10), (vec![0, 10], 2));
+        assert_eq!(rows("\tab", 5), (vec![0, 2], 2));
+        assert_eq!(cursor_cell("\tab", 2, 5, 4), (1, 2));
+    }
+
+    #[test]
+    fn narrow_widths() {
+        // no room for showbreak, draw keeps only the indent then
+        assert_eq!(rows("abc", 2), (vec![0, 2], 1));
+        assert!(line_rows("abc", 2, 4).indent < SHOWBREAK.width());
+        assert_eq!(rows("abc", 1), (vec![0, 1, 2], 0));
+        assert_eq!(rows("abc", 0), (vec![0, 1, 2], 0));
+        assert_eq!(cursor_cell("abc", 2, 1, 4), (2, 0));
+        assert_eq!(cursor_cell("abc", 3, 1, 4), (2, 1));
+        // wide char never gets cut, even if it doesn't fit at all
+        assert_eq!(rows("日本", 1), (vec![0, 1], 0));
+        assert_eq!(rows("a日", 2), (vec![0, 1], 1));
+        assert_eq!(rows("", 1), (vec![0], 0));
+    }
+
+    #[test]
+    fn row_of() {
+        let rows = line_rows("abcdefghij", 4, 4);
+        assert_eq!(rows.starts, [0, 4, 6, 8]);
+        assert_eq!(rows.row_of(0), 0);
+        assert_eq!(rows.row_of(5), 1);
+        assert_eq!(rows.row_of(10), 3);
+    }
+}